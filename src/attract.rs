@@ -0,0 +1,102 @@
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use rand::{Rng, thread_rng};
+
+use crate::{GameState, Progress};
+use crate::bot::Bot;
+use crate::graphics::transition::Transition;
+use crate::level_select::{CurrentLevel, GameMode, LEVELS};
+use crate::logic::sim::Heuristic;
+
+/// Lets the bot play a campaign level as a [GameMode::Demo] when the menu is left idle.
+pub struct AttractPlugin;
+
+impl Plugin for AttractPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Attract>()
+            .add_system(reset.in_schedule(OnEnter(GameState::Select)))
+            .add_system(wait.in_set(OnUpdate(GameState::Select)))
+            .add_system(interrupt.in_base_set(CoreSet::PreUpdate).after(InputSystem))
+            .add_system(restore.in_schedule(OnExit(GameState::Battle)))
+        ;
+    }
+}
+
+/// Seconds without input on the menu before the demo starts
+const IDLE_DELAY: f32 = 30.;
+
+#[derive(Resource, Default)]
+struct Attract {
+    /// Seconds since the last input
+    idle: f32,
+    /// Mode and level to restore after the demo
+    previous: Option<(GameMode, Option<u8>)>,
+}
+
+fn any_input(keys: &Input<KeyCode>, mouse: &Input<MouseButton>) -> bool {
+    keys.get_just_pressed().next().is_some() || mouse.get_just_pressed().next().is_some()
+}
+
+fn reset(mut attract: ResMut<Attract>) {
+    attract.idle = 0.;
+}
+
+fn wait(
+    mut commands: Commands,
+    mut attract: ResMut<Attract>,
+    mut cursor: EventReader<CursorMoved>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    time: Res<Time>,
+    transition: Option<Res<Transition>>,
+    mode: Res<GameMode>,
+    level: Option<Res<CurrentLevel>>,
+    progress: Res<Progress>,
+) {
+    let moved = cursor.iter().count() > 0;
+    if moved || any_input(&keys, &mouse) || transition.is_some() {
+        attract.idle = 0.;
+        return;
+    }
+    attract.idle += time.delta_seconds();
+    if attract.idle < IDLE_DELAY { return; }
+
+    attract.idle = 0.;
+    attract.previous = Some((*mode, level.map(|l| l.0)));
+    let demo = thread_rng().gen_range(1..=progress.level_unlocked.clamp(1, LEVELS));
+    commands.insert_resource(GameMode::Demo);
+    commands.insert_resource(CurrentLevel(demo));
+    commands.insert_resource(Bot::new(Heuristic));
+    commands.insert_resource(Transition::to(GameState::Battle));
+}
+
+/// Any input during the demo goes back to the menu, without reaching the battle systems.
+fn interrupt(
+    mut commands: Commands,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut mouse: ResMut<Input<MouseButton>>,
+    state: Res<State<GameState>>,
+    mode: Res<GameMode>,
+    transition: Option<Res<Transition>>,
+) {
+    if *mode != GameMode::Demo || state.0 != GameState::Battle { return; }
+    if !any_input(&keys, &mouse) { return; }
+    keys.reset_all();
+    mouse.reset_all();
+    if transition.is_none() { commands.insert_resource(Transition::to(GameState::Select)); }
+}
+
+fn restore(
+    mut commands: Commands,
+    mut attract: ResMut<Attract>,
+    mode: Res<GameMode>,
+) {
+    if *mode != GameMode::Demo { return; }
+    let (mode, level) = attract.previous.take().unwrap_or((GameMode::Campaign, None));
+    commands.insert_resource(mode);
+    match level {
+        Some(level) => commands.insert_resource(CurrentLevel(level)),
+        None => commands.remove_resource::<CurrentLevel>(),
+    }
+}
@@ -3,19 +3,27 @@ use std::time::Duration;
 use bevy::prelude::*;
 
 use crate::{collision, GameState};
-use crate::drones::{despawn_drone, drone_won, drones_dead, Enemy, kill_drone, update_drones};
-use crate::graphics::{MainBundle, package, sprite_from_tile};
+use crate::drones::{despawn_drone, drone_won, Drones, drones_dead, Enemy, kill_drone, update_drones};
+use crate::editor::CustomLevel;
+use crate::graphics::{health_bar, MainBundle, package, sprite_from_tile};
 use crate::graphics::animation::{Wiggle, wiggle};
-use crate::graphics::grid::{GridElement, update_z};
+use crate::graphics::grid::{CurrentPath, GridElement, update_z};
 use crate::graphics::loading::Textures;
-use crate::graphics::package::collect_package;
+use crate::graphics::package::{collect_package, CollectPackage, expire_packages, Inventory, PackageEffects, PackageOdds, update_decoys, use_package, UsePackage};
 use crate::graphics::palette::Palette;
 use crate::graphics::transition::Transition;
-use crate::level_select::CurrentLevel;
+use crate::level_select::{CurrentLevel, GameMode};
+use crate::logic::battle_log::{BattleLog, MoneyFlow};
+use crate::logic::difficulty::Difficulty;
+use crate::logic::economy::Economy;
+use crate::logic::event_log::GameEvent;
+use crate::logic::heatmap::Heatmap;
 use crate::logic::waves::{WaveIterator, WaveIteratorElement};
-use crate::music::{BGM, PlayBgmEvent};
+use crate::music::{BGM, PlayBgmEvent, PlaySfxEvent, SFX};
+use crate::sandbox::Sandbox;
+use crate::settings::Settings;
 use crate::shot::{bomb_exploded, bomb_exploding, make_bomb_explode, remove_shots};
-use crate::tower::{remove_slow_down, sell_tower, tower_fire, Towers, update_just_fired, upgrade_tower};
+use crate::tower::{remove_slow_down, sell_tower, Tower, tower_fire, Towers, update_coverage, update_just_fired, upgrade_tower};
 use crate::util::battle_z_from_y;
 
 pub struct BattlePlugin;
@@ -23,6 +31,11 @@ pub struct BattlePlugin;
 impl Plugin for BattlePlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_event::<Payout>()
+            .add_event::<CallWave>()
+            .add_event::<UsePackage>()
+            .add_event::<CollectPackage>()
+            .add_event::<GameEvent>()
             .add_system(
                 setup.in_schedule(OnEnter(GameState::Battle))
             )
@@ -30,14 +43,24 @@ impl Plugin for BattlePlugin {
                 cleanup.in_schedule(OnExit(GameState::Battle))
             )
             .add_systems(
-                (update_just_fired, remove_shots, tower_fire,
+                (update_just_fired, remove_shots, update_coverage, tower_fire,
                  sell_tower, upgrade_tower, drone_won, kill_drone,
                  update_drones.before(wiggle), despawn_drone, drones_dead.after(collision::collide).after(wiggle))
                     .in_set(OnUpdate(GameState::Battle))
             )
             .add_systems(
-                (bomb_exploding, make_bomb_explode, bomb_exploded, spawn_waves, skip_wave,
-                 remove_slow_down, collect_package, reset_state, update_z)
+                (bomb_exploding, make_bomb_explode, bomb_exploded, skip_wave,
+                 remove_slow_down, collect_package, reset_state, update_z, check_lives, update_battle_time, record_events, pay, call_wave)
+                    .in_set(OnUpdate(GameState::Battle))
+            )
+            .add_systems(
+                // The drones spawned this frame must exist before checking the wave is cleared
+                (spawn_waves, apply_system_buffers, wave_bonus)
+                    .chain()
+                    .in_set(OnUpdate(GameState::Battle))
+            )
+            .add_systems(
+                (update_decoys, use_package, expire_packages)
                     .in_set(OnUpdate(GameState::Battle))
             )
         ;
@@ -49,13 +72,47 @@ pub struct BattleUI;
 
 #[derive(Resource, Default)]
 pub struct DronesStats {
-    pub killed: u8,
-    pub survived: u8,
+    pub killed: u16,
+    pub survived: u16,
+    pub packages: u16,
+    /// Duration of the battle in seconds
+    pub time: f32,
 }
 
+/// Starting lives in endless mode
+pub const ENDLESS_LIVES: u16 = 20;
+
+/// Money of the sandbox, refilled all the time
+pub const SANDBOX_MONEY: u16 = 9999;
+
 #[derive(Resource)]
 pub struct Money(pub u16);
 
+/// Sent to add money: (amount, source, position of the "+€N" popup).
+/// The popup is shown next to the money counter when there is no position.
+pub struct Payout(pub u16, pub MoneyFlow, pub Option<Vec2>);
+
+/// Sent to start the next wave early.
+pub struct CallWave;
+
+/// The battle is lost when lives left reach zero.
+#[derive(Resource)]
+pub struct Lives {
+    /// Lives left
+    pub current: u16,
+    /// Starting lives, which a Repair package can't exceed
+    pub max: u16,
+}
+
+/// Progress of the current wave towards its bonus.
+#[derive(Resource, Default)]
+struct WaveBonus {
+    wave: usize,
+    /// Drones survived before the wave
+    survived: u16,
+    done: bool,
+}
+
 #[derive(Resource, PartialEq)]
 pub enum CursorState {
     /// Default state
@@ -66,6 +123,8 @@ pub enum CursorState {
     Sell,
     /// Upgrade a tower
     Upgrade,
+    /// Choose the target of the package in an [Inventory] slot
+    Target(usize),
 }
 
 impl CursorState {
@@ -75,6 +134,7 @@ impl CursorState {
             CursorState::Build(_) => Palette::C,
             CursorState::Sell => Palette::K,
             CursorState::Upgrade => Palette::G,
+            CursorState::Target(_) => Palette::M,
         }
     }
 }
@@ -89,20 +149,57 @@ fn setup(
     mut commands: Commands,
     mut bgm: EventWriter<PlayBgmEvent>,
     level: Res<CurrentLevel>,
+    mode: Res<GameMode>,
+    custom: Option<Res<CustomLevel>>,
+    difficulty: Res<Difficulty>,
 ) {
     commands.insert_resource(Pause(false));
     commands.insert_resource(X2(false));
+    let custom = match *mode {
+        // Campaign levels can be replaced by a level saved with the editor
+        GameMode::Custom | GameMode::Campaign => custom.map(|c| c.clone()),
+        _ => None,
+    };
 
-    bgm.send(PlayBgmEvent(match level.0 {
-        6 => BGM::ThemeMadness,
-        3 | 5 => BGM::Boss,
+    bgm.send(PlayBgmEvent(match (&custom, level.0) {
+        (Some(custom), _) => custom.level.bgm,
+        (_, 6) => BGM::ThemeMadness,
+        (_, 3 | 5) => BGM::Boss,
         _ => BGM::Theme,
     }));
 
     commands.insert_resource(CursorState::Select);
-    commands.insert_resource(Money(200));
+    commands.insert_resource(Money(match (*mode, &custom) {
+        (_, Some(custom)) => custom.level.money,
+        (GameMode::Sandbox, _) => SANDBOX_MONEY,
+        _ => (200. * difficulty.money_multiplier()) as u16,
+    }));
+    let lives = difficulty.scale_lives(match (*mode, &custom) {
+        (_, Some(custom)) => custom.level.lives,
+        (GameMode::Endless { .. } | GameMode::Sandbox, _) => ENDLESS_LIVES,
+        _ => level.get_lives(),
+    });
+    commands.insert_resource(Lives { current: lives, max: lives });
     commands.insert_resource(DronesStats::default());
-    commands.insert_resource(WaveIterator::from_level(level.0));
+    commands.insert_resource(WaveBonus::default());
+    // Level 0 is endless mode in level data, custom levels use the same rates
+    let level = match *mode {
+        GameMode::Endless { .. } | GameMode::Custom | GameMode::Sandbox => 0,
+        GameMode::Campaign | GameMode::Demo => level.0,
+    };
+    commands.insert_resource(Economy::new(level, *difficulty));
+    commands.insert_resource(PackageOdds::new(level, *difficulty));
+    commands.insert_resource(PackageEffects::default());
+    commands.insert_resource(Inventory::default());
+    commands.insert_resource(BattleLog::default());
+    commands.insert_resource(Heatmap::default());
+    commands.insert_resource(match (*mode, &custom) {
+        (GameMode::Endless { seed }, _) => WaveIterator::endless(seed),
+        (_, Some(custom)) => WaveIterator::starting_at(&custom.level.waves, custom.first_wave),
+        // Drones are spawned by hand in the sandbox
+        (GameMode::Sandbox, _) => WaveIterator::from_waves(&vec![]),
+        _ => WaveIterator::from_level(level),
+    });
 }
 
 fn reset_state(
@@ -140,50 +237,172 @@ fn cleanup(
 fn spawn_waves(
     mut wave_iterator: ResMut<WaveIterator>,
     mut commands: Commands,
+    mut payouts: EventWriter<Payout>,
+    mut events: EventWriter<GameEvent>,
     time: Res<Time>,
     textures: Res<Textures>,
+    settings: Res<Settings>,
+    difficulty: Res<Difficulty>,
+    odds: Res<PackageOdds>,
+    economy: Res<Economy>,
+    money: Res<Money>,
+    path: Res<CurrentPath>,
 ) {
     wave_iterator.next.tick(time.delta());
 
     if wave_iterator.next.just_finished() {
-        match wave_iterator.upcoming.pop() {
+        match wave_iterator.pop() {
             Some(WaveIteratorElement::NextWave(t)) => {
                 wave_iterator.next = Timer::from_seconds(t, TimerMode::Once);
+                events.send(GameEvent::WaveStarted { wave: wave_iterator.wave });
+                // Interest on the money banked during the previous wave
+                let interest = economy.interest(money.0);
+                if wave_iterator.wave > 1 && interest > 0 {
+                    payouts.send(Payout(interest, MoneyFlow::Interest, None));
+                }
             }
-            Some(WaveIteratorElement::NextDrone(drone, t)) => {
+            Some(WaveIteratorElement::NextDrone(drone, lane, t)) => {
                 wave_iterator.next = Timer::from_seconds(t, TimerMode::Once);
-                let atlas = &textures.tileset;
-                commands
-                    .spawn(drone.instantiate())
-                    .insert(MainBundle::from_xyz(0., 0., battle_z_from_y(0.)))
-                    .insert(Wiggle::with_frequency(Wiggle::slow()))
-                    .with_children(|builder| {
-                        sprite_from_tile(builder, drone.get_tiles(), atlas, 0.);
-                        package::spawn(builder, drone.get_model().package_offset(), atlas);
-                    })
-                    .insert(BattleUI)
-                    .insert(GridElement)
-                ;
+                let route = path.0.pick_route(lane);
+                let id = spawn_drone(&mut commands, drone, route, wave_iterator.hp_multiplier, *difficulty, &settings, &odds, &textures.tileset);
+                events.send(GameEvent::DroneSpawned { id, drone, route });
             }
             None => {}
         }
     }
 }
 
+/// Spawns a drone at the start of [route].
+pub fn spawn_drone(
+    commands: &mut Commands,
+    drone: Drones, route: usize, hp_multiplier: f32,
+    difficulty: Difficulty, settings: &Settings, odds: &PackageOdds,
+    atlas: &Handle<TextureAtlas>,
+) -> Entity {
+    commands
+        .spawn(drone.instantiate(route, difficulty, hp_multiplier))
+        .insert(MainBundle::from_xyz(0., 0., battle_z_from_y(0.)))
+        .insert(Wiggle::with_frequency(Wiggle::slow()))
+        .with_children(|builder| {
+            sprite_from_tile(builder, drone.get_tiles(), atlas, 0.);
+            package::spawn(builder, drone.get_model().package_offset(), odds, atlas);
+            if settings.health_bars { health_bar::spawn(builder, drone, atlas); }
+        })
+        .insert(BattleUI)
+        .insert(GridElement)
+        .id()
+}
+
 fn skip_wave(
     mut commands: Commands,
     transition: Option<Res<Transition>>,
+    sandbox: Option<Res<Sandbox>>,
     mut wave_iterator: ResMut<WaveIterator>,
+    settings: Res<Settings>,
+    mode: Res<GameMode>,
     enemies: Query<&Enemy>,
 ) {
-    if transition.is_some() { return; }
+    // The sandbox never ends
+    if transition.is_some() || sandbox.is_some() { return; }
     if enemies.is_empty() && wave_iterator.next.elapsed().gt(&Duration::from_secs(1)) {
-        if wave_iterator.next.remaining_secs() >= 2.1 {
+        if settings.auto_skip && wave_iterator.next.remaining_secs() >= 2.1 {
             let new_elapsed = wave_iterator.next.duration() - Duration::from_secs_f32(2.);
             wave_iterator.next.set_elapsed(new_elapsed);
         }
-        if wave_iterator.upcoming.is_empty() {
-            commands.insert_resource(Transition::to(GameState::GameOver));
+        if wave_iterator.is_over() {
+            commands.insert_resource(Transition::to(mode.after_battle()));
         }
     }
 }
+
+/// Starts the next wave on [CallWave] or N, paying a bonus for the time skipped.
+/// Waves can't be called while the game is paused.
+fn call_wave(
+    mut events: EventReader<CallWave>,
+    mut payouts: EventWriter<Payout>,
+    mut wave_iterator: ResMut<WaveIterator>,
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    economy: Res<Economy>,
+) {
+    let called = events.iter().count() > 0 || keys.just_pressed(KeyCode::N);
+    if !called || time.is_paused() { return; }
+    let Some(skipped) = wave_iterator.call_next_wave() else { return; };
+    let bonus = economy.early_call(skipped);
+    if bonus > 0 { payouts.send(Payout(bonus, MoneyFlow::EarlyCalls, None)); }
+}
+
+fn pay(
+    mut payouts: EventReader<Payout>,
+    mut events: EventWriter<GameEvent>,
+    mut money: ResMut<Money>,
+) {
+    for Payout(amount, source, _) in payouts.iter() {
+        money.0 = money.0.saturating_add(*amount);
+        events.send(GameEvent::Money { amount: *amount as i32, flow: *source });
+    }
+}
+
+/// Pays the wave bonus when the drones of a wave are all killed before the next one.
+fn wave_bonus(
+    mut payouts: EventWriter<Payout>,
+    mut bonus: ResMut<WaveBonus>,
+    wave_iterator: Res<WaveIterator>,
+    economy: Res<Economy>,
+    stats: Res<DronesStats>,
+    enemies: Query<&Enemy>,
+) {
+    if bonus.wave != wave_iterator.wave {
+        *bonus = WaveBonus { wave: wave_iterator.wave, survived: stats.survived, done: false };
+    }
+    if bonus.done || !wave_iterator.wave_spawned() || !enemies.is_empty() { return; }
+    bonus.done = true;
+    if stats.survived == bonus.survived {
+        payouts.send(Payout(economy.wave_bonus, MoneyFlow::WaveBonus, None));
+    }
+}
+
+/// Feeds the [BattleLog] and the [Heatmap] with the [GameEvent]s.
+fn record_events(
+    mut events: EventReader<GameEvent>,
+    mut log: ResMut<BattleLog>,
+    mut heatmap: ResMut<Heatmap>,
+    towers: Query<&Tower>,
+) {
+    for event in events.iter() {
+        log.record(event);
+        match *event {
+            GameEvent::Hit { damage, tower: Some(tower), .. } => {
+                if let Ok(tower) = towers.get(tower) { heatmap.damage((tower.x, tower.y), damage); }
+            }
+            GameEvent::Killed { route, advance, .. } => heatmap.death(route, advance),
+            GameEvent::Escaped { .. } => heatmap.escape(),
+            _ => {}
+        }
+    }
+}
+
+/// Counts the time played, not the time paused.
+fn update_battle_time(
+    mut stats: ResMut<DronesStats>,
+    time: Res<Time>,
+) {
+    if time.is_paused() { return; }
+    stats.time += time.delta_seconds();
+}
+
+fn check_lives(
+    mut commands: Commands,
+    mut sfx: EventWriter<PlaySfxEvent>,
+    transition: Option<Res<Transition>>,
+    sandbox: Option<Res<Sandbox>>,
+    mode: Res<GameMode>,
+    lives: Res<Lives>,
+) {
+    // The sandbox never ends, its lives are refilled
+    if transition.is_some() || sandbox.is_some() { return; }
+    if lives.current == 0 {
+        sfx.send(PlaySfxEvent(SFX::GameOver));
+        commands.insert_resource(Transition::defeat(mode.after_battle()));
+    }
+}
@@ -0,0 +1,119 @@
+use bevy::prelude::*;
+use strum::IntoEnumIterator;
+
+use crate::GameState;
+use crate::battle::Money;
+use crate::graphics::grid::{CurrentPath, Grid, RoadElement};
+use crate::graphics::gui::HoverPopup;
+use crate::graphics::loading::Textures;
+use crate::graphics::package::{CollectPackage, Dropped, Package};
+use crate::level_select::{CurrentLevel, GameMode};
+use crate::logic::battle_log::MoneyFlow;
+use crate::logic::event_log::GameEvent;
+use crate::logic::gen::{COLUMNS, ROWS};
+use crate::logic::sim::{Action, BattleView, Strategy, THINK_DELAY};
+use crate::logic::waves::WaveIterator;
+use crate::music::{PlaySfxEvent, SFX};
+use crate::tower;
+use crate::tower::{Tower, Towers};
+
+/// Lets a [Bot] play the battle when the resource is present.
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system(play.in_set(OnUpdate(GameState::Battle)))
+            .add_system(clean.in_schedule(OnExit(GameState::Battle)))
+        ;
+    }
+}
+
+/// Plays the current battle with a [Strategy], through the same actions as the player.
+#[derive(Resource)]
+pub struct Bot {
+    strategy: Box<dyn Strategy>,
+    think: Timer,
+}
+
+impl Bot {
+    pub fn new(strategy: impl Strategy + 'static) -> Self {
+        Bot { strategy: Box::new(strategy), think: Timer::from_seconds(THINK_DELAY, TimerMode::Repeating) }
+    }
+
+    pub fn name(&self) -> &'static str { self.strategy.name() }
+}
+
+fn play(
+    mut commands: Commands,
+    mut sfx: EventWriter<PlaySfxEvent>,
+    mut events: EventWriter<GameEvent>,
+    mut collect: EventWriter<CollectPackage>,
+    mut towers: Query<(Entity, &mut Tower, &mut HoverPopup)>,
+    packages: Query<(Entity, &Package), With<Dropped>>,
+    bot: Option<ResMut<Bot>>,
+    grid: Option<ResMut<Grid>>,
+    path: Option<Res<CurrentPath>>,
+    wave_iterator: Res<WaveIterator>,
+    mode: Res<GameMode>,
+    level: Res<CurrentLevel>,
+    time: Res<Time>,
+    textures: Res<Textures>,
+    mut money: ResMut<Money>,
+) {
+    let Some(mut bot) = bot else { return; };
+    let (Some(mut grid), Some(path)) = (grid, path) else { return; };
+    if !bot.think.tick(time.delta()).just_finished() { return; }
+
+    let packages: Vec<(Entity, &Package)> = packages.iter().collect();
+    let mut free: Vec<(usize, usize)> = vec![];
+    for y in 0..ROWS as usize {
+        for x in 0..COLUMNS as usize {
+            if grid.elements[y][x] == RoadElement::Rock && !grid.towers.contains(&(x, y)) { free.push((x, y)); }
+        }
+    }
+    let view = BattleView {
+        network: &path.0,
+        wave: wave_iterator.wave,
+        money: money.0,
+        unlocked: Towers::iter().filter(|t| mode.tower_unlocked(*t, level.0)).collect(),
+        free,
+        towers: towers.iter().map(|(_, t, _)| t.clone()).collect(),
+        packages: packages.iter().map(|(_, p)| p.kind()).collect(),
+    };
+
+    for action in bot.strategy.act(&view) {
+        match action {
+            Action::Build(model, x, y) => {
+                if !view.unlocked.contains(&model) || !view.free.contains(&(x, y)) || grid.towers.contains(&(x, y)) || money.0 < model.get_cost() { continue; }
+                sfx.send(PlaySfxEvent(SFX::PlaceTower));
+                money.0 -= model.get_cost();
+                grid.towers.insert((x, y));
+                let e_tower = tower::place_tower(x, y, &mut commands, model, &textures.tileset, &time);
+                events.send(GameEvent::TowerPlaced { tower: e_tower, model, x, y });
+                events.send(GameEvent::Money { amount: -(model.get_cost() as i32), flow: MoneyFlow::Building });
+            }
+            Action::Upgrade(x, y) => {
+                let Some((e_tower, mut t, mut hp)) = towers.iter_mut().find(|(_, t, _)| t.x == x && t.y == y) else { continue; };
+                let Some(cost) = t.upgrade_cost() else { continue; };
+                if cost > money.0 { continue; }
+                sfx.send(PlaySfxEvent(SFX::UpgradeTower));
+                money.0 -= cost;
+                t.rank += 1;
+                events.send(GameEvent::TowerUpgraded { tower: e_tower, rank: t.rank, cost });
+                events.send(GameEvent::Money { amount: -(cost as i32), flow: MoneyFlow::Upgrades });
+                hp.description = t.get_description();
+                hp.attr1 = t.get_attr1();
+                hp.attr2 = t.get_attr2();
+                hp.force_redraw = true;
+            }
+            Action::Collect(i) => {
+                if let Some((e_package, _)) = packages.get(i) { collect.send(CollectPackage(*e_package)); }
+            }
+        }
+    }
+}
+
+fn clean(mut commands: Commands) {
+    commands.remove_resource::<Bot>();
+}
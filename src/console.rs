@@ -0,0 +1,269 @@
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy_pkv::PkvStore;
+
+use crate::{Progress, util};
+use crate::battle::{Lives, Money, spawn_drone};
+use crate::bot::Bot;
+use crate::graphics::grid::CurrentPath;
+use crate::graphics::gui::HoverPopup;
+use crate::graphics::loading::{Fonts, Textures};
+use crate::graphics::package::PackageOdds;
+use crate::graphics::palette::Palette;
+use crate::graphics::text::{TextStyles, ttf};
+use crate::level_select::{CurrentLevel, GameMode, LEVELS, start_campaign};
+use crate::logic::battle_log::MoneyFlow;
+use crate::logic::console::{Command, complete, HELP, History};
+use crate::logic::difficulty::Difficulty;
+use crate::logic::event_log::GameEvent;
+use crate::logic::optimizer::{Plan, PLAN_FILE, Scripted};
+use crate::logic::sim::Heuristic;
+use crate::logic::waves::WaveIterator;
+use crate::settings::Settings;
+use crate::tower::Tower;
+use crate::util::size::f32_tile_to_f32;
+
+/// Developer console, toggled with the ` key. Only built with the `dev` feature.
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Console>()
+            .add_system(update_input.in_base_set(CoreSet::PreUpdate).after(InputSystem))
+            .add_systems((run_commands, god_mode, draw))
+        ;
+    }
+}
+
+/// Number of output lines shown above the input
+const OUTPUT_LINES: usize = 4;
+
+#[derive(Resource, Default)]
+struct Console {
+    open: bool,
+    input: String,
+    output: Vec<String>,
+    history: History,
+    /// Lines submitted this frame
+    submitted: Vec<String>,
+    /// Lives never go down
+    god: bool,
+}
+
+impl Console {
+    fn print(&mut self, line: String) {
+        self.output.push(line);
+        if self.output.len() > OUTPUT_LINES { self.output.remove(0); }
+    }
+}
+
+#[derive(Component)]
+struct ConsoleLine(usize);
+
+/// Reads the keyboard while the console is open, the game doesn't see the keys.
+fn update_input(
+    mut console: ResMut<Console>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut chars: EventReader<ReceivedCharacter>,
+) {
+    if keys.just_pressed(KeyCode::Grave) {
+        console.open = !console.open;
+        keys.reset_all();
+        chars.clear();
+        return;
+    }
+    if !console.open {
+        chars.clear();
+        return;
+    }
+
+    for c in chars.iter() {
+        if !c.char.is_control() && c.char != '`' { console.input.push(c.char); }
+    }
+    if keys.just_pressed(KeyCode::Back) { console.input.pop(); }
+    if keys.just_pressed(KeyCode::Tab) {
+        if let Some(completed) = complete(&console.input) { console.input = completed; }
+    }
+    if keys.just_pressed(KeyCode::Up) {
+        if let Some(line) = console.history.older().map(|l| l.to_string()) { console.input = line; }
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        if let Some(line) = console.history.newer().map(|l| l.to_string()) { console.input = line; }
+    }
+    if keys.just_pressed(KeyCode::Return) && !console.input.trim().is_empty() {
+        let line = std::mem::take(&mut console.input);
+        console.history.push(&line);
+        console.submitted.push(line);
+    }
+    keys.reset_all();
+}
+
+fn run_commands(
+    mut commands: Commands,
+    mut console: ResMut<Console>,
+    mut progress: ResMut<Progress>,
+    mut pkv: ResMut<PkvStore>,
+    mut time: ResMut<Time>,
+    mut towers: Query<(Entity, &mut Tower, &mut HoverPopup)>,
+    mut money: Option<ResMut<Money>>,
+    mut wave_iterator: Option<ResMut<WaveIterator>>,
+    mut events: EventWriter<GameEvent>,
+    mode: Option<Res<GameMode>>,
+    level: Option<Res<CurrentLevel>>,
+    path: Option<Res<CurrentPath>>,
+    odds: Option<Res<PackageOdds>>,
+    textures: Option<Res<Textures>>,
+    settings: Res<Settings>,
+    difficulty: Res<Difficulty>,
+) {
+    if console.submitted.is_empty() { return; }
+    let in_battle = "Only available during a battle.".to_string();
+
+    for line in std::mem::take(&mut console.submitted) {
+        console.print(format!("> {}", line));
+        let result = match Command::parse(&line) {
+            Err(e) => e,
+            Ok(Command::Money(amount)) => match money.as_mut() {
+                Some(money) => {
+                    let change = amount as i32 - money.0 as i32;
+                    if change != 0 { events.send(GameEvent::Money { amount: change, flow: MoneyFlow::Console }); }
+                    money.0 = amount;
+                    format!("Money set to {}.", amount)
+                }
+                None => in_battle.clone(),
+            },
+            Ok(Command::Spawn(drone, count)) => match (&path, &odds, &textures) {
+                (Some(path), Some(odds), Some(textures)) => {
+                    let hp_multiplier = wave_iterator.as_ref().map_or(1., |w| w.hp_multiplier);
+                    for _ in 0..count {
+                        let route = path.0.pick_route(None);
+                        let id = spawn_drone(&mut commands, drone, route, hp_multiplier, *difficulty, &settings, odds, &textures.tileset);
+                        events.send(GameEvent::DroneSpawned { id, drone, route });
+                    }
+                    format!("Spawned {} {}.", count, drone.get_name())
+                }
+                _ => in_battle.clone(),
+            },
+            Ok(Command::SkipWave) => match wave_iterator.as_mut() {
+                Some(waves) => {
+                    waves.skip_wave();
+                    format!("Skipped to wave {}.", waves.wave + 1)
+                }
+                None => in_battle.clone(),
+            },
+            Ok(Command::Level(0)) => "Levels start at 1.".to_string(),
+            Ok(Command::Level(n)) => {
+                start_campaign(&mut commands, n);
+                format!("Starting level {}.", n)
+            }
+            Ok(Command::UnlockAll) => {
+                progress.level_unlocked = progress.level_unlocked.max(LEVELS);
+                let _ = pkv.set("level", &progress.level_unlocked);
+                format!("Levels 1 to {} unlocked.", progress.level_unlocked)
+            }
+            Ok(Command::God) => {
+                console.god = !console.god;
+                format!("God mode {}.", if console.god { "on" } else { "off" })
+            }
+            Ok(Command::UpgradeAll) => {
+                let mut upgraded = 0;
+                for (e_tower, mut tower, mut popup) in towers.iter_mut() {
+                    if tower.upgrade_cost().is_none() { continue; }
+                    // Free upgrades, no money is spent
+                    while tower.upgrade_cost().is_some() {
+                        tower.rank += 1;
+                        events.send(GameEvent::TowerUpgraded { tower: e_tower, rank: tower.rank, cost: 0 });
+                    }
+                    popup.description = tower.get_description();
+                    popup.attr1 = tower.get_attr1();
+                    popup.attr2 = tower.get_attr2();
+                    popup.force_redraw = true;
+                    upgraded += 1;
+                }
+                let total = towers.iter().count();
+                format!("Upgraded {} of {} towers.", upgraded, total)
+            }
+            Ok(Command::TimeScale(scale)) => {
+                time.set_relative_speed(scale);
+                format!("Time runs {}x.", scale)
+            }
+            Ok(Command::Seed) => match (mode.as_deref(), level.as_deref()) {
+                (Some(GameMode::Endless { seed }), _) => format!("Endless seed {}.", seed),
+                (Some(_), Some(level)) => format!("Level {} has no seed.", level.0),
+                _ => "No game started.".to_string(),
+            },
+            Ok(Command::Bot) => match wave_iterator.as_ref() {
+                Some(_) => {
+                    commands.add(|world: &mut World| {
+                        if world.remove_resource::<Bot>().is_none() { world.insert_resource(Bot::new(Heuristic)); }
+                    });
+                    "Bot toggled.".to_string()
+                }
+                None => in_battle.clone(),
+            },
+            Ok(Command::BotScript) => match wave_iterator.as_ref() {
+                Some(_) => match util::data_path(PLAN_FILE).and_then(|path| std::fs::read_to_string(path).ok()) {
+                    Some(script) => match Plan::parse(&script) {
+                        Ok(plan) => {
+                            commands.insert_resource(Bot::new(Scripted::new(plan)));
+                            "Bot playing the saved plan.".to_string()
+                        }
+                        Err(e) => e,
+                    },
+                    None => format!("No {} in the data folder, run --optimize first.", PLAN_FILE),
+                },
+                None => in_battle.clone(),
+            },
+            Ok(Command::Help) => HELP.to_string(),
+        };
+        if !result.is_empty() { console.print(result); }
+    }
+}
+
+fn god_mode(
+    console: Res<Console>,
+    lives: Option<ResMut<Lives>>,
+) {
+    if !console.god { return; }
+    let Some(mut lives) = lives else { return; };
+    if lives.current != lives.max { lives.current = lives.max; }
+}
+
+/// Spawns the console lines when it opens and keeps them up to date.
+fn draw(
+    mut commands: Commands,
+    console: Res<Console>,
+    mut lines: Query<(Entity, &ConsoleLine, &mut Text)>,
+    fonts: Option<Res<Fonts>>,
+) {
+    if !console.open {
+        for (id, _, _) in lines.iter() { commands.entity(id).despawn(); }
+        return;
+    }
+    let Some(fonts) = fonts else { return; };
+
+    let value = |i: usize| match i {
+        0 => format!("> {}_", console.input),
+        i => console.output.iter().rev().nth(i - 1).cloned().unwrap_or_default(),
+    };
+
+    if lines.is_empty() {
+        for i in 0..=OUTPUT_LINES {
+            commands
+                .spawn(ttf(
+                    f32_tile_to_f32(1.), f32_tile_to_f32(util::size::HEIGHT as f32 - 2. - 1.25 * (OUTPUT_LINES - i) as f32),
+                    util::z_pos::POPUP_FG,
+                    &value(i),
+                    TextStyles::Body, &fonts, Palette::B,
+                ))
+                .insert(ConsoleLine(i));
+        }
+        return;
+    }
+
+    for (_, line, mut text) in lines.iter_mut() {
+        let value = value(line.0);
+        if text.sections[0].value != value { text.sections[0].value = value; }
+    }
+}
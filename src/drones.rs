@@ -1,22 +1,30 @@
 use std::time::Duration;
 
 use bevy::ecs::system::EntityCommands;
-use bevy::math::{vec3, Vec3Swizzles};
+use bevy::math::{vec2, vec3, Vec3Swizzles};
 use bevy::prelude::*;
 use bevy_text_mode::TextModeTextureAtlasSprite;
 use bevy_tweening::{Animator, Delay, EaseFunction, Tween, TweenCompleted};
 use bevy_tweening::lens::TransformPositionLens;
+use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
-use crate::battle::{BattleUI, DronesStats};
+use crate::battle::{BattleUI, DronesStats, Lives, Payout};
 use crate::collision::{body_size, BodyType, Contact, HitBox};
 use crate::graphics::{sprite_f32, tween};
 use crate::graphics::animation::Wiggle;
 use crate::graphics::grid::{CurrentPath, GridElement};
 use crate::graphics::loading::Textures;
-use crate::graphics::package::{ClickablePackage, Package};
+use crate::graphics::package::{ClickablePackage, Dropped, Package, PackageEffects};
 use crate::graphics::sprites::{DroneModels, TILE};
+use crate::logic::battle_log::MoneyFlow;
+use crate::logic::difficulty::Difficulty;
+use crate::logic::economy::Economy;
+use crate::logic::event_log::GameEvent;
+use crate::logic::rules;
+use crate::logic::waves::WaveIterator;
 use crate::music::{PlaySfxEvent, SFX};
+use crate::sandbox::Sandbox;
 use crate::shot::{Bomb, Shot, Shots, spawn_bomb};
 use crate::tower::Slow;
 use crate::util;
@@ -26,10 +34,17 @@ use crate::util::size::{f32_tile_to_f32, tile_to_f32};
 #[derive(Debug, Clone)]
 pub struct Stats {
     pub(crate) hp: f32,
-    speed: f32,
+    pub(crate) max_hp: f32,
+    pub(crate) speed: f32,
 }
 
-#[derive(Debug, Clone, Copy, EnumIter)]
+impl Stats {
+    const fn new(hp: f32, speed: f32) -> Self {
+        Stats { hp, max_hp: hp, speed }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, Serialize, Deserialize)]
 pub enum Drones {
     Simple1,
     Simple2,
@@ -43,32 +58,59 @@ pub enum Drones {
     Invader, // was here
 }
 
+
 #[derive(Component)]
 pub struct Enemy {
     pub class: Drones,
     pub stats: Stats,
     pub advance: f32,
+    /// Index of the route in [CurrentPath]
+    pub route: usize,
+    /// Cached segment of the route, see [crate::logic::path::Path::pos_cached]
+    pub segment: usize,
+}
+
+impl Enemy {
+    /// Removes [amount] hp, unless drones are invincible. Returns the damage dealt.
+    pub fn damage(&mut self, amount: f32, invincible: bool) -> f32 {
+        rules::damage(&mut self.stats.hp, amount, invincible)
+    }
 }
 
 impl Drones {
     pub const fn get_default_stats(&self) -> Stats {
         match self {
             Drones::Simple1 | Drones::Simple2 | Drones::Simple3 =>
-                Stats { hp: 25., speed: 0.5 },
+                Stats::new(25., 0.5),
             Drones::Medium1 | Drones::Medium2 | Drones::Medium3 | Drones::Medium4 =>
-                Stats { hp: 80., speed: 0.35 },
+                Stats::new(80., 0.35),
             Drones::Big1 | Drones::Big2 =>
-                Stats { hp: 300., speed: 0.25 },
+                Stats::new(300., 0.25),
             Drones::Invader =>
-                Stats { hp: 1000., speed: 0.125 },
+                Stats::new(1000., 0.125),
         }
     }
 
-    pub fn instantiate(&self) -> (Enemy, HitBox) {
+    /// Default stats scaled by the difficulty.
+    pub fn get_stats(&self, difficulty: Difficulty) -> Stats {
+        let stats = self.get_default_stats();
+        Stats {
+            hp: stats.hp * difficulty.hp_multiplier(),
+            max_hp: stats.max_hp * difficulty.hp_multiplier(),
+            speed: stats.speed * difficulty.speed_multiplier(),
+        }
+    }
+
+    pub fn instantiate(&self, route: usize, difficulty: Difficulty, hp_multiplier: f32) -> (Enemy, HitBox) {
+        let mut stats = self.get_stats(difficulty);
+        stats.hp *= hp_multiplier;
+        stats.max_hp *= hp_multiplier;
         (Enemy {
             class: *self,
-            stats: self.get_default_stats().clone(),
+            stats,
             advance: 0.,
+            route,
+            segment: 0,
         }, {
              let hitbox: Vec2 = self.get_model().get_hitbox();
              HitBox {
@@ -97,14 +139,52 @@ impl Drones {
     }
 
     pub fn get_tiles(&self) -> &'static [TILE] { self.get_model().get_tiles() }
+
+    /// Name of the drone class
+    pub const fn get_name(&self) -> &'static str {
+        match self {
+            Drones::Simple1 | Drones::Simple2 | Drones::Simple3 => "Simple",
+            Drones::Medium1 | Drones::Medium2 | Drones::Medium3 | Drones::Medium4 => "Medium",
+            Drones::Big1 | Drones::Big2 => "Big",
+            Drones::Invader => "Invader",
+        }
+    }
+
+    /// Bosses are announced in the wave preview.
+    pub const fn is_boss(&self) -> bool {
+        matches!(self, Drones::Big1 | Drones::Big2 | Drones::Invader)
+    }
+
+    /// Short description of the drone behavior
+    pub fn get_traits(&self) -> String {
+        let behavior = match self.get_default_stats().speed {
+            _ if self.is_boss() => "Boss",
+            s if s >= 0.5 => "Fast",
+            _ => "Steady",
+        };
+        let lives = self.lives_cost();
+        format!("{}, -{} {}", behavior, lives, if lives == 1 { "life" } else { "lives" })
+    }
+
+    /// Lives lost when the drone reaches the end of the path
+    pub const fn lives_cost(&self) -> u16 {
+        match self {
+            Drones::Simple1 | Drones::Simple2 | Drones::Simple3 => 1,
+            Drones::Medium1 | Drones::Medium2 | Drones::Medium3 | Drones::Medium4 => 2,
+            // The Invader of level 6 must not end the battle on its own with the starting lives
+            Drones::Big1 | Drones::Big2 | Drones::Invader => 5,
+        }
+    }
 }
 
 pub fn update_drones(
     mut drones: Query<(&mut Transform, &mut Enemy, Option<&Slow>)>,
     path: Option<Res<CurrentPath>>,
+    effects: Res<PackageEffects>,
     time: Res<Time>,
 ) {
     let Some(path) = path else { return; };
+    if effects.frozen(&time) { return; }
     for (mut pos, mut drone, slowed) in drones.iter_mut() {
         let speed_modulator = match slowed {
             Some(slow) => slow.factor,
@@ -113,7 +193,9 @@ pub fn update_drones(
 
         drone.advance += speed_modulator * drone.stats.speed * time.delta_seconds();
 
-        let Some(progress) = path.0.pos(drone.advance) else { continue; };
+        let advance = drone.advance;
+        let route = path.0.route(drone.route);
+        let Some(progress) = route.pos_cached(advance, &mut drone.segment) else { continue; };
 
         let size = body_size(drone.class.get_model().get_tiles());
         pos.translation.x = f32_tile_to_f32(progress.x * 2.)
@@ -126,10 +208,13 @@ pub fn update_drones(
 pub fn drones_dead(
     mut commands: Commands,
     mut sfx: EventWriter<PlaySfxEvent>,
+    mut events: EventWriter<GameEvent>,
     mut event_reader: EventReader<Contact>,
     mut enemies: Query<&mut Enemy>,
     shots: Query<(&Shot, &Transform)>,
+    sandbox: Option<Res<Sandbox>>,
 ) {
+    let invincible = sandbox.map_or(false, |s| s.invincible);
     for event in event_reader.iter() {
         match event {
             Contact((BodyType::Enemy, e_enemy), (BodyType::ShipShot, e_shot)) |
@@ -149,7 +234,10 @@ pub fn drones_dead(
                 let Ok(mut enemy) = enemies.get_mut(*e_enemy) else { continue };
                 match shot.class {
                     Shots::Bomb => spawn_bomb(Bomb::from_shot_translation(shot, t_shot.translation), &mut commands),
-                    Shots::Electricity => enemy.stats.hp = (enemy.stats.hp - shot.damage).max(0.),
+                    Shots::Electricity => {
+                        let dealt = enemy.damage(shot.damage, invincible);
+                        events.send(GameEvent::Hit { id: *e_enemy, drone: enemy.class, damage: dealt, hit: shot.damage, tower: Some(shot.source) });
+                    }
                 }
             }
             _ => {}
@@ -163,7 +251,12 @@ pub fn kill_drone(
     package: Query<&Package>,
     path: Res<CurrentPath>,
     textures: Res<Textures>,
+    wave_iterator: Res<WaveIterator>,
+    economy: Res<Economy>,
+    time: Res<Time>,
     mut stats: ResMut<DronesStats>,
+    mut payouts: EventWriter<Payout>,
+    mut events: EventWriter<GameEvent>,
     mut commands: Commands,
 ) {
     for (e_enemy, enemy, t_enemy) in enemies.iter().filter(|(_, e, _)| e.stats.hp <= 0.) {
@@ -198,8 +291,9 @@ pub fn kill_drone(
                         // Despawn drone package
                         commands.entity(e_enemy).remove_children(&[child_id]);
                         commands.entity(child_id).despawn();
+                        events.send(GameEvent::PackageDropped { kind: package.kind() });
 
-                        drop_package(&path, &textures, &mut commands, enemy, t_enemy.translation.xy(), package);
+                        drop_package(&path, &textures, &time, &mut commands, enemy, t_enemy.translation.xy(), package);
                     }
                     Err(_) => {
                         // Regular tile -> animate alpha
@@ -213,12 +307,18 @@ pub fn kill_drone(
             });
 
         stats.killed += 1;
+        events.send(GameEvent::Killed { id: e_enemy, drone: enemy.class, wave: wave_iterator.wave, route: enemy.route, advance: enemy.advance });
+
+        let size = body_size(enemy.class.get_tiles());
+        let pos = t_enemy.translation.xy() + vec2(size.x / 2., size.y);
+        payouts.send(Payout(economy.bounty(enemy.class), MoneyFlow::Kills, Some(pos)));
     }
 }
 
 fn drop_package(
     path: &Res<CurrentPath>,
     textures: &Res<Textures>,
+    time: &Res<Time>,
     commands: &mut Commands,
     enemy: &Enemy,
     starting_pos: Vec2,
@@ -226,7 +326,7 @@ fn drop_package(
 {
     // Respawn the package and make it fall on the road
     let (_, _, i, bg, fg, f, r) = package.tile();
-    let progress = path.0.pos(enemy.advance).unwrap();
+    let progress = path.0.route(enemy.route).pos(enemy.advance).unwrap();
     let offset = enemy.class.get_model().package_offset();
     let start = vec2_with_battle_z(starting_pos + offset);
     let end = vec3_with_battle_z(
@@ -242,6 +342,7 @@ fn drop_package(
         ))
         .insert(package.clone())
         .insert(ClickablePackage)
+        .insert(Dropped(time.elapsed()))
         .insert(GridElement)
         .insert(BattleUI)
         .insert(Animator::new(Tween::new(
@@ -266,12 +367,16 @@ pub fn despawn_drone(
 pub fn drone_won(
     drones: Query<(Entity, &Enemy), Changed<Transform>>,
     mut stats: ResMut<DronesStats>,
+    mut lives: ResMut<Lives>,
+    mut events: EventWriter<GameEvent>,
     path: Res<CurrentPath>,
     mut commands: Commands,
 ) {
     for (e_drone, drone) in drones.iter() {
-        if path.0.drone_won(drone.advance) {
+        if path.0.route(drone.route).drone_won(drone.advance) {
             stats.survived += 1;
+            lives.current = lives.current.saturating_sub(drone.class.lives_cost());
+            events.send(GameEvent::Escaped { id: e_drone, drone: drone.class });
             commands.get_entity(e_drone).map(EntityCommands::despawn_recursive);
         }
     }
@@ -0,0 +1,331 @@
+use bevy::math::vec2;
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::{GameState, util};
+use crate::graphics::{grid, sprite_f32};
+use crate::graphics::grid::GridUI;
+use crate::graphics::loading::{Fonts, Textures};
+use crate::graphics::palette::Palette;
+use crate::graphics::text::{TextStyles, ttf_anchor};
+use crate::graphics::transition::Transition;
+use crate::level_select::{CurrentLevel, GameMode, LEVELS};
+use crate::logic::level_data::{level_file, LevelData};
+use crate::logic::path::Network;
+use crate::music::{BGM, PlayBgmEvent};
+use crate::util::is_in;
+use crate::util::size::{f32_tile_to_f32, GUI_HEIGHT, tile_to_f32};
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Editor>()
+            .add_system(setup.in_schedule(OnEnter(GameState::Editor)))
+            .add_systems((update_buttons, update_road, draw_preview).in_set(OnUpdate(GameState::Editor)))
+            .add_system(clean.in_schedule(OnExit(GameState::Editor)))
+        ;
+    }
+}
+
+/// Level being edited, kept while it is tested.
+#[derive(Resource, Default)]
+pub struct Editor {
+    pub level: LevelData,
+    /// File the level is saved to, see [level_file]
+    slot: u8,
+    /// Result of the last action
+    status: String,
+}
+
+impl Editor {
+    /// Shows the validation result of the level.
+    fn check(&mut self) {
+        self.status = match self.level.validate() {
+            Ok(_) => "The level is ready to play.".to_string(),
+            Err(e) => e.get_message(),
+        };
+    }
+}
+
+/// Level played in [GameMode::Custom]
+#[derive(Resource, Clone)]
+pub struct CustomLevel {
+    pub level: LevelData,
+    /// Index of the wave the battle starts from
+    pub first_wave: usize,
+}
+
+/// Music a level can use
+const LEVEL_BGMS: [BGM; 3] = [BGM::Theme, BGM::Boss, BGM::ThemeMadness];
+
+#[derive(Component)]
+struct EditorUI;
+
+/// Spawn and exit markers, redrawn with the road
+#[derive(Component)]
+struct RoadMarker;
+
+#[derive(Component)]
+struct StatusText;
+
+#[derive(Component, Copy, Clone, PartialEq, EnumIter)]
+enum EditorButton {
+    Money,
+    Lives,
+    Music,
+    Slot,
+    Clear,
+    Load,
+    Save,
+    Play,
+    Waves,
+    Back,
+}
+
+impl EditorButton {
+    fn get_text(&self, editor: &Editor) -> String {
+        let level = &editor.level;
+        match self {
+            EditorButton::Money => format!("Money: €{}", level.money),
+            EditorButton::Lives => format!("Lives: {}", level.lives),
+            EditorButton::Music => format!("Music: {}", level.bgm.get_name()),
+            EditorButton::Slot if editor.slot == 0 => "Slot: custom".to_string(),
+            EditorButton::Slot => format!("Slot: level {}", editor.slot),
+            EditorButton::Clear => "Clear".to_string(),
+            EditorButton::Load => "Load".to_string(),
+            EditorButton::Save => "Save".to_string(),
+            EditorButton::Play => "Play".to_string(),
+            EditorButton::Waves => "Waves".to_string(),
+            EditorButton::Back => "Back".to_string(),
+        }
+    }
+
+    /// Position in tiles, buttons have Anchor::CenterLeft
+    fn get_pos(&self) -> Vec2 {
+        match self {
+            EditorButton::Money => vec2(2., 3.25),
+            EditorButton::Lives => vec2(12., 3.25),
+            EditorButton::Music => vec2(20., 3.25),
+            EditorButton::Slot => vec2(29., 3.25),
+            EditorButton::Clear => vec2(2., 1.25),
+            EditorButton::Load => vec2(9., 1.25),
+            EditorButton::Save => vec2(15., 1.25),
+            EditorButton::Play => vec2(21., 1.25),
+            EditorButton::Waves => vec2(27., 1.25),
+            EditorButton::Back => vec2(util::size::WIDTH as f32 - 6., 1.25),
+        }
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut bgm: EventWriter<PlayBgmEvent>,
+    mut editor: ResMut<Editor>,
+    fonts: Res<Fonts>,
+) {
+    bgm.send(PlayBgmEvent(editor.level.bgm));
+    editor.check();
+
+    for button in EditorButton::iter() {
+        let pos = button.get_pos();
+        commands
+            .spawn(ttf_anchor(
+                f32_tile_to_f32(pos.x), f32_tile_to_f32(pos.y),
+                util::z_pos::GUI_FG,
+                &button.get_text(&editor),
+                TextStyles::Heading, &fonts, Palette::D,
+                Anchor::CenterLeft,
+            ))
+            .insert(button)
+            .insert(EditorUI);
+    }
+
+    commands
+        .spawn(ttf_anchor(
+            f32_tile_to_f32(2.), f32_tile_to_f32(5.),
+            util::z_pos::GUI_FG,
+            &editor.status,
+            TextStyles::Body, &fonts, Palette::B,
+            Anchor::CenterLeft,
+        ))
+        .insert(StatusText)
+        .insert(EditorUI);
+}
+
+fn update_buttons(
+    mut commands: Commands,
+    mut bgm: EventWriter<PlayBgmEvent>,
+    mut editor: ResMut<Editor>,
+    mut buttons: Query<(&EditorButton, &Transform, &mut Text), Without<StatusText>>,
+    mut status: Query<&mut Text, With<StatusText>>,
+    windows: Query<&Window>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    transition: Option<Res<Transition>>,
+) {
+    if transition.is_some() { return; }
+
+    if keys.just_pressed(KeyCode::Escape) {
+        commands.insert_resource(Transition::to(GameState::Select));
+        return;
+    }
+
+    let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
+    let left = mouse.just_pressed(MouseButton::Left);
+    let right = mouse.just_pressed(MouseButton::Right);
+
+    for (button, pos, mut text) in buttons.iter_mut() {
+        let value = button.get_text(&editor);
+        let size = vec2(f32_tile_to_f32(0.7 * value.chars().count() as f32), f32_tile_to_f32(1.25));
+        let bottom_left = vec2(pos.translation.x, pos.translation.y - size.y / 2.);
+        let hovered = is_in(cursor_pos, bottom_left, size);
+        text.sections[0].style.color = if hovered { Palette::B.into() } else { Palette::D.into() };
+        if text.sections[0].value != value { text.sections[0].value = value; }
+        if !hovered || !(left || right) { continue; }
+
+        let editor = &mut *editor;
+        let level = &mut editor.level;
+        match button {
+            EditorButton::Money => {
+                level.money = if left { level.money.saturating_add(10) } else { level.money.saturating_sub(10) };
+            }
+            EditorButton::Lives => {
+                level.lives = if left { level.lives.saturating_add(1) } else { level.lives.saturating_sub(1).max(1) };
+            }
+            EditorButton::Music => {
+                let i = LEVEL_BGMS.iter().position(|b| *b == level.bgm).unwrap_or(0);
+                let n = LEVEL_BGMS.len();
+                level.bgm = LEVEL_BGMS[if left { (i + 1) % n } else { (i + n - 1) % n }];
+                bgm.send(PlayBgmEvent(level.bgm));
+            }
+            EditorButton::Slot => {
+                editor.slot = if left { (editor.slot + 1) % (LEVELS + 1) } else { (editor.slot + LEVELS) % (LEVELS + 1) };
+            }
+            EditorButton::Clear if left => {
+                level.path.clear();
+                editor.check();
+            }
+            EditorButton::Load if left => {
+                match LevelData::load(editor.slot) {
+                    Ok(level) => {
+                        editor.level = level;
+                        editor.check();
+                        bgm.send(PlayBgmEvent(editor.level.bgm));
+                    }
+                    Err(e) => editor.status = format!("Could not load {}: {}", level_file(editor.slot), e),
+                }
+            }
+            EditorButton::Save if left => {
+                if let Err(e) = level.validate() {
+                    editor.status = e.get_message();
+                    continue;
+                }
+                let file = level_file(editor.slot);
+                editor.status = match level.save(editor.slot) {
+                    Ok(_) if editor.slot == 0 => format!("Saved to {}.", file),
+                    Ok(_) => format!("Saved to {}, it replaces level {}.", file, editor.slot),
+                    Err(e) => format!("Could not save {}: {}", file, e),
+                };
+            }
+            EditorButton::Play if left => {
+                if let Err(e) = level.validate() {
+                    editor.status = e.get_message();
+                    continue;
+                }
+                commands.insert_resource(CustomLevel { level: level.clone(), first_wave: 0 });
+                // The level number is not used in custom levels
+                commands.insert_resource(CurrentLevel(0));
+                commands.insert_resource(GameMode::Custom);
+                commands.insert_resource(Transition::to(GameState::Battle));
+                return;
+            }
+            EditorButton::Waves if left => {
+                commands.insert_resource(Transition::to(GameState::WaveEditor));
+                return;
+            }
+            EditorButton::Back if left => {
+                commands.insert_resource(Transition::to(GameState::Select));
+                return;
+            }
+            _ => {}
+        }
+    }
+
+    let mut status = status.single_mut();
+    if status.sections[0].value != editor.status { status.sections[0].value = editor.status.clone(); }
+}
+
+/// Left click on a cell adds a corner to the road, right click removes the last one.
+fn update_road(
+    mut editor: ResMut<Editor>,
+    windows: Query<&Window>,
+    mouse: Res<Input<MouseButton>>,
+    transition: Option<Res<Transition>>,
+) {
+    if transition.is_some() { return; }
+    let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
+    if cursor_pos.y < tile_to_f32(GUI_HEIGHT) { return; }
+    let cell = (
+        (cursor_pos.x / tile_to_f32(2)) as isize,
+        ((cursor_pos.y - tile_to_f32(GUI_HEIGHT)) / tile_to_f32(2)) as isize,
+    );
+
+    if mouse.just_pressed(MouseButton::Left) {
+        match editor.level.add_point(cell) {
+            Ok(_) => editor.check(),
+            Err(e) => editor.status = e.get_message(),
+        }
+    } else if mouse.just_pressed(MouseButton::Right) {
+        editor.level.path.pop();
+        editor.check();
+    }
+}
+
+/// Redraws the road when it changes.
+fn draw_preview(
+    mut commands: Commands,
+    mut drawn: Local<Vec<(isize, isize)>>,
+    editor: Res<Editor>,
+    textures: Res<Textures>,
+    road: Query<Entity, Or<(With<GridUI>, With<RoadMarker>)>>,
+) {
+    if !road.is_empty() && *drawn == editor.level.path { return; }
+    for id in &road { commands.entity(id).despawn_recursive(); }
+    *drawn = editor.level.path.clone();
+
+    let network = match editor.level.path.len() {
+        0 | 1 => Network { routes: vec![] },
+        _ => editor.level.network(),
+    };
+    grid::draw_road(&mut commands, &textures, &network);
+
+    // Spawn and exit markers
+    let path = &editor.level.path;
+    for (cell, color) in [(path.first(), Palette::G), (path.last().filter(|_| path.len() > 1), Palette::K)] {
+        let Some((x, y)) = cell else { continue; };
+        commands
+            .spawn(sprite_f32(
+                422,
+                tile_to_f32(2 * *x as usize) + f32_tile_to_f32(0.5),
+                tile_to_f32(2 * *y as usize + GUI_HEIGHT) + f32_tile_to_f32(0.5),
+                util::z_pos::CURSOR,
+                Palette::Transparent, color,
+                false, 0, textures.tileset.clone(),
+            ))
+            .insert(RoadMarker)
+            .insert(EditorUI);
+    }
+}
+
+fn clean(
+    mut commands: Commands,
+    q1: Query<Entity, With<EditorUI>>,
+    q2: Query<Entity, With<GridUI>>,
+) {
+    for id in &q1 { commands.entity(id).despawn_recursive(); }
+    for id in &q2 { commands.entity(id).despawn_recursive(); }
+}
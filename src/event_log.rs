@@ -0,0 +1,67 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+use bevy::prelude::*;
+
+use crate::GameState;
+use crate::level_select::GameMode;
+use crate::logic::event_log::{EVENT_LOG_FILE, GameEvent, to_json_line};
+use crate::settings::Settings;
+use crate::util::data_path;
+
+/// Writes the [GameEvent]s of each battle to a JSON lines file when enabled in the settings.
+pub struct EventLogPlugin;
+
+impl Plugin for EventLogPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system(open.in_schedule(OnEnter(GameState::Battle)))
+            .add_system(write.in_set(OnUpdate(GameState::Battle)))
+            .add_system(close.in_schedule(OnExit(GameState::Battle)))
+        ;
+    }
+}
+
+#[derive(Resource)]
+struct EventLog {
+    /// Random id of the battle
+    run: u64,
+    tick: u64,
+    file: BufWriter<File>,
+}
+
+fn open(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mode: Res<GameMode>,
+) {
+    if !settings.event_log || *mode == GameMode::Demo { return; }
+    let Some(path) = data_path(EVENT_LOG_FILE) else { return; };
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => commands.insert_resource(EventLog { run: rand::random(), tick: 0, file: BufWriter::new(file) }),
+        Err(e) => warn!("Cannot open the event log {}: {}", path.display(), e),
+    }
+}
+
+fn write(
+    log: Option<ResMut<EventLog>>,
+    mut events: EventReader<GameEvent>,
+) {
+    let Some(mut log) = log else {
+        events.clear();
+        return;
+    };
+    log.tick += 1;
+    let (run, tick) = (log.run, log.tick);
+    for event in events.iter() {
+        let _ = writeln!(log.file, "{}", to_json_line(run, tick, event));
+    }
+}
+
+fn close(
+    mut commands: Commands,
+    log: Option<ResMut<EventLog>>,
+) {
+    if let Some(mut log) = log { let _ = log.file.flush(); }
+    commands.remove_resource::<EventLog>();
+}
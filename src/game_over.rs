@@ -4,27 +4,86 @@ use bevy::sprite::Anchor;
 use bevy_pkv::PkvStore;
 
 use crate::{GameState, Progress};
-use crate::battle::DronesStats;
-use crate::graphics::loading::Fonts;
+use crate::battle::{DronesStats, Lives, Money};
+use crate::drones::Drones;
+use crate::graphics::sprite_f32;
+use crate::graphics::grid::{CurrentPath, Grid, RoadElement};
+use crate::graphics::loading::{Fonts, Textures};
+use crate::graphics::package::PackageKind;
 use crate::graphics::palette::Palette;
 use crate::graphics::text;
 use crate::graphics::transition::Transition;
-use crate::level_select::CurrentLevel;
+use crate::level_select::{CurrentLevel, GameMode};
+use crate::logic::battle_log;
+use crate::logic::battle_log::{BattleLog, MoneyFlow};
+use crate::logic::difficulty::Difficulty;
+use crate::logic::heatmap::{ADVANCE_STEP, HEATMAP_FILE, Heatmap};
+use crate::logic::score::{BattleResult, Record, stars};
+use crate::logic::waves::WaveIterator;
 use crate::music::{BGM, PlayBgmEvent};
-use crate::util::size::{tile_to_f32, WIDTH};
+use crate::util::data_path;
+use crate::util::size::{f32_tile_to_f32, GUI_HEIGHT, tile_to_f32, WIDTH};
 use crate::util::z_pos;
 
 pub struct GameOverPlugin;
 
+/// Position and height of the kills per wave chart, in tiles
+const CHART_Y: f32 = 13.;
+const CHART_HEIGHT: f32 = 6.;
+
 #[derive(Component)]
 pub struct GameOverUI;
 
+/// The game over screen has a summary page, a details page and a heatmap page.
+#[derive(Component, Copy, Clone, PartialEq)]
+enum Page {
+    Summary,
+    Details,
+    Heatmap,
+}
+
+impl Page {
+    fn hint(&self) -> &'static str {
+        match self {
+            Page::Summary => "Press Tab for details",
+            Page::Details => "Press Tab for the heatmap",
+            Page::Heatmap => "Tab: summary - A: this battle / all battles - E: export to CSV",
+        }
+    }
+
+    fn next(&self) -> Page {
+        match self {
+            Page::Summary => Page::Details,
+            Page::Details => Page::Heatmap,
+            Page::Heatmap => Page::Summary,
+        }
+    }
+}
+
+/// Page shown, and whether the heatmap is aggregated over all battles
+#[derive(Resource)]
+struct ShownPage(Page, bool);
+
+/// Heatmap of every battle on the same road, this one included
+#[derive(Resource)]
+struct TotalHeatmap(Heatmap);
+
+/// Layer of the heatmap page, for this battle or all battles
+#[derive(Component)]
+struct HeatLayer(bool);
+
+#[derive(Component)]
+struct HeatmapStatus;
+
 impl Plugin for GameOverPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_system(
                 setup.in_schedule(OnEnter(GameState::GameOver))
             )
+            .add_system(
+                setup_heatmap.in_schedule(OnEnter(GameState::GameOver))
+            )
             .add_system(
                 cleanup.in_schedule(OnExit(GameState::GameOver))
             )
@@ -41,17 +100,42 @@ fn setup(
     mut progress: ResMut<Progress>,
     mut pkv: ResMut<PkvStore>,
     stats: ResMut<DronesStats>,
+    lives: Res<Lives>,
+    money: Res<Money>,
     current_level: Res<CurrentLevel>,
+    mode: Res<GameMode>,
+    difficulty: Res<Difficulty>,
+    waves: Res<WaveIterator>,
+    log: Res<BattleLog>,
     fonts: Res<Fonts>,
+    textures: Res<Textures>,
 ) {
     bgm.send(PlayBgmEvent(BGM::Pause));
+    spawn_details(&mut commands, &fonts, &textures, &log);
 
+    if let GameMode::Endless { seed } = *mode {
+        let reached = waves.wave as u32;
+        let key = GameMode::best_wave_key(seed, *difficulty);
+        let best = pkv.get::<u32>(&key).unwrap_or(0);
+        let mut texts: Vec<(String, usize)> = vec![
+            (format!("You've reached wave {}!", reached), 16),
+            (format!("{} drones taken down on seed {} ({}).", stats.killed, seed, difficulty.get_name()), 11),
+        ];
+        if reached > best {
+            let _ = pkv.set(&key, &reached);
+            texts.push(("That's a new record!".to_string(), 8));
+        } else {
+            texts.push((format!("Your best is wave {}.", best), 8));
+        }
+        spawn_texts(&mut commands, &fonts, texts);
+        return;
+    }
+
+    let mut won = lives.current > 0;
     let mut texts: Vec<(String, usize)> = vec![
-        ("You've seen all drones!".into(), 16),
+        (if won { "You've seen all drones!" } else { "You ran out of lives!" }.into(), 16),
     ];
 
-    let mut won = true;
-
     if stats.survived == 0 {
         texts.push((format!("You've taken down all {} of them!", stats.killed), 11));
     } else if stats.killed == 0 {
@@ -61,12 +145,18 @@ fn setup(
     } else {
         texts.push((format!("You've taken down {} of them,", stats.killed), 11));
         texts.push((format!("but {} of them survived.", stats.survived), 8));
-        if stats.survived > 5 {
-            won = false;
+        if !won {
             texts.push((format!("Try harder next time..."), 5));
         }
     }
 
+    // Custom levels don't count towards the campaign progress
+    if *mode == GameMode::Custom {
+        if won { texts.push(("Nice job! Time to make it harder?".to_string(), 5)); }
+        spawn_texts(&mut commands, &fonts, texts);
+        return;
+    }
+
     if won {
         if current_level.0 == 6 {
             texts.push(("You are the new boss of Sabotage, Inc.!".to_string(), 5));
@@ -76,17 +166,199 @@ fn setup(
         } else {
             texts.push(("Nice job! Can you survive the next level?".to_string(), 5));
         }
-        if progress.level_unlocked <= current_level.0 {
-            progress.level_unlocked += 1;
-            let _ = pkv.set("level", &progress.level_unlocked);
-        }
+        progress.clear(current_level.0, *difficulty, &mut pkv);
     }
 
+    // Score
+    let score = BattleResult {
+        killed: stats.killed,
+        survived: stats.survived,
+        money: money.0,
+        packages: stats.packages,
+        time: stats.time,
+    }.score();
+    let stars = stars(current_level.0, won, score);
+    let record = Record { level: current_level.0, difficulty: *difficulty, score, stars };
+    let new_best = won && progress.save_record(record, &mut pkv);
+    // After the record, whose stars may unlock the next level
+    if won { progress.unlock(&mut pkv); }
+    texts.push((match (stars, new_best) {
+        (0, _) => format!("Score: {}", score),
+        (_, false) => format!("Score: {} - {}/3 stars", score, stars),
+        (_, true) => format!("New best score: {} - {}/3 stars", score, stars),
+    }, 19));
+
+    spawn_texts(&mut commands, &fonts, texts);
+}
+
+/// Spawns centered lines of text at the given heights (in tiles).
+fn spawn_texts(commands: &mut Commands, fonts: &Res<Fonts>, texts: Vec<(String, usize)>) {
     for (t, y) in texts {
         commands
-            .spawn(text::ttf_anchor(tile_to_f32(WIDTH / 2), tile_to_f32(y), z_pos::TITLE_TEXT, &t, text::TextStyles::Heading, &fonts, Palette::A, Anchor::BottomCenter))
+            .spawn(text::ttf_anchor(tile_to_f32(WIDTH / 2), tile_to_f32(y), z_pos::TITLE_TEXT, &t, text::TextStyles::Heading, fonts, Palette::A, Anchor::BottomCenter))
+            .insert(Page::Summary)
             .insert(GameOverUI);
     }
+    spawn_hint(commands, fonts, Page::Summary);
+}
+
+fn spawn_hint(commands: &mut Commands, fonts: &Res<Fonts>, page: Page) {
+    let mut hint = text::ttf_anchor(
+        tile_to_f32(WIDTH / 2), f32_tile_to_f32(0.5), z_pos::TITLE_TEXT,
+        page.hint(), text::TextStyles::Body, fonts, Palette::D, Anchor::BottomCenter,
+    );
+    if page != Page::Summary { hint.visibility = Visibility::Hidden; }
+    commands.spawn(hint).insert(page).insert(GameOverUI);
+}
+
+/// Lists [entries] as "name xN", or "none".
+fn list<T>(entries: &Vec<(T, u16)>, name: impl Fn(&T) -> &'static str) -> String {
+    let mut counts: Vec<(&str, u16)> = vec![];
+    for (key, n) in entries.iter() {
+        match counts.iter_mut().find(|(k, _)| *k == name(key)) {
+            Some((_, total)) => *total += n,
+            None => counts.push((name(key), *n)),
+        }
+    }
+    if counts.is_empty() { return "none".to_string(); }
+    counts.iter().map(|(k, n)| format!("{} x{}", k, n)).collect::<Vec<_>>().join(", ")
+}
+
+/// Spawns the hidden details page: kills per wave chart and battle statistics.
+fn spawn_details(commands: &mut Commands, fonts: &Res<Fonts>, textures: &Res<Textures>, log: &BattleLog) {
+    let mut spawn_text = |x: f32, y: f32, text: &str, style: text::TextStyles, anchor: Anchor| {
+        let mut bundle = text::ttf_anchor(x, f32_tile_to_f32(y), z_pos::TITLE_TEXT, text, style, fonts, Palette::A, anchor);
+        bundle.visibility = Visibility::Hidden;
+        commands.spawn(bundle).insert(Page::Details).insert(GameOverUI);
+    };
+
+    spawn_text(tile_to_f32(WIDTH / 2), 21., "Battle details", text::TextStyles::Heading, Anchor::BottomCenter);
+    spawn_text(tile_to_f32(2), 19.5, "Kills per wave", text::TextStyles::Body, Anchor::BottomLeft);
+
+    let money = |entries: &Vec<(MoneyFlow, u32)>, word: &str| match entries.is_empty() {
+        true => "none".to_string(),
+        false => entries.iter().map(|(f, n)| format!("€{} {} {}", n, word, f.get_name())).collect::<Vec<_>>().join(", "),
+    };
+    let damage = log.damage_per_model();
+    let lines = [
+        format!("Escaped: {}", list(&log.escaped, |d: &Drones| d.get_name())),
+        format!("Earned: {}", money(&log.earned, "from")),
+        format!("Spent: {}", money(&log.spent, "on")),
+        format!("Damage: {}", match damage.is_empty() {
+            true => "none".to_string(),
+            false => damage.iter().map(|(t, d)| format!("{} {}", t.get_name(), *d as u32)).collect::<Vec<_>>().join(", "),
+        }),
+        format!("Most valuable: {}", match log.most_valuable_tower() {
+            Some(t) => format!("{} ({:.1} damage per €)", t.model.get_name(), t.damage / t.invested as f32),
+            None => "none".to_string(),
+        }),
+        format!("Packages: {}", list(&log.packages, |p: &PackageKind| p.get_name())),
+    ];
+    for (i, line) in lines.iter().enumerate() {
+        spawn_text(tile_to_f32(2), 11. - 1.5 * i as f32, line, text::TextStyles::Body, Anchor::BottomLeft);
+    }
+
+    // Kills per wave chart
+    let waves = log.kills_per_wave.len();
+    let max = log.kills_per_wave.iter().copied().max().unwrap_or(0);
+    if max > 0 {
+        let width = (tile_to_f32(WIDTH - 4) / waves as f32).min(tile_to_f32(1));
+        for (i, kills) in log.kills_per_wave.iter().enumerate() {
+            if *kills == 0 { continue; }
+            let mut bar = sprite_f32(
+                421, tile_to_f32(2) + i as f32 * width, f32_tile_to_f32(CHART_Y), z_pos::TITLE_TEXT,
+                Palette::Transparent, if i % 2 == 0 { Palette::G } else { Palette::H },
+                false, 0, textures.tileset.clone(),
+            );
+            bar.transform.scale.x = width / tile_to_f32(1);
+            bar.transform.scale.y = CHART_HEIGHT * *kills as f32 / max as f32;
+            bar.visibility = Visibility::Hidden;
+            commands.spawn(bar).insert(Page::Details).insert(GameOverUI);
+        }
+    }
+
+    spawn_hint(commands, fonts, Page::Details);
+}
+
+/// Saves the heatmap of the battle with the previous ones, and spawns the hidden heatmap page.
+fn setup_heatmap(
+    mut commands: Commands,
+    mut pkv: ResMut<PkvStore>,
+    heatmap: Res<Heatmap>,
+    current_level: Res<CurrentLevel>,
+    mode: Res<GameMode>,
+    path: Res<CurrentPath>,
+    grid: Option<Res<Grid>>,
+    fonts: Res<Fonts>,
+) {
+    let run = Heatmap { runs: 1, ..heatmap.clone() };
+    let key = mode.heatmap_key(current_level.0);
+    let mut total = key.as_ref().and_then(|k| pkv.get::<Heatmap>(k).ok()).unwrap_or_default();
+    total.merge(&run);
+    if let Some(key) = key { let _ = pkv.set(&key, &total); }
+
+    let mut spawn_text = |y: f32, text: &str, layer: Option<bool>| {
+        let mut bundle = text::ttf_anchor(tile_to_f32(WIDTH / 2), f32_tile_to_f32(y), z_pos::TITLE_TEXT, text, text::TextStyles::Body, &fonts, Palette::A, Anchor::BottomCenter);
+        bundle.visibility = Visibility::Hidden;
+        let mut entity = commands.spawn(bundle);
+        entity.insert(Page::Heatmap).insert(GameOverUI);
+        if let Some(aggregated) = layer { entity.insert(HeatLayer(aggregated)); }
+        entity.id()
+    };
+    spawn_text(4., "Deaths along the road (red) and damage dealt from each cell (blue)", None);
+    spawn_text(2.75, &format!("This battle: {} escaped", run.escapes), Some(false));
+    spawn_text(2.75, &format!("All {} battles: {} escaped", total.runs, total.escapes), Some(true));
+    let status = spawn_text(1.5, "", None);
+    commands.entity(status).insert(HeatmapStatus);
+    spawn_hint(&mut commands, &fonts, Page::Heatmap);
+
+    for (map, aggregated) in [(&run, false), (&total, true)] {
+        spawn_heat_cells(&mut commands, map, &path, grid.as_deref(), aggregated);
+    }
+
+    commands.insert_resource(ShownPage(Page::Summary, false));
+    commands.insert_resource(TotalHeatmap(total));
+}
+
+/// Spawns a colored square per cell of the grid.
+fn spawn_heat_cells(commands: &mut Commands, map: &Heatmap, path: &CurrentPath, grid: Option<&Grid>, aggregated: bool) {
+    let mut deaths: Vec<((usize, usize), u32)> = vec![];
+    for ((route, step), n) in map.deaths.iter() {
+        if *route >= path.0.routes.len() { continue; }
+        let Some(p) = path.0.route(*route).pos((*step as f32 + 0.5) * ADVANCE_STEP) else { continue; };
+        if p.x < 0. || p.y < 0. { continue; }
+        battle_log::add(&mut deaths, (p.x.round() as usize, p.y.round() as usize), *n);
+    }
+    let max_deaths = deaths.iter().map(|(_, n)| *n).max().unwrap_or(0).max(1) as f32;
+    let max_damage = map.max_damage().max(1.);
+
+    let cell = tile_to_f32(2);
+    let mut spawn_cell = |(x, y): (usize, usize), color: Color| {
+        let mut bundle = SpriteBundle {
+            sprite: Sprite { color, custom_size: Some(Vec2::splat(cell - 2.)), anchor: Anchor::BottomLeft, ..default() },
+            transform: Transform::from_xyz(x as f32 * cell + 1., y as f32 * cell + tile_to_f32(GUI_HEIGHT) + 1., z_pos::TITLE_TEXT),
+            ..default()
+        };
+        bundle.visibility = Visibility::Hidden;
+        commands.spawn(bundle).insert(Page::Heatmap).insert(HeatLayer(aggregated)).insert(GameOverUI);
+    };
+
+    // The road is drawn faintly under the deaths
+    if let Some(grid) = grid {
+        for (y, row) in grid.elements.iter().enumerate() {
+            for (x, element) in row.iter().enumerate() {
+                if *element == RoadElement::Road && !deaths.iter().any(|(c, _)| *c == (x, y)) {
+                    spawn_cell((x, y), Palette::D.transparent(0.3));
+                }
+            }
+        }
+    }
+    for (cell, n) in deaths.iter() {
+        spawn_cell(*cell, Palette::K.transparent(0.2 + 0.8 * *n as f32 / max_deaths));
+    }
+    for (cell, amount) in map.damage.iter() {
+        spawn_cell(*cell, Palette::C.transparent(0.2 + 0.8 * amount / max_damage));
+    }
 }
 
 fn cleanup(
@@ -105,13 +377,48 @@ fn exit_game_over(
     keys: Res<Input<KeyCode>>,
     mouse: Res<Input<MouseButton>>,
     transition: Option<Res<Transition>>,
+    mode: Res<GameMode>,
+    mut shown: ResMut<ShownPage>,
+    total: Res<TotalHeatmap>,
+    mut pages: Query<(&Page, Option<&HeatLayer>, &mut Visibility)>,
+    mut status: Query<&mut Text, With<HeatmapStatus>>,
 ) {
     if transition.is_some() { return; }
+    // Custom levels go back to the editor
+    let next = match *mode {
+        GameMode::Custom => GameState::Editor,
+        _ => GameState::Select,
+    };
+    let heatmap = shown.0 == Page::Heatmap;
+    if keys.just_pressed(KeyCode::Tab) || (heatmap && keys.just_pressed(KeyCode::A)) {
+        // Flip pages, or heatmap layers
+        if keys.just_pressed(KeyCode::Tab) { shown.0 = shown.0.next(); } else { shown.1 = !shown.1; }
+        for (page, layer, mut visibility) in pages.iter_mut() {
+            let visible = *page == shown.0 && layer.map_or(true, |l| l.0 == shown.1);
+            *visibility = if visible { Visibility::Inherited } else { Visibility::Hidden };
+        }
+        return;
+    }
+    if heatmap && keys.just_pressed(KeyCode::E) {
+        let message = match data_path(HEATMAP_FILE) {
+            Some(path) => match std::fs::write(&path, total.0.to_csv()) {
+                Ok(_) => {
+                    // The full path is too long for the screen
+                    info!("Heatmap exported to {}", path.display());
+                    format!("Heatmap of all battles exported to the data folder as {}.", HEATMAP_FILE)
+                }
+                Err(e) => format!("Could not write {}: {}", path.display(), e),
+            },
+            None => "No data directory to export the heatmap to.".to_string(),
+        };
+        if let Ok(mut text) = status.get_single_mut() { text.sections[0].value = message; }
+        return;
+    }
     for _ in keys.get_just_pressed() {
-        commands.insert_resource(Transition::to(GameState::Select));
+        commands.insert_resource(Transition::to(next));
         return;
     }
     if mouse.just_pressed(MouseButton::Left) {
-        commands.insert_resource(Transition::to(GameState::Select));
+        commands.insert_resource(Transition::to(next));
     }
 }
@@ -0,0 +1,195 @@
+use bevy::math::vec2;
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+
+use crate::{GameState, util};
+use crate::collision::{BodyType, HitBox};
+use crate::graphics::circle;
+use crate::graphics::circle::Circles;
+use crate::graphics::grid::{CurrentPath, Grid, RoadElement};
+use crate::graphics::loading::Fonts;
+use crate::graphics::palette::Palette;
+use crate::graphics::text::{TextStyles, ttf_anchor};
+use crate::tower::Tower;
+use crate::util::size::{f32_tile_to_f32, GUI_HEIGHT, tile_to_f32};
+use crate::util::z_pos::DEBUG;
+
+/// Overlay of hitboxes, tower ranges, routes and grid cells, toggled with F3.
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<DebugOverlay>()
+            .add_system(toggle)
+            .add_systems((draw_grid.after(toggle), draw_bodies.after(toggle)).in_set(OnUpdate(GameState::Battle)))
+            .add_system(clean.in_schedule(OnExit(GameState::Battle)))
+        ;
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct DebugOverlay {
+    pub enabled: bool,
+    /// The grid and routes must be redrawn
+    dirty: bool,
+}
+
+/// Cells and routes, redrawn when the grid changes
+#[derive(Component)]
+struct DebugGrid;
+
+/// Hitboxes and ranges, redrawn every frame
+#[derive(Component)]
+struct DebugBody;
+
+/// Distance between two dots of a route, in cells
+const DOT_STEP: f32 = 0.25;
+/// Distance between two distance markers, in cells
+const MARKER_STEP: f32 = 5.;
+
+fn toggle(
+    mut commands: Commands,
+    mut overlay: ResMut<DebugOverlay>,
+    keys: Res<Input<KeyCode>>,
+    drawn: Query<Entity, Or<(With<DebugGrid>, With<DebugBody>)>>,
+) {
+    if !keys.just_pressed(KeyCode::F3) { return; }
+    overlay.enabled = !overlay.enabled;
+    overlay.dirty = true;
+    for id in &drawn { commands.entity(id).despawn_recursive(); }
+}
+
+fn rect(x: f32, y: f32, z: f32, size: Vec2, color: Color) -> SpriteBundle {
+    SpriteBundle {
+        sprite: Sprite { color, custom_size: Some(size), anchor: Anchor::BottomLeft, ..default() },
+        transform: Transform::from_xyz(x, y, z),
+        ..default()
+    }
+}
+
+/// Center of the cell (x, y) of a route, in pixels.
+fn cell_center(p: Vec2) -> Vec2 {
+    vec2(f32_tile_to_f32(p.x * 2. + 1.), f32_tile_to_f32(p.y * 2. + 1. + GUI_HEIGHT as f32))
+}
+
+fn cell_color(element: &RoadElement, tower: bool) -> Color {
+    match (element, tower) {
+        (_, true) => Palette::M.transparent(0.35),
+        (RoadElement::Plain, _) => Palette::D.transparent(0.15),
+        (RoadElement::Road, _) => Palette::J.transparent(0.2),
+        (RoadElement::Rock, _) => Palette::L.transparent(0.3),
+    }
+}
+
+/// Draws the classification of the cells and the routes with distance markers.
+fn draw_grid(
+    mut commands: Commands,
+    mut overlay: ResMut<DebugOverlay>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut dot: Local<Option<Handle<ColorMaterial>>>,
+    grid: Option<Res<Grid>>,
+    path: Option<Res<CurrentPath>>,
+    circles: Res<Circles>,
+    fonts: Option<Res<Fonts>>,
+    drawn: Query<Entity, With<DebugGrid>>,
+) {
+    let Some(grid) = grid else { return; };
+    let Some(fonts) = fonts else { return; };
+    if !overlay.enabled { return; }
+    if !overlay.dirty && !grid.is_changed() && !path.as_ref().map_or(false, |p| p.is_changed()) { return; }
+    overlay.dirty = false;
+    for id in &drawn { commands.entity(id).despawn_recursive(); }
+
+    let cell = tile_to_f32(2);
+    for (y, row) in grid.elements.iter().enumerate().take(util::size::GRID_HEIGHT / 2) {
+        for (x, element) in row.iter().enumerate().take(util::size::WIDTH / 2) {
+            commands
+                .spawn(rect(
+                    x as f32 * cell + 1., y as f32 * cell + tile_to_f32(GUI_HEIGHT) + 1., DEBUG,
+                    Vec2::splat(cell - 2.),
+                    cell_color(element, grid.towers.contains(&(x, y))),
+                ))
+                .insert(DebugGrid);
+        }
+    }
+
+    let Some(path) = path else { return; };
+    let dot = dot.get_or_insert_with(|| {
+        let color: Color = Palette::H.into();
+        materials.add(color.into())
+    });
+    for (i, route) in path.0.routes.iter().enumerate() {
+        let mut advance = 0.;
+        while let Some(p) = route.path.pos(advance) {
+            let center = cell_center(p);
+            commands
+                .spawn(circle::mesh(&circles, dot, 0.75, center.x, center.y, DEBUG + 0.1))
+                .insert(DebugGrid);
+
+            let markers = advance / MARKER_STEP;
+            if advance > 0. && (markers - markers.round()).abs() < 1e-3 {
+                commands
+                    .spawn(ttf_anchor(
+                        center.x, center.y + f32_tile_to_f32(0.5) + 2. * i as f32,
+                        DEBUG + 0.2,
+                        &format!("{}", advance.round()),
+                        TextStyles::Body, &fonts, Palette::A,
+                        Anchor::BottomCenter,
+                    ))
+                    .insert(DebugGrid);
+            }
+
+            if route.path.drone_won(advance) { break; }
+            advance += DOT_STEP;
+        }
+    }
+}
+
+/// Draws the hitboxes, colored by [BodyType], and the range of the towers.
+fn draw_bodies(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut range: Local<Option<Handle<ColorMaterial>>>,
+    overlay: Res<DebugOverlay>,
+    bodies: Query<(&HitBox, &Transform)>,
+    towers: Query<&Tower>,
+    circles: Res<Circles>,
+    drawn: Query<Entity, With<DebugBody>>,
+) {
+    if !overlay.enabled { return; }
+    for id in &drawn { commands.entity(id).despawn_recursive(); }
+
+    for (hitbox, pos) in bodies.iter() {
+        let color = match hitbox.body_type {
+            BodyType::Enemy => Palette::K,
+            BodyType::ShipShot => Palette::I,
+        };
+        let origin = pos.translation.truncate();
+        let corner = origin + hitbox.offset;
+        commands
+            .spawn(rect(corner.x, corner.y, DEBUG + 0.3, vec2(hitbox.width, hitbox.height), color.transparent(0.4)))
+            .insert(DebugBody);
+        // Origin of the entity, the box is drawn from it plus the offset
+        commands
+            .spawn(rect(origin.x - 0.5, origin.y - 0.5, DEBUG + 0.4, Vec2::ONE, color.into()))
+            .insert(DebugBody);
+    }
+
+    let range = range.get_or_insert_with(|| materials.add(Palette::C.transparent(0.15).into()));
+    for tower in towers.iter() {
+        let center = util::tower_center(tower.x, tower.y);
+        commands
+            .spawn(circle::mesh(&circles, range, tower.range(), center.x, center.y, DEBUG + 0.05))
+            .insert(DebugBody);
+    }
+}
+
+fn clean(
+    mut commands: Commands,
+    mut overlay: ResMut<DebugOverlay>,
+    drawn: Query<Entity, Or<(With<DebugGrid>, With<DebugBody>)>>,
+) {
+    overlay.dirty = true;
+    for id in &drawn { commands.entity(id).despawn_recursive(); }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bevy::ecs::system::EntityCommands;
+use bevy::math::{vec2, vec3, Vec3Swizzles};
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use bevy_tweening::{Animator, EaseFunction, Tween, TweenCompleted};
+use bevy_tweening::lens::TransformPositionLens;
+
+use crate::battle::{BattleUI, Payout};
+use crate::collision::body_size;
+use crate::drones::Enemy;
+use crate::graphics::{text, tween};
+use crate::graphics::loading::Fonts;
+use crate::graphics::palette::Palette;
+use crate::graphics::text::TextStyles;
+use crate::logic::event_log::GameEvent;
+use crate::settings::Settings;
+use crate::util;
+use crate::util::size::tile_to_f32;
+use crate::util::tweening::{FLOATING_TEXT, FLOATING_TEXT_DESPAWN};
+use crate::util::z_pos;
+
+/// Numbers closer than this distance are merged into a single one.
+const MERGE_DISTANCE: f32 = tile_to_f32(2);
+
+/// Position of payouts without one, just above the GUI
+const MONEY_POS: Vec2 = Vec2::new(tile_to_f32(4), tile_to_f32(util::size::GUI_HEIGHT));
+
+/// Spawns a text centered on [pos] which floats up and fades out.
+pub fn spawn(commands: &mut Commands, fonts: &Fonts, pos: Vec2, text: &str, color: Palette) {
+    let start = vec3(pos.x, pos.y, z_pos::FLOATING_TEXT);
+    let end = start + vec3(0., tile_to_f32(2), 0.);
+
+    commands
+        .spawn(text::ttf_anchor(
+            start.x, start.y, start.z,
+            text, TextStyles::Body, fonts, color,
+            Anchor::BottomCenter,
+        ))
+        .insert(Animator::new(Tween::new(
+            EaseFunction::CubicOut,
+            Duration::from_millis(FLOATING_TEXT),
+            TransformPositionLens { start, end },
+        ).with_completed_event(FLOATING_TEXT_DESPAWN)))
+        .insert(Animator::new(tween::tween_text_opacity(FLOATING_TEXT, color.into(), false)))
+        .insert(BattleUI)
+    ;
+}
+
+pub fn despawn_floating_text(
+    mut commands: Commands,
+    mut tween_completed: EventReader<TweenCompleted>,
+) {
+    for TweenCompleted { entity, user_data } in tween_completed.iter() {
+        if *user_data == FLOATING_TEXT_DESPAWN {
+            commands.get_entity(*entity).map(EntityCommands::despawn_recursive);
+        }
+    }
+}
+
+pub fn spawn_damage_numbers(
+    mut commands: Commands,
+    mut events: EventReader<GameEvent>,
+    drones: Query<(&Transform, Option<&Enemy>)>,
+    settings: Res<Settings>,
+    fonts: Res<Fonts>,
+) {
+    if !settings.damage_numbers {
+        events.clear();
+        return;
+    }
+
+    // Sum the damages taken by each drone during this frame
+    let mut damages: HashMap<Entity, f32> = HashMap::new();
+    for event in events.iter() {
+        let GameEvent::Hit { id, damage, .. } = *event else { continue; };
+        if damage <= 0. { continue; }
+        *damages.entry(id).or_insert(0.) += damage;
+    }
+
+    // Merge close numbers so that Paint Bomb splashes stay readable
+    let mut numbers: Vec<(Vec2, f32)> = vec![];
+    for (e_drone, amount) in damages {
+        let Ok((t_drone, enemy)) = drones.get(e_drone) else { continue };
+        let size = match enemy {
+            Some(enemy) => body_size(enemy.class.get_tiles()),
+            None => Vec2::splat(tile_to_f32(1)),
+        };
+        let pos = t_drone.translation.xy() + vec2(size.x / 2., size.y);
+        match numbers.iter_mut().find(|(p, _)| p.distance(pos) < MERGE_DISTANCE) {
+            Some((_, total)) => *total += amount,
+            None => numbers.push((pos, amount)),
+        }
+    }
+
+    for (pos, amount) in numbers {
+        if amount < 0.5 { continue; }
+        spawn(&mut commands, &fonts, pos, &format!("{}", amount.round()), Palette::I);
+    }
+}
+
+pub fn spawn_payouts(
+    mut commands: Commands,
+    mut payouts: EventReader<Payout>,
+    fonts: Res<Fonts>,
+) {
+    for Payout(amount, _, pos) in payouts.iter() {
+        if *amount == 0 { continue; }
+        spawn(&mut commands, &fonts, pos.unwrap_or(MONEY_POS), &format!("+€{}", amount), Palette::H);
+    }
+}
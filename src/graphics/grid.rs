@@ -1,4 +1,3 @@
-use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
 
 use bevy::prelude::*;
@@ -7,12 +6,14 @@ use rand::{RngCore, thread_rng};
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-use crate::{GameState, logic};
+use crate::GameState;
+use crate::editor::CustomLevel;
 use crate::graphics::loading::Textures;
 use crate::graphics::sprite;
 use crate::graphics::sprites::TILE;
-use crate::level_select::CurrentLevel;
-use crate::logic::path::path_of_level_n;
+use crate::level_select::{CurrentLevel, GameMode};
+use crate::logic::gen::buildable_cells;
+use crate::logic::path::{Network, path_of_endless, path_of_level_n, path_of_sandbox};
 use crate::util::{battle_z_from_y, size, z_pos};
 use crate::util::size::is_oob;
 
@@ -38,7 +39,7 @@ impl Plugin for GridPlugin {
 pub struct GridUI;
 
 #[derive(Resource)]
-pub struct CurrentPath(pub logic::path::Path);
+pub struct CurrentPath(pub Network);
 
 /// BE CAREFUL THERE IS A FACTOR 2 BETWEEN GRID AND TILES, `GRID[.][.]` = 4 TILES
 #[derive(Resource)]
@@ -55,56 +56,34 @@ fn setup(
     mut commands: Commands,
     textures: Res<Textures>,
     level: Res<CurrentLevel>,
+    mode: Res<GameMode>,
+    custom: Option<Res<CustomLevel>>,
 ) {
-    let points = path_of_level_n(level.0);
-    let path = logic::path::Path::from_points(points.clone());
-    commands.insert_resource(CurrentPath(path));
-
-    draw_road(&mut commands, &textures, points);
+    let network = match (*mode, custom) {
+        (GameMode::Endless { seed }, _) => path_of_endless(seed),
+        (GameMode::Custom | GameMode::Campaign, Some(custom)) => custom.level.network(),
+        (GameMode::Sandbox, _) => path_of_sandbox(level.0),
+        _ => path_of_level_n(level.0),
+    };
+    draw_road(&mut commands, &textures, &network);
+    commands.insert_resource(CurrentPath(network));
 }
 
-pub fn draw_road(mut commands: &mut Commands, textures: &Res<Textures>, points: Vec<Vec2>) {
+/// Draws every route of a level.
+pub fn draw_road(mut commands: &mut Commands, textures: &Res<Textures>, network: &Network) {
     let mut grid = vec![vec![RoadElement::Plain; size::WIDTH]; size::HEIGHT];
 
     // Draw road
-    for i in 0..points.len() - 1 {
-        let (p1, p2) = (points[i], points[i + 1]);
-        if p1.x == p2.x {
-            let x = p1.x as usize;
-            let (y1, y2) = (p1.y as usize, p2.y as usize);
-            let (y1, y2) = (min(y1, y2), max(y1, y2));
-            for y in y1..=y2 {
-                if is_oob(x as isize, y as isize) { continue }
-                grid[y][x] = RoadElement::Road;
-            }
-        } else {
-            let y = p1.y as usize;
-            let (x1, x2) = (p1.x as usize, p2.x as usize);
-            let (x1, x2) = (min(x1, x2), max(x1, x2));
-            for x in x1..=x2 {
-                if is_oob(x as isize, y as isize) { continue }
-                grid[y][x] = RoadElement::Road;
-            }
-        }
+    let road: HashSet<(isize, isize)> = network.routes.iter()
+        .flat_map(|route| route.path.cells())
+        .filter(|(x, y)| !is_oob(*x, *y))
+        .collect();
+    for (x, y) in road.iter() {
+        grid[*y as usize][*x as usize] = RoadElement::Road;
     }
 
-    for y in 0..size::GRID_HEIGHT {
-        for x in 0..size::WIDTH {
-            if grid[y][x] == RoadElement::Road { continue }
-            for (dx, dy) in [
-                (0, -2),
-                (-1, -1), (0, -1), (1, -1),
-                (-2, 0), (-1, 0), (1, 0), (2, 0),
-                (-1, 1), (0, 1), (1, 1),
-                (0, 2),
-            ] {
-                if is_oob(x as isize + dx, y as isize + dy) { continue }
-                if grid[(y as isize + dy) as usize][(x as isize + dx) as usize] == RoadElement::Road {
-                    grid[y][x] = RoadElement::Rock;
-                    continue
-                }
-            }
-        }
+    for (x, y) in buildable_cells(&road) {
+        grid[y as usize][x as usize] = RoadElement::Rock;
     }
 
     draw_road_tiles(&grid, &mut commands, &textures.tileset);
@@ -140,7 +119,7 @@ impl Direction {
             Direction::S => (0, -1),
             Direction::SW => (-1, -1),
             Direction::W => (-1, 0),
-            Direction::NW => (-1, -1),
+            Direction::NW => (-1, 1),
         }
     }
 }
@@ -191,6 +170,10 @@ impl RoadElement {
         let e = adjacent.get(&Direction::E);
         let s = adjacent.get(&Direction::S);
         let w = adjacent.get(&Direction::W);
+        let ne = adjacent.get(&Direction::NE);
+        let se = adjacent.get(&Direction::SE);
+        let sw = adjacent.get(&Direction::SW);
+        let nw = adjacent.get(&Direction::NW);
 
         match self {
             RoadElement::Plain => {
@@ -239,6 +222,17 @@ impl RoadElement {
                     tiles[0] = (0, 1, 291, 4, 3, true, 3);
                     tiles[2] = (0, 0, 323, 4, 3, true, 3);
                 }
+
+                // Diagonal road: cut the corners which don't lead to another road
+                let road = |opt| is(opt, RoadElement::Road);
+                let diagonal = (road(ne) && !road(n) && !road(e)) || (road(nw) && !road(n) && !road(w))
+                    || (road(se) && !road(s) && !road(e)) || (road(sw) && !road(s) && !road(w));
+                if diagonal {
+                    if !road(n) && !road(w) && !road(nw) { tiles[0] = (0, 1, 322, 4, 3, true, 0); }
+                    if !road(n) && !road(e) && !road(ne) { tiles[1] = (1, 1, 322, 4, 3, false, 0); }
+                    if !road(s) && !road(w) && !road(sw) { tiles[2] = (0, 0, 322, 4, 3, false, 2); }
+                    if !road(s) && !road(e) && !road(se) { tiles[3] = (1, 0, 322, 4, 3, true, 2); }
+                }
             }
             RoadElement::Rock => {
                 if is(w, RoadElement::Road) && is(s, RoadElement::Road) {
@@ -6,15 +6,24 @@ use bevy_text_mode::TextModeTextureAtlasSprite;
 use strum::IntoEnumIterator;
 
 use crate::{GameState, tower, util};
-use crate::battle::{BattleUI, CursorState, Money, Pause, X2};
+use crate::battle::{BattleUI, CallWave, CursorState, Lives, Money, Pause, X2};
+use crate::bot::Bot;
 use crate::collision::body_size;
-use crate::graphics::{circle, MainBundle, sprite, sprite_f32, sprite_from_tile_with_alpha, sprite_from_tile_with_alpha_and_x_offset, text};
+use crate::editor::CustomLevel;
+use crate::graphics::{circle, MainBundle, sprite, sprite_f32, sprite_from_tile, sprite_from_tile_with_alpha, sprite_from_tile_with_alpha_and_x_offset, text};
 use crate::graphics::circle::Circles;
+use crate::graphics::floating_text::{despawn_floating_text, spawn_damage_numbers, spawn_payouts};
 use crate::graphics::grid::{Grid, RoadElement};
+use crate::graphics::health_bar::update_health_bars;
 use crate::graphics::loading::{Fonts, Textures};
+use crate::graphics::package::{Inventory, UsePackage};
 use crate::graphics::palette::Palette;
 use crate::graphics::text::TextStyles;
-use crate::level_select::CurrentLevel;
+use crate::level_select::{CurrentLevel, GameMode, sandbox_path_name};
+use crate::logic::battle_log::MoneyFlow;
+use crate::logic::difficulty::Difficulty;
+use crate::logic::event_log::GameEvent;
+use crate::logic::waves::WaveIterator;
 use crate::music::{PlaySfxEvent, SFX};
 use crate::tower::{Tower, Towers};
 use crate::util::{is_in, z_pos};
@@ -27,9 +36,11 @@ impl Plugin for GuiPlugin {
         app
             .add_system(setup.in_schedule(OnEnter(GameState::Battle)))
             .add_systems(
-                (update_money, update_cursor, update_popup, update_tower_button,
-                 update_text_button, place_tower, show_radius)
+                (update_money, update_lives, update_wave_text, update_wave_preview, update_cursor, update_popup, update_tower_button,
+                 update_text_button, place_tower, show_radius, update_health_bars,
+                 spawn_damage_numbers, spawn_payouts, despawn_floating_text)
                     .in_set(OnUpdate(GameState::Battle)))
+            .add_system(update_inventory.in_set(OnUpdate(GameState::Battle)))
         ;
     }
 }
@@ -49,6 +60,7 @@ enum TextButton {
     Sell,
     X2,
     Pause,
+    Next,
 }
 
 impl TextButton {
@@ -59,6 +71,7 @@ impl TextButton {
             TextButton::Sell => "Sell",
             TextButton::X2 => "Turbo",
             TextButton::Pause => "Pause",
+            TextButton::Next => "Next",
         }
     }
 
@@ -68,6 +81,7 @@ impl TextButton {
             TextButton::Sell => Vec2::new(f32_tile_to_f32(2.5), f32_tile_to_f32(1.25)),
             TextButton::X2 => Vec2::new(f32_tile_to_f32(3.3), f32_tile_to_f32(1.25)),
             TextButton::Pause => Vec2::new(f32_tile_to_f32(3.3), f32_tile_to_f32(1.25)),
+            TextButton::Next => Vec2::new(f32_tile_to_f32(2.7), f32_tile_to_f32(1.25)),
         }
     }
 }
@@ -75,25 +89,65 @@ impl TextButton {
 #[derive(Component)]
 struct MoneyText;
 
+#[derive(Component)]
+struct LivesText;
+
+/// Wave counter of endless mode
+#[derive(Component)]
+struct WaveText;
+
+/// Time left before the next wave, and boss warning
+#[derive(Component)]
+struct CountdownText;
+
+/// Icons and counts of the next wave drones
+#[derive(Component)]
+struct WavePreview;
+
+/// Wave shown by the [WavePreview]
+#[derive(Resource)]
+struct PreviewedWave(Option<usize>);
+
+/// Slot of the [Inventory]
+#[derive(Component)]
+struct InventorySlot(usize);
+
+/// Scale of the [WavePreview] icons
+const PREVIEW_SCALE: f32 = 0.5;
+
 fn setup(
     mut commands: Commands,
     fonts: Res<Fonts>,
     textures: Res<Textures>,
     level: Res<CurrentLevel>,
+    mode: Res<GameMode>,
+    custom: Option<Res<CustomLevel>>,
+    bot: Option<Res<Bot>>,
 ) {
     // Text
     let left_margin = f32_tile_to_f32(2.);
-    for (x, y, text, style) in [
-        (left_margin, f32_tile_to_f32(3.6), format!("Level {}", level.0), TextStyles::Heading),
-        (left_margin, f32_tile_to_f32(2.1), level.get_name().to_string(), TextStyles::Body),
+    let (title, subtitle) = match (*mode, custom) {
+        (GameMode::Endless { seed }, _) => (format!("Endless #{}", seed), String::new()),
+        (GameMode::Custom, Some(custom)) => (custom.level.name.clone(), "Custom level".to_string()),
+        (GameMode::Campaign, Some(custom)) => (format!("Level {}", level.0), custom.level.name.clone()),
+        (GameMode::Sandbox, _) => ("Sandbox".to_string(), sandbox_path_name(level.0)),
+        (GameMode::Demo, _) => (
+            format!("Demo: {} bot", bot.map_or("no", |b| b.name())),
+            format!("Level {} - press any key", level.0),
+        ),
+        _ => (format!("Level {}", level.0), level.get_name().to_string()),
+    };
+    let endless = matches!(*mode, GameMode::Endless { .. });
+    for (x, y, text, style, wave_text) in [
+        (left_margin, f32_tile_to_f32(3.6), title, TextStyles::Heading, false),
+        (left_margin, f32_tile_to_f32(2.1), subtitle, TextStyles::Body, endless),
     ] {
-        commands
-            .spawn(text::ttf(
-                x, y, z_pos::GUI_FG,
-                &text, style, &fonts, Palette::D,
-            ))
-            .insert(BattleUI)
-        ;
+        let mut entity = commands.spawn(text::ttf(
+            x, y, z_pos::GUI_FG,
+            &text, style, &fonts, Palette::D,
+        ));
+        entity.insert(BattleUI);
+        if wave_text { entity.insert(WaveText); }
     }
 
     commands
@@ -105,6 +159,50 @@ fn setup(
         .insert(BattleUI)
     ;
 
+    commands
+        .spawn(text::ttf(
+            f32_tile_to_f32(8.), f32_tile_to_f32(0.5), z_pos::GUI_FG,
+            "", TextStyles::Heading, &fonts, Palette::D,
+        ))
+        .insert(LivesText)
+        .insert(BattleUI)
+    ;
+
+    let mut countdown = text::ttf_anchor(
+        tile_to_f32(1), f32_tile_to_f32(util::size::HEIGHT as f32 - 0.5), z_pos::GUI_FG,
+        "", TextStyles::Body, &fonts, Palette::D,
+        Anchor::TopLeft,
+    );
+    countdown.text.sections.push(TextSection::new("", TextStyles::Body.style(&fonts, Palette::K)));
+    commands
+        .spawn(countdown)
+        .insert(CountdownText)
+        .insert(BattleUI)
+    ;
+    commands.insert_resource(PreviewedWave(None));
+
+    // Inventory, in the top right corner
+    for i in 0..util::package::INVENTORY_SLOTS {
+        let (x, y) = (util::size::WIDTH as f32 - 2. * (util::package::INVENTORY_SLOTS - i) as f32, util::size::HEIGHT as f32 - 1.5);
+        commands
+            .spawn(sprite_f32(
+                EMPTY_SLOT, f32_tile_to_f32(x), f32_tile_to_f32(y), z_pos::GUI_FG,
+                Palette::Transparent, Palette::D,
+                false, 0, textures.tileset.clone(),
+            ))
+            .insert(InventorySlot(i))
+            .insert(BattleUI)
+        ;
+        commands
+            .spawn(text::ttf_anchor(
+                f32_tile_to_f32(x + 0.5), f32_tile_to_f32(y), z_pos::GUI_FG,
+                &format!("{}", i + 1), TextStyles::Body, &fonts, Palette::D,
+                Anchor::TopCenter,
+            ))
+            .insert(BattleUI)
+        ;
+    }
+
     // Background
     for x in 0..util::size::WIDTH {
         for y in 0..util::size::GUI_HEIGHT {
@@ -158,6 +256,7 @@ fn setup(
     for (x, y, b) in [
         (util::size::WIDTH as f32 - 2., 4.75, TextButton::Upgrade),
         (util::size::WIDTH as f32 - 2., 3.0, TextButton::Sell),
+        (util::size::WIDTH as f32 - 6., 3.0, TextButton::Next),
         (util::size::WIDTH as f32 - 6., 1.25, TextButton::Pause),
         (util::size::WIDTH as f32 - 2., 1.25, TextButton::X2),
     ] {
@@ -183,6 +282,157 @@ fn update_money(
     }
 }
 
+fn update_lives(
+    lives: Res<Lives>,
+    mut text: Query<&mut Text, With<LivesText>>,
+) {
+    if lives.is_changed() || lives.is_added() {
+        let mut text = text.single_mut();
+        text.sections[0].value = match lives.current {
+            1 => "1 life".to_string(),
+            n => format!("{} lives", n),
+        };
+        text.sections[0].style.color = if lives.current <= 3 { Palette::K.into() } else { Palette::D.into() };
+    }
+}
+
+fn update_wave_text(
+    wave_iterator: Res<WaveIterator>,
+    mut text: Query<&mut Text, With<WaveText>>,
+) {
+    let Ok(mut text) = text.get_single_mut() else { return; };
+    if wave_iterator.is_changed() {
+        text.sections[0].value = format!("Wave {}", wave_iterator.wave);
+    }
+}
+
+/// Shows the drones of the next wave until it starts.
+fn update_wave_preview(
+    mut commands: Commands,
+    mut previewed: ResMut<PreviewedWave>,
+    mut countdown: Query<&mut Text, With<CountdownText>>,
+    preview: Query<Entity, With<WavePreview>>,
+    wave_iterator: Res<WaveIterator>,
+    difficulty: Res<Difficulty>,
+    textures: Res<Textures>,
+    fonts: Res<Fonts>,
+) {
+    let Ok(mut text) = countdown.get_single_mut() else { return; };
+    let next = wave_iterator.next_is_wave().then_some(wave_iterator.wave + 1);
+    let value = match next {
+        Some(n) => format!("Wave {} in {}s (N)", n, wave_iterator.next.remaining_secs().ceil()),
+        None => String::new(),
+    };
+    if text.sections[0].value != value { text.sections[0].value = value; }
+
+    if previewed.0 == next { return; }
+    previewed.0 = next;
+    for e in preview.iter() { commands.entity(e).despawn_recursive(); }
+
+    let drones = match next {
+        Some(_) => wave_iterator.next_wave(),
+        None => vec![],
+    };
+    text.sections[1].value = match drones.iter().any(|(d, _)| d.is_boss()) {
+        true => " BOSS".to_string(),
+        false => String::new(),
+    };
+
+    let mut x = tile_to_f32(1);
+    let y = f32_tile_to_f32(util::size::HEIGHT as f32 - 3.5);
+    for (drone, count) in drones {
+        let size = body_size(drone.get_tiles()) * PREVIEW_SCALE;
+        let mut stats = drone.get_stats(*difficulty);
+        stats.max_hp *= wave_iterator.next_hp_multiplier;
+
+        let mut bundle = MainBundle::from_xyz(x, y, z_pos::GUI_FG);
+        bundle.transform.scale = Vec3::splat(PREVIEW_SCALE);
+        commands
+            .spawn(bundle)
+            .with_children(|builder| sprite_from_tile(builder, drone.get_tiles(), &textures.tileset, 0.))
+            .insert(HoverPopup::new(
+                drone.get_name(),
+                &drone.get_traits(),
+                Some(("HP".to_string(), ((stats.max_hp / 100.).ceil() as u8).clamp(1, 10))),
+                Some(("Speed".to_string(), ((stats.speed * 10.).ceil() as u8).clamp(1, 10))),
+                size.x, size.y,
+            ))
+            .insert(WavePreview)
+            .insert(BattleUI)
+        ;
+        commands
+            .spawn(text::ttf_anchor(
+                x + size.x + 2., y, z_pos::GUI_FG,
+                &format!("x{}", count), TextStyles::Body, &fonts,
+                if drone.is_boss() { Palette::K } else { Palette::D },
+                Anchor::BottomLeft,
+            ))
+            .insert(WavePreview)
+            .insert(BattleUI)
+        ;
+        x += size.x + tile_to_f32(3);
+    }
+}
+
+/// Tile of the empty [InventorySlot]s
+const EMPTY_SLOT: usize = 423;
+
+/// Shows the [Inventory], and uses its packages with a click or the number keys.
+/// Packages which need a target are used on the next click on the battlefield.
+fn update_inventory(
+    mut use_package: EventWriter<UsePackage>,
+    mut slots: Query<(&InventorySlot, &Transform, &mut TextModeTextureAtlasSprite)>,
+    mut inventory: ResMut<Inventory>,
+    cursor_state: Option<ResMut<CursorState>>,
+    windows: Query<&Window>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+) {
+    let Some(mut cursor_state) = cursor_state else { return; };
+
+    if inventory.is_changed() {
+        for (slot, _, mut sprite) in slots.iter_mut() {
+            let (index, fg): (usize, Palette) = match inventory.slots[slot.0] {
+                Some(kind) => {
+                    let (_, _, i, _, fg, _, _) = kind.tile();
+                    (i, fg.into())
+                }
+                None => (EMPTY_SLOT, Palette::D),
+            };
+            sprite.index = index;
+            sprite.fg = fg.into();
+        }
+    }
+
+    let cursor_pos = util::cursor_pos(windows);
+    let clicked = mouse.just_pressed(MouseButton::Left);
+    let keys_pressed = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3].map(|k| keys.just_pressed(k));
+    let pressed = slots.iter().find(|(slot, t, _)| {
+        let hovered = cursor_pos.map_or(false, |c| is_in(c, t.translation.xy(), Vec2::splat(tile_to_f32(1))));
+        keys_pressed.get(slot.0).copied().unwrap_or(false) || (clicked && hovered)
+    });
+
+    if let Some((slot, t, _)) = pressed {
+        let Some(kind) = inventory.slots[slot.0] else { return; };
+        if kind.needs_target() {
+            cursor_state.set_if_neq(CursorState::Target(slot.0));
+        } else if let Some(kind) = inventory.take(slot.0) {
+            // Show the effect below the inventory
+            use_package.send(UsePackage(kind, t.translation.xy() - Vec2::new(0., tile_to_f32(3))));
+        }
+        return;
+    }
+
+    let CursorState::Target(i) = *cursor_state else { return; };
+    let Some(cursor_pos) = cursor_pos else { return; };
+    if clicked && cursor_pos.y > tile_to_f32(util::size::GUI_HEIGHT) {
+        if let Some(kind) = inventory.take(i) {
+            use_package.send(UsePackage(kind, cursor_pos));
+        }
+        cursor_state.set_if_neq(CursorState::Select);
+    }
+}
+
 fn update_cursor(
     mut commands: Commands,
     grid: Option<Res<Grid>>,
@@ -323,7 +573,8 @@ fn spawn_popup(
     commands
         .spawn(MainBundle::from_xyz(
             owner_pos.translation.x + info.width + f32_tile_to_f32(0.5),
-            owner_pos.translation.y + info.height - tile_to_f32(3),
+            // Keep the popup on screen
+            (owner_pos.translation.y + info.height - tile_to_f32(3)).min(tile_to_f32(util::size::HEIGHT - 6)),
             z_pos::POPUP_BG,
         ))
         .insert(Popup(owner_id))
@@ -486,6 +737,7 @@ fn update_tower_button(
     mouse: Res<Input<MouseButton>>,
     money: Res<Money>,
     current_level: Res<CurrentLevel>,
+    mode: Res<GameMode>,
 ) {
     let Some(mut cursor_state) = cursor_state else { return; };
     let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
@@ -493,7 +745,7 @@ fn update_tower_button(
 
     for (button, pos, id) in &buttons {
         let button_state: ButtonState;
-        if money.0 < button.0.get_cost() || current_level.0 < button.0.unlocked_at() {
+        if money.0 < button.0.get_cost() || !mode.tower_unlocked(button.0, current_level.0) {
             button_state = ButtonState::CantBuild;
         } else if is_in(cursor_pos, pos.translation.xy(), Vec2::new(tile_to_f32(2), tile_to_f32(3))) {
             button_state = ButtonState::Selected;
@@ -530,6 +782,7 @@ fn place_tower(
     time: Res<Time>,
     grid: Option<ResMut<Grid>>,
     mut money: ResMut<Money>,
+    mut events: EventWriter<GameEvent>,
 ) {
     let Some(mut state) = state else { return; };
     let Some(mut grid) = grid else { return; };
@@ -559,7 +812,9 @@ fn place_tower(
                     if money.0 >= t.get_cost() {
                         money.0 -= t.get_cost();
                         grid.towers.insert((x, y));
-                        tower::place_tower(x, y, &mut commands, *t, &textures.tileset, &time);
+                        let e_tower = tower::place_tower(x, y, &mut commands, *t, &textures.tileset, &time);
+                        events.send(GameEvent::TowerPlaced { tower: e_tower, model: *t, x, y });
+                        events.send(GameEvent::Money { amount: -(t.get_cost() as i32), flow: MoneyFlow::Building });
                     }
                     state.set_if_neq(CursorState::Select);
                     return;
@@ -595,6 +850,7 @@ fn place_tower(
 
 fn update_text_button(
     mut sfx: EventWriter<PlaySfxEvent>,
+    mut call_wave: EventWriter<CallWave>,
     cursor_state: Option<ResMut<CursorState>>,
     mut buttons: Query<(&TextButton, &Transform, &mut Text)>,
     mut time: ResMut<Time>,
@@ -647,6 +903,7 @@ fn update_text_button(
                     }
                     pause.0 = !pause.0;
                 }
+                TextButton::Next => { call_wave.send(CallWave); }
             }
         }
 
@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+use bevy_text_mode::TextModeTextureAtlasSprite;
+
+use crate::collision::body_size;
+use crate::drones::{Drones, Enemy};
+use crate::graphics::palette::Palette;
+use crate::graphics::sprite_f32;
+use crate::util::size::tile_to_f32;
+use crate::util::z_pos;
+
+/// Segments of a health bar, for each tile of the drone width
+const SEGMENTS_PER_TILE: usize = 4;
+
+/// A segment of a drone health bar: (index from the left, number of segments).
+#[derive(Component)]
+pub struct HealthBarSegment(usize, usize);
+
+/// Spawns a hidden health bar above [drone].
+pub fn spawn(builder: &mut ChildBuilder, drone: Drones, atlas: &Handle<TextureAtlas>) {
+    let size = body_size(drone.get_tiles());
+    let count = (size.x / tile_to_f32(1)) as usize * SEGMENTS_PER_TILE;
+    for i in 0..count {
+        let mut bundle = sprite_f32(
+            419, i as f32 * tile_to_f32(1) / SEGMENTS_PER_TILE as f32, size.y, z_pos::HEALTH_BAR_OFFSET,
+            Palette::Transparent, Palette::G,
+            false, 0, atlas.clone(),
+        );
+        bundle.transform.scale.y = 0.5;
+        bundle.visibility = Visibility::Hidden;
+        builder
+            .spawn(bundle)
+            .insert(HealthBarSegment(i, count));
+    }
+}
+
+/// Shows the health bar of damaged drones.
+pub fn update_health_bars(
+    drones: Query<(&Enemy, &Children), Changed<Enemy>>,
+    mut segments: Query<(&HealthBarSegment, &mut TextModeTextureAtlasSprite, &mut Visibility)>,
+) {
+    for (enemy, children) in drones.iter() {
+        let ratio = enemy.stats.hp / enemy.stats.max_hp;
+        for &child in children.iter() {
+            let Ok((segment, mut sprite, mut visibility)) = segments.get_mut(child) else { continue };
+            let fg: Color = if (segment.0 as f32) < ratio * segment.1 as f32 { Palette::G.into() } else { Palette::K.into() };
+            if sprite.fg != fg { sprite.fg = fg; }
+            visibility.set_if_neq(if ratio < 1. { Visibility::Inherited } else { Visibility::Hidden });
+        }
+    }
+}
@@ -4,6 +4,7 @@ use bevy_text_mode::{TextModeSpriteSheetBundle, TextModeTextureAtlasSprite};
 use bevy_tweening::{component_animator_system, TweeningPlugin};
 
 use crate::graphics::animation::AnimationPlugin;
+use crate::graphics::debug::DebugPlugin;
 use crate::graphics::grid::GridPlugin;
 use crate::graphics::gui::GuiPlugin;
 use crate::graphics::loading::LoadingPlugin;
@@ -24,6 +25,9 @@ pub mod package;
 pub mod gui;
 pub mod tween;
 pub mod circle;
+pub mod health_bar;
+pub mod floating_text;
+pub mod debug;
 
 #[derive(Bundle, Debug, Default)]
 pub struct MainBundle {
@@ -138,6 +142,7 @@ impl Plugin for GraphicsPlugin {
             .add_plugin(TweeningPlugin)
             .add_plugin(GridPlugin)
             .add_plugin(GuiPlugin)
+            .add_plugin(DebugPlugin)
             .add_startup_system(circle::setup)
         ;
     }
@@ -1,47 +1,254 @@
+use std::time::Duration;
+
 use bevy::asset::Handle;
 use bevy::hierarchy::{ChildBuilder, DespawnRecursiveExt};
 use bevy::input::Input;
 use bevy::math::{Vec2, Vec3Swizzles};
-use bevy::prelude::{Commands, Component, Entity, EventWriter, MouseButton, Query, Res, ResMut, Transform, Window};
+use bevy::prelude::{Commands, Component, DetectChangesMut, Entity, EventReader, EventWriter, MouseButton, Query, Res, ResMut, Resource, Time, Transform, Visibility, Window, With, Without};
 use bevy::sprite::TextureAtlas;
-use enum_derived::Rand;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use serde::Serialize;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 
 use crate::{graphics, shot, util};
-use crate::battle::{CursorState, Money};
+use crate::battle::{BattleUI, CursorState, DronesStats, Lives, Money};
+use crate::drones::Enemy;
+use crate::graphics::floating_text;
+use crate::graphics::grid::{CurrentPath, GridElement};
+use crate::graphics::loading::{Fonts, Textures};
+use crate::graphics::palette::Palette;
 use crate::graphics::sprites::TILE;
+use crate::logic::battle_log::MoneyFlow;
+use crate::logic::difficulty::Difficulty;
+use crate::logic::event_log::GameEvent;
+use crate::logic::rules;
 use crate::logic::tower_stats::OMEGA_DAMAGES;
 use crate::music::{PlaySfxEvent, SFX};
+use crate::settings::Settings;
 use crate::shot::spawn_bomb;
-use crate::util::{is_in, z_pos};
+use crate::tower::Slow;
+use crate::util::{is_in, vec2_with_battle_z, z_pos};
+use crate::util::package::{BLINK_FREQUENCY, COFFEE_DURATION, DECOY_DURATION, FREEZE_DURATION, INVENTORY_SLOTS, PACKAGE_BLINK, PACKAGE_LIFETIME};
 use crate::util::size::battle::OMEGA_RANGE;
 use crate::util::size::tile_to_f32;
 
 #[derive(Component)]
 pub struct ClickablePackage;
 
-#[derive(Rand, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, EnumIter, Serialize)]
 pub enum PackageKind {
-    #[weight(20)]
     Common,
-    #[weight(2)]
     Money,
-    // #[weight(0)]
-    // Coffee,
-    #[weight(2)]
+    Coffee,
+    Freeze,
+    Repair,
+    Mystery,
+    Decoy,
     Cursed,
     Omega,
 }
 
 impl PackageKind {
+    /// Odds of the package on a level (0 for endless mode).
+    /// New kinds appear along the campaign, and there are more [PackageKind::Cursed] ones on harder difficulties.
+    fn get_weight(&self, level: u8, difficulty: Difficulty) -> u32 {
+        let from_level = |first: u8, weight: u32| if level == 0 || level >= first { weight } else { 0 };
+        match (self, difficulty) {
+            (PackageKind::Common, _) => 20,
+            (PackageKind::Money, Difficulty::Easy) => 3,
+            (PackageKind::Money, Difficulty::Nightmare) => 1,
+            (PackageKind::Money, _) => 2,
+            (PackageKind::Coffee, _) => from_level(2, 2),
+            (PackageKind::Freeze, _) => from_level(3, 1),
+            (PackageKind::Repair, Difficulty::Easy) => from_level(2, 2),
+            (PackageKind::Repair, _) => from_level(2, 1),
+            (PackageKind::Mystery, _) => from_level(5, 1),
+            (PackageKind::Decoy, _) => from_level(4, 1),
+            (PackageKind::Cursed, Difficulty::Easy) => 1,
+            (PackageKind::Cursed, Difficulty::Normal) => 2,
+            (PackageKind::Cursed, Difficulty::Hard) => 4,
+            (PackageKind::Cursed, Difficulty::Nightmare) => 6,
+            (PackageKind::Omega, Difficulty::Easy) => 2,
+            (PackageKind::Omega, _) => 1,
+        }
+    }
+
+    pub const fn get_name(&self) -> &'static str {
+        match self {
+            PackageKind::Common => "Common",
+            PackageKind::Money => "Money",
+            PackageKind::Coffee => "Coffee",
+            PackageKind::Freeze => "Freeze",
+            PackageKind::Repair => "Repair",
+            PackageKind::Mystery => "Mystery",
+            PackageKind::Decoy => "Decoy",
+            PackageKind::Cursed => "Cursed",
+            PackageKind::Omega => "Omega",
+        }
+    }
+
+    /// Popup shown when the package is collected
+    fn get_effect(&self) -> String {
+        match self {
+            PackageKind::Common => format!("+€{}", util::package::MONEY_SMALL),
+            PackageKind::Money => format!("+€{}", util::package::MONEY_BIG),
+            PackageKind::Coffee => "Towers fire faster!".to_string(),
+            PackageKind::Freeze => "Drones frozen!".to_string(),
+            PackageKind::Repair => "+1 life".to_string(),
+            PackageKind::Mystery => "?".to_string(),
+            PackageKind::Decoy => "Decoy dropped!".to_string(),
+            PackageKind::Cursed => format!("-€{}", util::package::MONEY_CURSE),
+            PackageKind::Omega => "Omega!".to_string(),
+        }
+    }
+
+    const fn get_sfx(&self) -> SFX {
+        match self {
+            PackageKind::Common => SFX::PackageCommon,
+            PackageKind::Money => SFX::PackageBonus,
+            PackageKind::Coffee => SFX::PackageCoffee,
+            PackageKind::Freeze => SFX::PackageFreeze,
+            PackageKind::Repair => SFX::PackageRepair,
+            PackageKind::Mystery => SFX::PackageMystery,
+            PackageKind::Decoy => SFX::PackageDecoy,
+            PackageKind::Cursed => SFX::PackageMalus,
+            PackageKind::Omega => SFX::PackageOmega,
+        }
+    }
+
     fn get_tile(&self) -> usize {
         return match self {
             PackageKind::Common => 393,
+            PackageKind::Freeze => 394,
             PackageKind::Money => 395,
-            // PackageKind::Coffee => 397,
+            PackageKind::Repair => 396,
             PackageKind::Cursed => 397,
+            PackageKind::Mystery => 398,
+            PackageKind::Coffee => 399,
+            PackageKind::Decoy => 400,
             PackageKind::Omega => 401,
         } as usize;
     }
+
+    pub fn tile(&self) -> TILE {
+        (0, 0, self.get_tile(), 14, self.get_color() as u8, false, 0)
+    }
+
+    fn get_color(&self) -> Palette {
+        match self {
+            PackageKind::Freeze => Palette::C,
+            PackageKind::Repair => Palette::G,
+            PackageKind::Mystery => Palette::M,
+            PackageKind::Decoy => Palette::I,
+            _ => Palette::L,
+        }
+    }
+
+    /// Packages which go to the [Inventory] instead of being used at once
+    const fn is_storable(&self) -> bool {
+        matches!(self, PackageKind::Coffee | PackageKind::Freeze | PackageKind::Decoy | PackageKind::Omega)
+    }
+
+    /// Packages used on a position chosen by the player
+    pub const fn needs_target(&self) -> bool {
+        matches!(self, PackageKind::Decoy | PackageKind::Omega)
+    }
+
+    /// Effect of a [PackageKind::Mystery] package
+    pub fn mystery(rng: &mut impl Rng) -> Self {
+        let kinds: Vec<PackageKind> = PackageKind::iter().filter(|k| *k != PackageKind::Mystery).collect();
+        *kinds.choose(rng).unwrap()
+    }
+}
+
+/// Odds of each [PackageKind] during a battle.
+#[derive(Resource)]
+pub struct PackageOdds {
+    kinds: Vec<PackageKind>,
+    dist: Option<WeightedIndex<u32>>,
+}
+
+impl PackageOdds {
+    pub fn new(level: u8, difficulty: Difficulty) -> Self {
+        let kinds: Vec<PackageKind> = PackageKind::iter().collect();
+        let dist = WeightedIndex::new(kinds.iter().map(|k| k.get_weight(level, difficulty))).ok();
+        PackageOdds { kinds, dist }
+    }
+
+    pub fn pick(&self, rng: &mut impl Rng) -> PackageKind {
+        match &self.dist {
+            Some(dist) => self.kinds[dist.sample(rng)],
+            None => PackageKind::Common,
+        }
+    }
+}
+
+/// Timed effects of the collected packages.
+#[derive(Resource, Default)]
+pub struct PackageEffects {
+    /// End of the [PackageKind::Coffee] buff
+    coffee: Duration,
+    /// End of the [PackageKind::Freeze] stun
+    freeze: Duration,
+}
+
+impl PackageEffects {
+    pub fn coffee(&self, time: &Time) -> bool { time.elapsed() < self.coffee }
+
+    pub fn frozen(&self, time: &Time) -> bool { time.elapsed() < self.freeze }
+}
+
+/// Lure slowing down the drones around it.
+#[derive(Component)]
+pub struct Decoy {
+    t_final: Duration,
+}
+
+/// Sent to apply the effect of a package at a position.
+pub struct UsePackage(pub PackageKind, pub Vec2);
+
+/// Sent to collect a package without clicking on it.
+pub struct CollectPackage(pub Entity);
+
+/// Stored packages, to be used later.
+#[derive(Resource, Default)]
+pub struct Inventory {
+    pub slots: [Option<PackageKind>; INVENTORY_SLOTS],
+}
+
+impl Inventory {
+    /// Stores a package in the first free slot, returns false when the inventory is full.
+    fn store(&mut self, kind: PackageKind) -> bool {
+        match self.slots.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(kind);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn take(&mut self, i: usize) -> Option<PackageKind> {
+        self.slots.get_mut(i).and_then(Option::take)
+    }
+}
+
+/// Time when a package was dropped on the road.
+#[derive(Component)]
+pub struct Dropped(pub Duration);
+
+impl Dropped {
+    /// Returns true when the package is done falling.
+    fn landed(&self, time: &Time) -> bool {
+        time.elapsed() >= self.0 + Duration::from_millis(util::tweening::PACKAGE_DROP)
+    }
+
+    /// Seconds left before the package expires
+    fn time_left(&self, time: &Time) -> f32 {
+        PACKAGE_LIFETIME - (time.elapsed() - self.0).as_secs_f32()
+    }
 }
 
 #[derive(Component, Clone)]
@@ -50,17 +257,17 @@ pub struct Package {
 }
 
 impl Package {
-    pub fn new() -> Self {
-        Package { kind: PackageKind::rand() }
+    pub fn new(odds: &PackageOdds) -> Self {
+        Package { kind: odds.pick(&mut thread_rng()) }
     }
 
-    pub fn tile(&self) -> TILE {
-        (0, 0, self.kind.get_tile(), 14, 11, false, 0)
-    }
+    pub fn tile(&self) -> TILE { self.kind.tile() }
+
+    pub fn kind(&self) -> PackageKind { self.kind }
 }
 
-pub fn spawn(builder: &mut ChildBuilder, offset: Vec2, atlas: &Handle<TextureAtlas>) {
-    let package = Package::new();
+pub fn spawn(builder: &mut ChildBuilder, offset: Vec2, odds: &PackageOdds, atlas: &Handle<TextureAtlas>) {
+    let package = Package::new(odds);
     let (_, _, i, bg, fg, f, r) = package.tile();
     builder
         .spawn(graphics::sprite_f32(
@@ -71,43 +278,160 @@ pub fn spawn(builder: &mut ChildBuilder, offset: Vec2, atlas: &Handle<TextureAtl
     ;
 }
 
+/// Collects the clicked packages, the landed ones but curses with [Settings::auto_collect], and the ones sent with [CollectPackage].
 pub fn collect_package(
     mut commands: Commands,
     mut sfx: EventWriter<PlaySfxEvent>,
-    packages: Query<(&Package, &Transform, Entity)>,
+    mut use_package: EventWriter<UsePackage>,
+    mut collect: EventReader<CollectPackage>,
+    packages: Query<(&Package, &Transform, Entity, Option<&Dropped>)>,
     windows: Query<&Window>,
     mouse: Res<Input<MouseButton>>,
     state: Option<Res<CursorState>>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+    mut inventory: ResMut<Inventory>,
     mut money: ResMut<Money>,
+    mut stats: ResMut<DronesStats>,
+    mut events: EventWriter<GameEvent>,
 ) {
-    let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
-    if !mouse.just_pressed(MouseButton::Left) { return; }
-    let sell = state.is_some() && state.unwrap().eq(&CursorState::Sell);
+    let cursor_pos = util::cursor_pos(windows);
+    let clicked = mouse.just_pressed(MouseButton::Left);
+    let sell = state.as_ref().map_or(false, |s| s.eq(&CursorState::Sell));
+    let targeting = state.as_ref().map_or(false, |s| matches!(**s, CursorState::Target(_)));
+    let sent: Vec<Entity> = collect.iter().map(|c| c.0).collect();
 
-    for (p, t, id) in &packages {
+    for (p, t, id, dropped) in &packages {
+        let pos = t.translation.xy();
         // Click on package
-        if is_in(cursor_pos, t.translation.xy(), Vec2::new(tile_to_f32(1), tile_to_f32(1))) {
-            commands.entity(id).despawn_recursive();
+        let hovered = cursor_pos.map_or(false, |c| is_in(c, pos, Vec2::new(tile_to_f32(1), tile_to_f32(1))));
+        let landed = dropped.map_or(false, |d| d.landed(&time));
+        let auto = settings.auto_collect && landed && p.kind != PackageKind::Cursed;
+        if !(clicked && hovered && !targeting) && !auto && !sent.contains(&id) { continue; }
 
-            if sell {
-                money.0 += util::package::MONEY_SELL;
-                sfx.send(PlaySfxEvent(SFX::SellTower));
-            } else {
-                match p.kind {
-                    PackageKind::Common => { money.0 += util::package::MONEY_SMALL; }
-                    PackageKind::Money => { money.0 += util::package::MONEY_BIG; }
-                    // PackageKind::Coffee => {}
-                    PackageKind::Cursed => { if money.0 >= util::package::MONEY_CURSE { money.0 -= util::package::MONEY_CURSE; } else { money.0 = 0; } }
-                    PackageKind::Omega => {
-                        spawn_bomb(shot::Bomb::new(cursor_pos, OMEGA_RANGE, OMEGA_DAMAGES), &mut commands);
-                    }
-                }
-                match p.kind {
-                    PackageKind::Cursed => sfx.send(PlaySfxEvent(SFX::PackageMalus)),
-                    _ => sfx.send(PlaySfxEvent(SFX::PackageBonus)),
+        commands.entity(id).despawn_recursive();
+        stats.packages += 1;
+        events.send(GameEvent::PackageCollected { kind: p.kind });
+
+        if sell && clicked && hovered {
+            money.0 += util::package::MONEY_SELL;
+            events.send(GameEvent::Money { amount: util::package::MONEY_SELL as i32, flow: MoneyFlow::Packages });
+            sfx.send(PlaySfxEvent(SFX::SellTower));
+        } else if p.kind.is_storable() && inventory.store(p.kind) {
+            sfx.send(PlaySfxEvent(SFX::PackageBonus));
+        } else {
+            // Packages are centered on their tile
+            use_package.send(UsePackage(p.kind, pos + Vec2::splat(tile_to_f32(1) / 2.)));
+        }
+    }
+}
+
+/// Applies the effect of the packages at the position of their [UsePackage] event.
+pub fn use_package(
+    mut commands: Commands,
+    mut sfx: EventWriter<PlaySfxEvent>,
+    mut events: EventReader<UsePackage>,
+    time: Res<Time>,
+    fonts: Res<Fonts>,
+    textures: Res<Textures>,
+    mut money: ResMut<Money>,
+    mut lives: ResMut<Lives>,
+    mut effects: ResMut<PackageEffects>,
+    path: Res<CurrentPath>,
+    mut game_events: EventWriter<GameEvent>,
+) {
+    for UsePackage(package, pos) in events.iter() {
+        let kind = match package {
+            PackageKind::Mystery => PackageKind::mystery(&mut thread_rng()),
+            kind => *kind,
+        };
+        let mut effect = kind.get_effect();
+        match kind {
+            PackageKind::Common | PackageKind::Money | PackageKind::Cursed => {
+                let amount = rules::package_money(kind, money.0);
+                money.0 = money.0.saturating_add_signed(amount as i16);
+                let flow = if amount < 0 { MoneyFlow::Curses } else { MoneyFlow::Packages };
+                game_events.send(GameEvent::Money { amount, flow });
+            }
+            PackageKind::Coffee => {
+                effects.coffee = time.elapsed() + Duration::from_secs_f32(COFFEE_DURATION);
+            }
+            PackageKind::Freeze => {
+                effects.freeze = time.elapsed() + Duration::from_secs_f32(FREEZE_DURATION);
+            }
+            PackageKind::Repair => {
+                match rules::repair(lives.current, lives.max) {
+                    Some(repaired) => lives.current = repaired,
+                    None => effect = "Lives already full".to_string(),
                 }
             }
+            // Resolved above
+            PackageKind::Mystery => {}
+            PackageKind::Decoy => {
+                let (_, _, i, bg, fg, f, r) = kind.tile();
+                let start = vec2_with_battle_z(rules::decoy_position(&path.0, *pos) - Vec2::splat(tile_to_f32(1) / 2.));
+                commands
+                    .spawn(graphics::sprite_f32(
+                        i, start.x, start.y, start.z,
+                        bg.into(), fg.into(), f, r,
+                        textures.tileset.clone(),
+                    ))
+                    .insert(Decoy { t_final: time.elapsed() + Duration::from_secs_f32(DECOY_DURATION) })
+                    .insert(GridElement)
+                    .insert(BattleUI)
+                ;
+            }
+            PackageKind::Omega => {
+                spawn_bomb(shot::Bomb::new(*pos, OMEGA_RANGE, OMEGA_DAMAGES), &mut commands);
+            }
+        }
+        sfx.send(PlaySfxEvent(kind.get_sfx()));
+
+        if *package == PackageKind::Mystery { effect = format!("? {}", effect); }
+        let color = if kind == PackageKind::Cursed { Palette::K } else { Palette::C };
+        floating_text::spawn(&mut commands, &fonts, *pos + Vec2::new(0., tile_to_f32(1)), &effect, color);
+    }
+}
 
+/// Makes the dropped packages blink before they expire.
+pub fn expire_packages(
+    mut commands: Commands,
+    mut packages: Query<(Entity, &Dropped, &mut Visibility)>,
+    time: Res<Time>,
+) {
+    for (id, dropped, mut visibility) in packages.iter_mut() {
+        let left = dropped.time_left(&time);
+        if left <= 0. {
+            commands.entity(id).despawn_recursive();
+        } else if left < PACKAGE_BLINK {
+            visibility.set_if_neq(match (left * BLINK_FREQUENCY) as u32 % 2 {
+                0 => Visibility::Inherited,
+                _ => Visibility::Hidden,
+            });
         }
     }
-}
\ No newline at end of file
+}
+
+/// Slows down the drones close to a [Decoy], and removes it when it expires.
+pub fn update_decoys(
+    mut commands: Commands,
+    decoys: Query<(Entity, &Transform, &Decoy), Without<Enemy>>,
+    enemies: Query<(Entity, &Transform, Option<&Slow>), With<Enemy>>,
+    time: Res<Time>,
+) {
+    for (e_decoy, t_decoy, decoy) in decoys.iter() {
+        if decoy.t_final <= time.elapsed() {
+            commands.entity(e_decoy).despawn_recursive();
+            continue;
+        }
+        for (e_enemy, t_enemy, slow) in enemies.iter() {
+            let Some(factor) = rules::decoy_slow(t_enemy.translation.xy(), t_decoy.translation.xy(), slow.map(|s| s.factor)) else { continue; };
+            if let Some(mut entity_commands) = commands.get_entity(e_enemy) {
+                entity_commands.insert(Slow {
+                    factor,
+                    t_final: time.elapsed() + Duration::from_secs_f32(0.5),
+                });
+            }
+        }
+    }
+}
@@ -22,15 +22,18 @@ impl Plugin for TransitionPlugin {
 /// Resource to animate the game frame.
 /// - Closing transition if [next_state] is Some.
 /// - Opening transition else.
+/// Panes are red after a defeat.
 #[derive(Resource)]
 pub struct Transition {
     in_progress: bool,
     next_state: Option<GameState>,
+    defeat: bool,
 }
 
 impl Transition {
-    pub fn to(state: GameState) -> Self { Self { in_progress: false, next_state: Some(state) } }
-    pub fn open() -> Self { Self { in_progress: false, next_state: None } }
+    pub fn to(state: GameState) -> Self { Self { in_progress: false, next_state: Some(state), defeat: false } }
+    pub fn defeat(state: GameState) -> Self { Self { defeat: true, ..Self::to(state) } }
+    pub fn open() -> Self { Self { in_progress: false, next_state: None, defeat: false } }
     pub fn is_opening(&self) -> bool { self.next_state.is_none() }
 }
 
@@ -53,6 +56,7 @@ pub(in crate::graphics) fn start_transition(
         panes.iter().for_each(|id| commands.entity(id).despawn_recursive());
 
         let open = transition.is_opening();
+        let fill: u8 = if transition.defeat { 10 } else { 9 };
         let tween = |start, end| Tween::new(
             if open { EaseFunction::CubicIn } else { EaseFunction::CubicOut },
             Duration::from_millis(util::transition::SPEED),
@@ -82,7 +86,7 @@ pub(in crate::graphics) fn start_transition(
                         let (index, bg, fg, rotation) = match (x, y) {
                             (_, y) if y == last_y - 1 => (23, 15, 3, 3),
                             (_, y) if y == last_y - 2 => (0, 15, 16, 0),
-                            _ => (0, 9, 16, 0),
+                            _ => (0, fill, 16, 0),
                         };
                         builder.spawn(sprite(
                             index, x, y, 0.,
@@ -106,7 +110,7 @@ pub(in crate::graphics) fn start_transition(
                         let (index, bg, fg, rotation) = match (x, y) {
                             (_, 0) => (23, 15, 3, 1),
                             (_, 1) => (0, 15, 16, 0),
-                            _ => (0, 9, 16, 0),
+                            _ => (0, fill, 16, 0),
                         };
                         builder.spawn(sprite(
                             index, x, y, 0.,
@@ -132,7 +136,7 @@ pub(in crate::graphics) fn end_transition(
             tween_completed.clear();
 
             if let Some(state) = transition.next_state {
-                commands.insert_resource(Transition::open());
+                commands.insert_resource(Transition { defeat: transition.defeat, ..Transition::open() });
                 next_state.set(state);
             } else {
                 commands.remove_resource::<Transition>();
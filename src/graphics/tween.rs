@@ -1,7 +1,9 @@
 use std::time::Duration;
 
+use bevy::prelude::{Color, Text};
 use bevy_text_mode::TextModeTextureAtlasSprite;
 use bevy_tweening::{EaseFunction, Lens, Tween};
+use bevy_tweening::lens::TextColorLens;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct TransformTextModeSpriteAlphaLens {
@@ -25,4 +27,13 @@ pub fn tween_text_mode_sprite_opacity(ms: u64, appear: bool) -> Tween<TextModeTe
             end: if appear { 1. } else { 0. },
         },
     )
-}
\ No newline at end of file
+}
+pub fn tween_text_opacity(ms: u64, color: Color, appear: bool) -> Tween<Text> {
+    let (mut start, mut end) = (color, color);
+    if appear { start.set_a(0.); } else { end.set_a(0.); }
+    Tween::new(
+        EaseFunction::CubicIn,
+        Duration::from_millis(ms),
+        TextColorLens { start, end, section: 0 },
+    )
+}
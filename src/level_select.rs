@@ -1,15 +1,23 @@
 use bevy::math::{vec2, Vec3Swizzles};
 use bevy::prelude::*;
 use bevy::sprite::Anchor;
+use bevy_pkv::PkvStore;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 
 use crate::{GameState, Progress, util};
-use crate::graphics::{grid, sprite};
+use crate::graphics::{grid, sprite, sprite_f32};
 use crate::graphics::grid::GridUI;
 use crate::graphics::loading::{Fonts, Textures};
 use crate::graphics::palette::Palette;
 use crate::graphics::text::{TextStyles, ttf_anchor};
 use crate::graphics::transition::Transition;
+use crate::editor::CustomLevel;
+use crate::logic::difficulty::Difficulty;
+use crate::logic::level_data::LevelData;
+use crate::logic::path::Network;
 use crate::music::{BGM, PlayBgmEvent};
+use crate::tower::Towers;
 use crate::util::is_in;
 use crate::util::size::{f32_tile_to_f32, tile_to_f32};
 
@@ -18,7 +26,11 @@ pub struct LevelSelectPlugin;
 impl Plugin for LevelSelectPlugin {
     fn build(&self, app: &mut App) {
         app
+            .insert_resource(GameMode::Campaign)
+            .insert_resource(EndlessSeed(1))
+            .init_resource::<Difficulty>()
             .add_system(setup.in_schedule(OnEnter(GameState::Select)))
+            .insert_resource(SandboxPath(0))
             .add_system(update.in_set(OnUpdate(GameState::Select)))
             .add_system(clean.in_schedule(OnExit(GameState::Select)))
         ;
@@ -39,6 +51,88 @@ impl CurrentLevel {
             _ => ""
         }
     }
+
+    /// Stars needed to unlock the level
+    pub const fn required_stars(&self) -> u8 {
+        match self.0 {
+            6 => 8,
+            _ => 0,
+        }
+    }
+
+    /// Starting lives of the level on Normal, the 6th Simple drone to escape loses the battle
+    pub const fn get_lives(&self) -> u16 {
+        match self.0 {
+            1 => 6,
+            2 | 3 | 4 | 5 => 6,
+            6 => 6,
+            _ => 6,
+        }
+    }
+}
+
+#[derive(Resource, Copy, Clone, PartialEq)]
+pub enum GameMode {
+    /// Levels of the level map
+    Campaign,
+    /// Generated path and waves, until the player runs out of lives
+    Endless { seed: u64 },
+    /// Level made in the level editor
+    Custom,
+    /// Practice with unlimited money, drones are spawned by hand
+    Sandbox,
+    /// Campaign level played by the bot on the menu, see [crate::attract]
+    Demo,
+}
+
+impl GameMode {
+    /// [PkvStore] key of the best wave reached with an endless seed
+    pub fn best_wave_key(seed: u64, difficulty: Difficulty) -> String {
+        format!("endless.{}.{}", seed, difficulty.get_name())
+    }
+
+    /// [PkvStore] key of the heatmap aggregated over the battles on the same road
+    pub fn heatmap_key(&self, level: u8) -> Option<String> {
+        match self {
+            GameMode::Campaign => Some(format!("heatmap.{}", level)),
+            GameMode::Endless { seed } => Some(format!("heatmap.endless.{}", seed)),
+            GameMode::Custom | GameMode::Sandbox | GameMode::Demo => None,
+        }
+    }
+
+    /// State shown at the end of a battle, demos go back to the menu without touching the saves.
+    pub fn after_battle(&self) -> GameState {
+        match self {
+            GameMode::Demo => GameState::Select,
+            _ => GameState::GameOver,
+        }
+    }
+
+    /// Towers are unlocked along the campaign, and all available in other modes.
+    pub fn tower_unlocked(&self, tower: Towers, level: u8) -> bool {
+        match self {
+            GameMode::Campaign => level >= tower.unlocked_at(),
+            _ => true,
+        }
+    }
+}
+
+/// Seed of the next endless game
+#[derive(Resource)]
+struct EndlessSeed(u64);
+
+/// Level whose path is used in the sandbox, 0 for a blank grid
+#[derive(Resource)]
+struct SandboxPath(u8);
+
+/// Number of campaign levels, each with a fixed path
+pub const LEVELS: u8 = 6;
+
+pub fn sandbox_path_name(level: u8) -> String {
+    match level {
+        0 => "Straight road".to_string(),
+        n => format!("Path of level {}", n),
+    }
 }
 
 #[derive(Component)]
@@ -50,12 +144,98 @@ struct MainText;
 #[derive(Component)]
 struct LevelButton(pub u8);
 
+/// Stars of a level on the selected difficulty
+#[derive(Component)]
+struct LevelStars(u8);
+
+/// Text buttons of the menu around the level map
+#[derive(Component, Copy, Clone, PartialEq, EnumIter)]
+enum MenuButton {
+    Endless,
+    Sandbox,
+    Difficulty,
+    Editor,
+    Settings,
+}
+
+impl MenuButton {
+    fn get_text(&self, difficulty: Difficulty) -> String {
+        match self {
+            MenuButton::Endless => "Endless".to_string(),
+            MenuButton::Sandbox => "Sandbox".to_string(),
+            MenuButton::Difficulty => format!("Difficulty: {}", difficulty.get_name()),
+            MenuButton::Editor => "Editor".to_string(),
+            MenuButton::Settings => "Settings".to_string(),
+        }
+    }
+
+    /// Position in tiles
+    fn get_pos(&self) -> Vec2 {
+        let (width, height) = (util::size::WIDTH as f32, util::size::HEIGHT as f32);
+        match self {
+            MenuButton::Endless => vec2(2., 1.25),
+            MenuButton::Sandbox => vec2(2., height - 2.),
+            MenuButton::Difficulty => vec2(width / 2., height - 2.),
+            MenuButton::Editor => vec2(width - 2., height - 2.),
+            MenuButton::Settings => vec2(width - 2., 1.25),
+        }
+    }
+
+    fn get_anchor(&self) -> Anchor {
+        match self {
+            MenuButton::Endless | MenuButton::Sandbox => Anchor::CenterLeft,
+            MenuButton::Difficulty => Anchor::Center,
+            MenuButton::Editor | MenuButton::Settings => Anchor::CenterRight,
+        }
+    }
+
+    fn get_size(&self) -> Vec2 {
+        let width = match self {
+            MenuButton::Difficulty => 16.,
+            MenuButton::Editor => 4.5,
+            _ => 5.5,
+        };
+        vec2(f32_tile_to_f32(width), f32_tile_to_f32(1.25))
+    }
+
+    /// Bottom left corner of the hovered area, from the position of the text
+    fn get_bottom_left(&self, pos: Vec2) -> Vec2 {
+        let size = self.get_size();
+        let y = pos.y - size.y / 2. - f32_tile_to_f32(0.25);
+        match self {
+            MenuButton::Endless | MenuButton::Sandbox => vec2(pos.x, y),
+            MenuButton::Difficulty => pos - size / 2.,
+            MenuButton::Editor | MenuButton::Settings => vec2(pos.x - size.x, y),
+        }
+    }
+
+    fn get_color(&self, difficulty: Difficulty, hovered: bool) -> Palette {
+        match (self, hovered) {
+            (MenuButton::Difficulty, true) => Palette::A,
+            (MenuButton::Difficulty, false) => difficulty_color(difficulty),
+            (_, true) => Palette::B,
+            (_, false) => Palette::D,
+        }
+    }
+}
+
+/// Color of the difficulty on the level map
+fn difficulty_color(difficulty: Difficulty) -> Palette {
+    match difficulty {
+        Difficulty::Easy => Palette::G,
+        Difficulty::Normal => Palette::B,
+        Difficulty::Hard => Palette::I,
+        Difficulty::Nightmare => Palette::K,
+    }
+}
+
 fn setup(
     mut commands: Commands,
     mut bgm: EventWriter<PlayBgmEvent>,
     textures: Res<Textures>,
     fonts: Res<Fonts>,
     progress: Res<Progress>,
+    difficulty: Res<Difficulty>,
 ) {
     bgm.send(PlayBgmEvent(BGM::Title));
 
@@ -68,7 +248,7 @@ fn setup(
         vec2(5., 5.),
         vec2(14., 5.),
     ];
-    grid::draw_road(&mut commands, &textures, path);
+    grid::draw_road(&mut commands, &textures, &Network::single(path));
 
     commands
         .spawn(ttf_anchor(
@@ -93,6 +273,20 @@ fn setup(
         .insert(MainText)
         .insert(SelectUI);
 
+    for button in MenuButton::iter() {
+        let pos = button.get_pos();
+        commands
+            .spawn(ttf_anchor(
+                f32_tile_to_f32(pos.x), f32_tile_to_f32(pos.y),
+                util::z_pos::GUI_BG,
+                &button.get_text(*difficulty),
+                TextStyles::Heading, &fonts, button.get_color(*difficulty, false),
+                button.get_anchor(),
+            ))
+            .insert(button)
+            .insert(SelectUI);
+    }
+
     for (x, y, index, level) in [
         (8, 7, 422, 1),
         (9, 7, 423, 2),
@@ -149,6 +343,34 @@ fn setup(
                 .spawn(sprite)
                 .insert(SelectUI)
                 .insert(LevelButton(level));
+
+            commands
+                .spawn(ttf_anchor(
+                    tile_to_f32(x) + f32_tile_to_f32(0.5),
+                    tile_to_f32(y + util::size::GUI_HEIGHT),
+                    util::z_pos::GUI_BG,
+                    "",
+                    TextStyles::Body, &fonts, Palette::I,
+                    Anchor::TopCenter,
+                ))
+                .insert(LevelStars(level))
+                .insert(SelectUI);
+
+            // Markers of the difficulties the level was cleared on
+            let cleared = progress.cleared(level);
+            for (i, d) in Difficulty::iter().enumerate() {
+                if cleared & d.bit() == 0 { continue; }
+                let mut marker = sprite_f32(
+                    419,
+                    tile_to_f32(x) + (2 * i) as f32,
+                    tile_to_f32(y + 1 + util::size::GUI_HEIGHT),
+                    util::z_pos::GUI_BG,
+                    Palette::Transparent, difficulty_color(d),
+                    false, 0, textures.tileset.clone(),
+                );
+                marker.transform.scale.y = 0.5;
+                commands.spawn(marker).insert(SelectUI);
+            }
         } else {
             commands
                 .spawn(sprite)
@@ -161,32 +383,120 @@ fn update(
     mut commands: Commands,
     windows: Query<&Window>,
     buttons: Query<(&Transform, &LevelButton)>,
-    mut text: Query<&mut Text, With<MainText>>,
+    mut menu_buttons: Query<(&MenuButton, &Transform, &mut Text)>,
+    mut text: Query<&mut Text, (With<MainText>, Without<MenuButton>)>,
+    mut level_stars: Query<(&LevelStars, &mut Text), (Without<MainText>, Without<MenuButton>)>,
+    mut endless_seed: ResMut<EndlessSeed>,
+    mut sandbox_path: ResMut<SandboxPath>,
+    mut difficulty: ResMut<Difficulty>,
     mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    pkv: Res<PkvStore>,
     progress: Res<Progress>,
     transition: Option<Res<Transition>>,
 ) {
     if transition.is_some() { return; }
     let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
+    let clicked = mouse.just_pressed(MouseButton::Left);
 
     let mut text = text.single_mut();
     text.sections[0].value = "Select a level".to_string();
 
+    for (button, pos, mut button_text) in menu_buttons.iter_mut() {
+        let hovered = is_in(cursor_pos, button.get_bottom_left(pos.translation.xy()), button.get_size());
+        if hovered {
+            match button {
+                MenuButton::Endless => {
+                    // Arrow keys change the seed
+                    if keys.just_pressed(KeyCode::Left) && endless_seed.0 > 1 { endless_seed.0 -= 1; }
+                    if keys.just_pressed(KeyCode::Right) { endless_seed.0 += 1; }
+
+                    let seed = endless_seed.0;
+                    text.sections[0].value = match pkv.get::<u32>(&GameMode::best_wave_key(seed, *difficulty)) {
+                        Ok(best) => format!("< Seed {} > Best: wave {}", seed, best),
+                        Err(_) => format!("< Seed {} >", seed),
+                    };
+
+                    if clicked {
+                        // The level number is not used in endless mode
+                        commands.insert_resource(CurrentLevel(0));
+                        commands.insert_resource(GameMode::Endless { seed });
+                        commands.insert_resource(Transition::to(GameState::Battle));
+                        return;
+                    }
+                }
+                MenuButton::Sandbox => {
+                    // Arrow keys change the path
+                    if keys.just_pressed(KeyCode::Left) && sandbox_path.0 > 0 { sandbox_path.0 -= 1; }
+                    if keys.just_pressed(KeyCode::Right) && sandbox_path.0 < LEVELS { sandbox_path.0 += 1; }
+                    text.sections[0].value = format!("< {} >", sandbox_path_name(sandbox_path.0));
+
+                    if clicked {
+                        commands.insert_resource(CurrentLevel(sandbox_path.0));
+                        commands.insert_resource(GameMode::Sandbox);
+                        commands.insert_resource(Transition::to(GameState::Battle));
+                        return;
+                    }
+                }
+                MenuButton::Difficulty if clicked => *difficulty = difficulty.next(),
+                MenuButton::Editor if clicked => {
+                    commands.insert_resource(Transition::to(GameState::Editor));
+                    return;
+                }
+                MenuButton::Settings if clicked => {
+                    commands.insert_resource(Transition::to(GameState::Settings));
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        let value = button.get_text(*difficulty);
+        if button_text.sections[0].value != value { button_text.sections[0].value = value; }
+        button_text.sections[0].style.color = button.get_color(*difficulty, hovered).into();
+    }
+
+    for (stars, mut stars_text) in level_stars.iter_mut() {
+        let value = match progress.record(stars.0, *difficulty) {
+            Some(record) => "*".repeat(record.stars as usize),
+            None => String::new(),
+        };
+        if stars_text.sections[0].value != value { stars_text.sections[0].value = value; }
+    }
+
     for (pos, level) in &buttons {
         if !is_in(cursor_pos, pos.translation.xy(), vec2(tile_to_f32(1), tile_to_f32(1))) { continue; }
 
-        text.sections[0].value = match level.0 {
-            i => format!("Level {}", i),
+        let cleared: Vec<&str> = Difficulty::iter()
+            .filter(|d| progress.cleared(level.0) & d.bit() != 0)
+            .map(|d| d.get_name())
+            .collect();
+        let unlocked = level.0 <= progress.level_unlocked;
+        let required_stars = CurrentLevel(level.0).required_stars();
+        text.sections[0].value = match (progress.record(level.0, *difficulty), cleared.is_empty()) {
+            _ if !unlocked && progress.stars_total() < required_stars => format!("Level {}: {} stars needed", level.0, required_stars),
+            (Some(record), _) => format!("Level {}: best {} ({})", level.0, record.score, cleared.join(", ")),
+            (None, true) => format!("Level {}", level.0),
+            (None, false) => format!("Level {} ({})", level.0, cleared.join(", ")),
         };
 
-        let unlocked = level.0 <= progress.level_unlocked;
         if mouse.just_pressed(MouseButton::Left) && unlocked {
-            commands.insert_resource(CurrentLevel(level.0));
-            commands.insert_resource(Transition::to(GameState::Battle));
+            start_campaign(&mut commands, level.0);
         }
     }
 }
 
+/// Starts campaign level [level], or the level saved in its place with the editor.
+pub fn start_campaign(commands: &mut Commands, level: u8) {
+    match LevelData::replacing(level) {
+        Some(level) => commands.insert_resource(CustomLevel { level, first_wave: 0 }),
+        None => commands.remove_resource::<CustomLevel>(),
+    }
+    commands.insert_resource(CurrentLevel(level));
+    commands.insert_resource(GameMode::Campaign);
+    commands.insert_resource(Transition::to(GameState::Battle));
+}
+
 fn clean(
     mut commands: Commands,
     q1: Query<Entity, With<SelectUI>>,
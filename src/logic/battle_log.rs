@@ -0,0 +1,158 @@
+use bevy::prelude::{Entity, Resource};
+use serde::Serialize;
+
+use crate::drones::Drones;
+use crate::graphics::package::PackageKind;
+use crate::logic::event_log::GameEvent;
+use crate::tower::Towers;
+
+/// Where money comes from, or goes to.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum MoneyFlow {
+    Kills,
+    WaveBonus,
+    Interest,
+    EarlyCalls,
+    Packages,
+    Selling,
+    Building,
+    Upgrades,
+    Curses,
+    /// Set with the dev console
+    #[cfg(feature = "dev")]
+    Console,
+}
+
+impl MoneyFlow {
+    pub const fn get_name(&self) -> &'static str {
+        match self {
+            MoneyFlow::Kills => "kills",
+            MoneyFlow::WaveBonus => "wave bonuses",
+            MoneyFlow::Interest => "interest",
+            MoneyFlow::EarlyCalls => "early calls",
+            MoneyFlow::Packages => "packages",
+            MoneyFlow::Selling => "selling",
+            MoneyFlow::Building => "towers",
+            MoneyFlow::Upgrades => "upgrades",
+            MoneyFlow::Curses => "curses",
+            #[cfg(feature = "dev")]
+            MoneyFlow::Console => "console",
+        }
+    }
+}
+
+/// Damage dealt by a tower, and money invested in it.
+#[derive(Debug, Copy, Clone)]
+pub struct TowerLog {
+    pub entity: Entity,
+    pub model: Towers,
+    pub damage: f32,
+    pub invested: u16,
+}
+
+/// Statistics recorded from the [GameEvent]s of a battle, shown on the game over screen.
+#[derive(Resource, Default)]
+pub struct BattleLog {
+    /// Kills of each wave, starting from wave 1
+    pub kills_per_wave: Vec<u16>,
+    pub escaped: Vec<(Drones, u16)>,
+    pub earned: Vec<(MoneyFlow, u32)>,
+    pub spent: Vec<(MoneyFlow, u32)>,
+    pub towers: Vec<TowerLog>,
+    pub packages: Vec<(PackageKind, u16)>,
+}
+
+/// Adds [n] to the entry of [key].
+pub fn add<K: PartialEq, N: std::ops::AddAssign>(entries: &mut Vec<(K, N)>, key: K, n: N) {
+    match entries.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, total)) => *total += n,
+        None => entries.push((key, n)),
+    }
+}
+
+impl BattleLog {
+    pub fn record(&mut self, event: &GameEvent) {
+        match *event {
+            GameEvent::Hit { damage, tower: Some(tower), .. } => self.damage(tower, damage),
+            GameEvent::Killed { wave, .. } => self.kill(wave),
+            GameEvent::Escaped { drone, .. } => self.escaped(drone),
+            GameEvent::TowerPlaced { tower, model, .. } => self.tower_built(tower, model),
+            GameEvent::TowerUpgraded { tower, cost, .. } => self.tower_upgraded(tower, cost),
+            GameEvent::PackageCollected { kind } => self.package(kind),
+            GameEvent::Money { amount, flow } if amount < 0 => self.spent(flow, amount.unsigned_abs() as u16),
+            GameEvent::Money { amount, flow } => self.earned(flow, amount as u16),
+            _ => {}
+        }
+    }
+
+    /// Records a kill during [wave] (starting from 1).
+    pub fn kill(&mut self, wave: usize) {
+        let i = wave.max(1) - 1;
+        if self.kills_per_wave.len() <= i { self.kills_per_wave.resize(i + 1, 0); }
+        self.kills_per_wave[i] += 1;
+    }
+
+    pub fn escaped(&mut self, drone: Drones) { add(&mut self.escaped, drone, 1); }
+
+    pub fn earned(&mut self, source: MoneyFlow, amount: u16) { add(&mut self.earned, source, amount as u32); }
+
+    pub fn spent(&mut self, source: MoneyFlow, amount: u16) { add(&mut self.spent, source, amount as u32); }
+
+    pub fn package(&mut self, kind: PackageKind) { add(&mut self.packages, kind, 1); }
+
+    /// The money spent comes with its own [GameEvent::Money].
+    pub fn tower_built(&mut self, entity: Entity, model: Towers) {
+        self.towers.push(TowerLog { entity, model, damage: 0., invested: model.get_cost() });
+    }
+
+    pub fn tower_upgraded(&mut self, entity: Entity, cost: u16) {
+        if let Some(tower) = self.towers.iter_mut().find(|t| t.entity == entity) { tower.invested += cost; }
+    }
+
+    pub fn damage(&mut self, source: Entity, amount: f32) {
+        if let Some(tower) = self.towers.iter_mut().find(|t| t.entity == source) { tower.damage += amount; }
+    }
+
+    /// Total damage of each tower model
+    pub fn damage_per_model(&self) -> Vec<(Towers, f32)> {
+        let mut damage = vec![];
+        for tower in self.towers.iter() { add(&mut damage, tower.model, tower.damage); }
+        damage
+    }
+
+    /// Tower with the most damage per € invested
+    pub fn most_valuable_tower(&self) -> Option<TowerLog> {
+        self.towers.iter()
+            .filter(|t| t.damage > 0.)
+            .max_by(|a, b| (a.damage / a.invested as f32).total_cmp(&(b.damage / b.invested as f32)))
+            .copied()
+    }
+}
+
+#[test]
+fn test_battle_log() {
+    let mut log = BattleLog::default();
+    log.kill(1);
+    log.kill(3);
+    log.kill(3);
+    assert_eq!(log.kills_per_wave, vec![1, 0, 2]);
+
+    let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+    let hit = |tower: u32, damage: f32| GameEvent::Hit { id: Entity::from_raw(9), drone: Drones::Simple1, damage, hit: damage, tower: Some(Entity::from_raw(tower)) };
+    for event in [
+        GameEvent::TowerPlaced { tower: a, model: Towers::Lightning, x: 0, y: 0 },
+        GameEvent::Money { amount: -40, flow: MoneyFlow::Building },
+        GameEvent::TowerPlaced { tower: b, model: Towers::Lightning, x: 1, y: 0 },
+        GameEvent::Money { amount: -40, flow: MoneyFlow::Building },
+        GameEvent::TowerUpgraded { tower: b, rank: 2, cost: 80 },
+        GameEvent::Money { amount: -80, flow: MoneyFlow::Upgrades },
+        hit(1, 100.),
+        hit(2, 150.),
+        hit(3, 1000.),
+        GameEvent::Money { amount: 5, flow: MoneyFlow::Kills },
+    ] { log.record(&event); }
+    assert_eq!(log.damage_per_model(), vec![(Towers::Lightning, 250.)]);
+    assert_eq!(log.most_valuable_tower().map(|t| t.entity), Some(a));
+    assert_eq!(log.spent, vec![(MoneyFlow::Building, 80), (MoneyFlow::Upgrades, 80)]);
+    assert_eq!(log.earned, vec![(MoneyFlow::Kills, 5)]);
+}
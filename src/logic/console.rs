@@ -0,0 +1,145 @@
+use strum::IntoEnumIterator;
+
+use crate::drones::Drones;
+
+/// Words the console can complete, in the order they are suggested
+const WORDS: [&str; 15] = ["money", "spawn", "wave", "skip", "level", "unlock", "all", "god", "tower", "upgrade", "timescale", "seed", "bot", "script", "help"];
+
+/// Number of commands kept in the history
+const HISTORY_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Money(u16),
+    Spawn(Drones, u16),
+    SkipWave,
+    Level(u8),
+    UnlockAll,
+    God,
+    UpgradeAll,
+    TimeScale(f32),
+    Seed,
+    Bot,
+    /// Lets the bot play the plan saved by the optimizer
+    BotScript,
+    Help,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |i: usize| -> Result<&str, String> {
+            words.get(i).copied().ok_or_else(|| format!("Missing argument for {}.", words[0]))
+        };
+        let Some(first) = words.first() else { return Err(String::new()); };
+        match (*first, words.get(1).copied()) {
+            ("money", _) => number(1)?.parse().map(Command::Money).map_err(|_| "Usage: money <amount>".to_string()),
+            ("spawn", Some(name)) => {
+                let drone = drone_of_name(name).ok_or_else(|| format!("Unknown drone {}.", name))?;
+                let count = match words.get(2) {
+                    Some(n) => n.parse().map_err(|_| "Usage: spawn <drone> [count]".to_string())?,
+                    None => 1,
+                };
+                Ok(Command::Spawn(drone, count))
+            }
+            ("wave", Some("skip")) => Ok(Command::SkipWave),
+            ("level", _) => number(1)?.parse().map(Command::Level).map_err(|_| "Usage: level <n>".to_string()),
+            ("unlock", Some("all")) => Ok(Command::UnlockAll),
+            ("god", None) => Ok(Command::God),
+            ("tower", Some("upgrade")) if words.get(2) == Some(&"all") => Ok(Command::UpgradeAll),
+            ("timescale", _) => match number(1)?.parse::<f32>() {
+                Ok(scale) if scale > 0. => Ok(Command::TimeScale(scale)),
+                _ => Err("Usage: timescale <positive factor>".to_string()),
+            },
+            ("seed", None) => Ok(Command::Seed),
+            ("bot", None) => Ok(Command::Bot),
+            ("bot", Some("script")) => Ok(Command::BotScript),
+            ("help", None) => Ok(Command::Help),
+            _ => Err(format!("Unknown command: {}", line.trim())),
+        }
+    }
+}
+
+pub const HELP: &str = "money N, spawn DRONE [N], wave skip, level N, unlock all, god, tower upgrade all, timescale X, seed, bot, bot script";
+
+fn drone_of_name(name: &str) -> Option<Drones> {
+    Drones::iter().find(|d| format!("{:?}", d).eq_ignore_ascii_case(name))
+}
+
+/// Completes the last word of [line] with a command word or a drone name.
+pub fn complete(line: &str) -> Option<String> {
+    let (head, last) = match line.rfind(' ') {
+        Some(i) => line.split_at(i + 1),
+        None => ("", line),
+    };
+    if last.is_empty() { return None; }
+    let drones: Vec<String> = Drones::iter().map(|d| format!("{:?}", d)).collect();
+    let mut candidates = WORDS.iter().map(|w| w.to_string()).chain(drones);
+    let word = candidates.find(|w| w.to_lowercase().starts_with(&last.to_lowercase()))?;
+    Some(format!("{}{}", head, word))
+}
+
+/// Commands typed in the console, browsed with the arrow keys.
+#[derive(Debug, Default)]
+pub struct History {
+    lines: Vec<String>,
+    /// Position while browsing, equal to the length when not browsing
+    cursor: usize,
+}
+
+impl History {
+    pub fn push(&mut self, line: &str) {
+        if self.lines.last().map(|l| l.as_str()) != Some(line) {
+            self.lines.push(line.to_string());
+            if self.lines.len() > HISTORY_SIZE { self.lines.remove(0); }
+        }
+        self.cursor = self.lines.len();
+    }
+
+    pub fn older(&mut self) -> Option<&str> {
+        if self.cursor == 0 { return None; }
+        self.cursor -= 1;
+        self.lines.get(self.cursor).map(|l| l.as_str())
+    }
+
+    /// Returns an empty line past the most recent command.
+    pub fn newer(&mut self) -> Option<&str> {
+        if self.cursor >= self.lines.len() { return None; }
+        self.cursor += 1;
+        Some(self.lines.get(self.cursor).map_or("", |l| l.as_str()))
+    }
+}
+
+#[test]
+fn test_parse_command() {
+    assert_eq!(Command::parse("money 500"), Ok(Command::Money(500)));
+    assert_eq!(Command::parse("spawn Big2 3"), Ok(Command::Spawn(Drones::Big2, 3)));
+    assert_eq!(Command::parse("spawn simple1"), Ok(Command::Spawn(Drones::Simple1, 1)));
+    assert_eq!(Command::parse(" wave  skip "), Ok(Command::SkipWave));
+    assert_eq!(Command::parse("tower upgrade all"), Ok(Command::UpgradeAll));
+    assert_eq!(Command::parse("timescale 4"), Ok(Command::TimeScale(4.)));
+    assert!(Command::parse("timescale 0").is_err());
+    assert!(Command::parse("spawn Big7").is_err());
+    assert!(Command::parse("money").is_err());
+    assert_eq!(Command::parse("bot"), Ok(Command::Bot));
+    assert_eq!(Command::parse("bot script"), Ok(Command::BotScript));
+    assert!(Command::parse("fly").is_err());
+}
+
+#[test]
+fn test_complete_and_history() {
+    assert_eq!(complete("tim"), Some("timescale".to_string()));
+    assert_eq!(complete("spawn Me"), Some("spawn Medium1".to_string()));
+    assert_eq!(complete("tower up"), Some("tower upgrade".to_string()));
+    assert_eq!(complete("spawn "), None);
+
+    let mut history = History::default();
+    history.push("god");
+    history.push("seed");
+    assert_eq!(history.older(), Some("seed"));
+    assert_eq!(history.older(), Some("god"));
+    assert_eq!(history.older(), None);
+    assert_eq!(history.newer(), Some("seed"));
+    assert_eq!(history.newer(), Some(""));
+    assert_eq!(history.newer(), None);
+}
@@ -0,0 +1,90 @@
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+#[derive(Resource, Debug, Copy, Clone, Default, PartialEq, Eq, Hash, EnumIter, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Nightmare,
+}
+
+impl Difficulty {
+    pub const fn get_name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+            Difficulty::Nightmare => "Nightmare",
+        }
+    }
+
+    /// Difficulty of the given name, whatever the case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Difficulty::iter().find(|d| d.get_name().eq_ignore_ascii_case(name))
+    }
+
+    pub const fn next(&self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Nightmare,
+            Difficulty::Nightmare => Difficulty::Easy,
+        }
+    }
+
+    /// Bit of the difficulty in [crate::Progress] masks
+    pub const fn bit(&self) -> u8 { 1 << *self as u8 }
+
+    pub const fn hp_multiplier(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 1.3,
+            Difficulty::Nightmare => 1.7,
+        }
+    }
+
+    pub const fn speed_multiplier(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.9,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 1.1,
+            Difficulty::Nightmare => 1.25,
+        }
+    }
+
+    pub const fn money_multiplier(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.25,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 0.85,
+            Difficulty::Nightmare => 0.7,
+        }
+    }
+
+    /// Scales the starting lives of a level, keeping at least one.
+    pub fn scale_lives(&self, lives: u16) -> u16 {
+        let multiplier = match self {
+            Difficulty::Easy => 1.5,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 0.75,
+            Difficulty::Nightmare => 0.5,
+        };
+        ((lives as f32 * multiplier).round() as u16).max(1)
+    }
+}
+
+#[test]
+fn test_difficulty_bits() {
+    use strum::IntoEnumIterator;
+    let mask = Difficulty::iter().fold(0, |mask, d| {
+        assert_eq!(mask & d.bit(), 0);
+        mask | d.bit()
+    });
+    assert_eq!(mask, 0b1111);
+    assert_eq!(Difficulty::Nightmare.next(), Difficulty::Easy);
+}
@@ -0,0 +1,36 @@
+use std::collections::VecDeque;
+
+/// Duration over which the damage per second is averaged, in seconds
+pub const DPS_WINDOW: f32 = 5.;
+
+/// Damage per second of a tower, averaged over the last [DPS_WINDOW] seconds.
+#[derive(Debug, Clone, Default)]
+pub struct DpsMeter {
+    /// Time and amount of the recent hits
+    hits: VecDeque<(f32, f32)>,
+}
+
+impl DpsMeter {
+    pub fn hit(&mut self, t: f32, amount: f32) {
+        self.hits.push_back((t, amount));
+    }
+
+    /// Forgets the hits older than [DPS_WINDOW] and returns the damage per second at [t].
+    pub fn dps(&mut self, t: f32) -> f32 {
+        while self.hits.front().map_or(false, |(t0, _)| t - t0 > DPS_WINDOW) {
+            self.hits.pop_front();
+        }
+        self.hits.iter().map(|(_, amount)| amount).sum::<f32>() / DPS_WINDOW
+    }
+}
+
+#[test]
+fn test_dps_meter() {
+    let mut meter = DpsMeter::default();
+    assert_eq!(meter.dps(0.), 0.);
+    meter.hit(1., 10.);
+    meter.hit(2., 15.);
+    assert_eq!(meter.dps(2.), 5.);
+    assert_eq!(meter.dps(6.5), 3.);
+    assert_eq!(meter.dps(8.), 0.);
+}
@@ -0,0 +1,82 @@
+use bevy::prelude::Resource;
+
+use crate::drones::Drones;
+use crate::logic::difficulty::Difficulty;
+
+/// Money rewards of a battle, depending on the level and the difficulty.
+#[derive(Resource, Debug, Clone)]
+pub struct Economy {
+    /// Bounty of Simple, Medium, Big and Invader drones
+    bounties: [u16; 4],
+    /// Paid when a wave is cleared without any drone escaping
+    pub wave_bonus: u16,
+    /// Share of the banked money paid at the start of each wave
+    pub interest_rate: f32,
+    /// Maximum interest paid at once
+    pub max_interest: u16,
+    /// Paid per second skipped when calling a wave early
+    pub early_call_bonus: f32,
+}
+
+impl Economy {
+    /// Rates of a campaign level, level 0 being endless mode.
+    pub fn new(level: u8, difficulty: Difficulty) -> Self {
+        let (bounties, wave_bonus) = match level {
+            1 => ([3, 6, 15, 40], 15),
+            2 | 3 => ([2, 5, 12, 35], 20),
+            4 | 5 => ([2, 4, 10, 30], 25),
+            6 => ([1, 3, 8, 25], 30),
+            _ => ([2, 4, 10, 30], 20),
+        };
+        let scale = |n: u16| ((n as f32 * difficulty.money_multiplier()).round() as u16).max(1);
+        Economy {
+            bounties: bounties.map(scale),
+            wave_bonus: scale(wave_bonus),
+            interest_rate: match difficulty {
+                Difficulty::Easy => 0.03,
+                Difficulty::Normal | Difficulty::Hard => 0.02,
+                Difficulty::Nightmare => 0.01,
+            },
+            max_interest: match level {
+                0 => 50,
+                _ => 25,
+            },
+            early_call_bonus: 0.5 * difficulty.money_multiplier(),
+        }
+    }
+
+    pub const fn bounty(&self, drone: Drones) -> u16 {
+        match drone {
+            Drones::Simple1 | Drones::Simple2 | Drones::Simple3 => self.bounties[0],
+            Drones::Medium1 | Drones::Medium2 | Drones::Medium3 | Drones::Medium4 => self.bounties[1],
+            Drones::Big1 | Drones::Big2 => self.bounties[2],
+            Drones::Invader => self.bounties[3],
+        }
+    }
+
+    /// Interest on [money], rounded down.
+    pub fn interest(&self, money: u16) -> u16 {
+        ((money as f32 * self.interest_rate) as u16).min(self.max_interest)
+    }
+
+    /// Bonus for calling a wave [skipped] seconds early, rounded down.
+    pub fn early_call(&self, skipped: f32) -> u16 {
+        (skipped.max(0.) * self.early_call_bonus) as u16
+    }
+}
+
+#[test]
+fn test_economy() {
+    let normal = Economy::new(1, Difficulty::Normal);
+    let nightmare = Economy::new(1, Difficulty::Nightmare);
+    assert!(normal.bounty(Drones::Invader) > normal.bounty(Drones::Simple1));
+    assert!(nightmare.bounty(Drones::Big1) < normal.bounty(Drones::Big1));
+    assert!(nightmare.bounty(Drones::Simple1) >= 1);
+
+    assert_eq!(normal.interest(0), 0);
+    assert_eq!(normal.interest(500), 10);
+    assert_eq!(normal.interest(60000), normal.max_interest);
+
+    assert_eq!(normal.early_call(0.), 0);
+    assert_eq!(normal.early_call(30.), 15);
+}
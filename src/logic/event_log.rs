@@ -0,0 +1,119 @@
+use bevy::prelude::Entity;
+use serde::{Serialize, Serializer};
+
+use crate::drones::Drones;
+use crate::graphics::package::PackageKind;
+use crate::logic::battle_log::MoneyFlow;
+use crate::tower::Towers;
+
+/// File of the event log, in the data directory
+pub const EVENT_LOG_FILE: &str = "events.jsonl";
+
+/// Sent by the battle systems for each gameplay event, and read by the [crate::logic::battle_log::BattleLog],
+/// the heatmap, the damage numbers, the sandbox and the event log.
+/// Drones and towers are logged with the index of their entity.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GameEvent {
+    WaveStarted { wave: usize },
+    DroneSpawned {
+        #[serde(serialize_with = "index")]
+        id: Entity,
+        drone: Drones,
+        route: usize,
+    },
+    ShotFired {
+        #[serde(serialize_with = "index")]
+        tower: Entity,
+        model: Towers,
+    },
+    /// [damage] is the damage dealt, [hit] the damage of the shot, even on invincible drones
+    Hit {
+        #[serde(serialize_with = "index")]
+        id: Entity,
+        drone: Drones,
+        damage: f32,
+        hit: f32,
+        #[serde(serialize_with = "maybe_index")]
+        tower: Option<Entity>,
+    },
+    Killed {
+        #[serde(serialize_with = "index")]
+        id: Entity,
+        drone: Drones,
+        wave: usize,
+        route: usize,
+        advance: f32,
+    },
+    Escaped {
+        #[serde(serialize_with = "index")]
+        id: Entity,
+        drone: Drones,
+    },
+    TowerPlaced {
+        #[serde(serialize_with = "index")]
+        tower: Entity,
+        model: Towers,
+        x: usize,
+        y: usize,
+    },
+    TowerUpgraded {
+        #[serde(serialize_with = "index")]
+        tower: Entity,
+        rank: u8,
+        cost: u16,
+    },
+    TowerSold {
+        #[serde(serialize_with = "index")]
+        tower: Entity,
+    },
+    PackageDropped { kind: PackageKind },
+    PackageCollected { kind: PackageKind },
+    /// Money earned (positive) or spent (negative)
+    Money { amount: i32, flow: MoneyFlow },
+}
+
+fn index<S: Serializer>(entity: &Entity, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u32(entity.index())
+}
+
+fn maybe_index<S: Serializer>(entity: &Option<Entity>, serializer: S) -> Result<S::Ok, S::Error> {
+    match entity {
+        Some(entity) => serializer.serialize_some(&entity.index()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Serialize)]
+struct LogLine<'a> {
+    run: u64,
+    /// Frame of the battle
+    tick: u64,
+    #[serde(flatten)]
+    event: &'a GameEvent,
+}
+
+/// Serializes [event] as a line of the event log.
+pub fn to_json_line(run: u64, tick: u64, event: &GameEvent) -> String {
+    serde_json::to_string(&LogLine { run, tick, event }).unwrap_or_default()
+}
+
+#[test]
+fn test_json_line() {
+    assert_eq!(
+        to_json_line(7, 42, &GameEvent::WaveStarted { wave: 3 }),
+        r#"{"run":7,"tick":42,"event":"wave_started","wave":3}"#,
+    );
+    assert_eq!(
+        to_json_line(7, 43, &GameEvent::Hit { id: Entity::from_raw(12), drone: Drones::Big2, damage: 1.5, hit: 2., tower: None }),
+        r#"{"run":7,"tick":43,"event":"hit","id":12,"drone":"Big2","damage":1.5,"hit":2.0,"tower":null}"#,
+    );
+    assert_eq!(
+        to_json_line(7, 44, &GameEvent::TowerSold { tower: Entity::from_raw(3) }),
+        r#"{"run":7,"tick":44,"event":"tower_sold","tower":3}"#,
+    );
+    assert_eq!(
+        to_json_line(1, 0, &GameEvent::Money { amount: -50, flow: MoneyFlow::Building }),
+        r#"{"run":1,"tick":0,"event":"money","amount":-50,"flow":"Building"}"#,
+    );
+}
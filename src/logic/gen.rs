@@ -1,64 +1,381 @@
 use std::collections::HashSet;
 
 use bevy::math::{vec2, Vec2};
-use rand::{RngCore, thread_rng};
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
 
-use crate::util;
+use crate::drones::Drones;
+use crate::util::size::{GRID_HEIGHT, is_oob, WIDTH};
 
-pub fn gen_path() -> Vec<Vec2> {
-    let mut current_x: u8 = 0;
-    let mut current_y: u8 = (thread_rng().next_u32() % 5 + 3) as u8;
+type Cell = (isize, isize);
 
-    let mut covered: HashSet<(u8, u8)> = HashSet::new();
-    covered.insert((current_x, current_y));
+/// Size of the grid, in cells
+pub const COLUMNS: isize = (WIDTH / 2) as isize;
+pub const ROWS: isize = (GRID_HEIGHT / 2) as isize;
 
-    let mut path: Vec<Vec2> = Vec::new();
-    path.push(vec2(current_x as f32, current_y as f32));
+/// Number of valid candidates compared by [gen_path]
+const CANDIDATES: usize = 24;
+/// Maximum number of walks tried by [gen_path]
+const MAX_WALKS: usize = 2000;
+/// Distance (in cells) under which a tower spot is considered to cover a road cell
+const COVERAGE_RADIUS: isize = 3;
+
+/// Edge of the grid where a path starts or ends.
+/// Below the grid is the GUI, so paths never go through the bottom edge.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+}
+
+/// Constraints of a generated path.
+#[derive(Debug, Clone)]
+pub struct GenParams {
+    /// Length of the path in cells
+    pub min_length: f32,
+    pub max_length: f32,
+    /// Minimum number of turns
+    pub turns: usize,
+    /// Minimum number of U-turns around a pocket of buildable cells
+    pub loops: usize,
+    pub entry: Edge,
+    pub exit: Edge,
+}
+
+impl Default for GenParams {
+    fn default() -> Self {
+        GenParams {
+            min_length: 25.,
+            max_length: 60.,
+            turns: 4,
+            loops: 0,
+            entry: Edge::Left,
+            exit: Edge::Right,
+        }
+    }
+}
+
+impl GenParams {
+    /// Looser constraints, to retry when no path satisfies these ones.
+    pub fn relaxed(&self) -> Self {
+        GenParams {
+            min_length: (self.min_length - 2.).max(0.),
+            max_length: self.max_length + 5.,
+            turns: self.turns.saturating_sub(1),
+            loops: self.loops.saturating_sub(1),
+            ..self.clone()
+        }
+    }
+
+    /// Later levels get shorter paths, with less room to build towers.
+    pub fn for_level(level: u8) -> Self {
+        let harder = level.saturating_sub(7) as f32;
+        GenParams {
+            min_length: (40. - 2. * harder).max(22.),
+            max_length: (70. - 3. * harder).max(32.),
+            turns: 6usize.saturating_sub(level as usize / 4).max(2),
+            loops: if level < 12 { 1 } else { 0 },
+            entry: Edge::Left,
+            exit: if level % 3 == 0 { Edge::Top } else { Edge::Right },
+        }
+    }
+}
+
+/// Generates the best path among several random walks satisfying [params].
+/// Candidates are scored on the tower spots they leave around the road.
+/// Returns [None] if no walk satisfies [params].
+pub fn gen_path(params: &GenParams, rng: &mut impl Rng) -> Option<Vec<Vec2>> {
+    let mut best: Option<(f32, Vec<Vec2>)> = None;
+    let mut valid = 0;
+
+    for _ in 0..MAX_WALKS {
+        let Some((path, _)) = walk(start_cell(params.entry, rng), params.exit, &HashSet::new(), rng) else { continue; };
+        if !satisfies(&path, params) { continue; }
+
+        let score = score(&path);
+        if best.as_ref().map_or(true, |(s, _)| score > *s) { best = Some((score, path)); }
+
+        valid += 1;
+        if valid >= CANDIDATES { break; }
+    }
+
+    best.map(|(_, path)| path)
+}
+
+/// Generates a path with [gen_path], relaxing [params] until it succeeds.
+pub fn gen_relaxed_path(params: &GenParams, rng: &mut impl Rng) -> Vec<Vec2> {
+    let mut params = params.clone();
+    loop {
+        if let Some(path) = gen_path(&params, rng) { return path; }
+        params = params.relaxed();
+    }
+}
+
+/// Generates two lanes: the second one merges into the first one.
+pub fn gen_two_lanes(params: &GenParams, rng: &mut impl Rng) -> Vec<Vec<Vec2>> {
+    loop {
+        let first = gen_relaxed_path(params, rng);
+        let cells = cells_of(&first);
+        let covered: HashSet<Cell> = cells.iter().map(|(c, _)| *c).collect();
+
+        // Start from another cell of the entry edge, away from the first lane
+        let start = start_cell(params.entry, rng);
+        let first_start = (first[0].x as isize, first[0].y as isize);
+        if start.0.abs_diff(first_start.0) + start.1.abs_diff(first_start.1) < 3 { continue; }
+
+        let Some((mut second, Some(merge))) = walk(start, params.exit, &covered, rng) else { continue; };
+        if merge.0.abs_diff(start.0) + merge.1.abs_diff(start.1) < 3 { continue; }
+
+        // Follow the first lane after the merge point
+        let Some((_, next)) = cells.iter().find(|(c, _)| *c == merge) else { continue; };
+        let last = second[second.len() - 1];
+        second.extend(first[*next..].iter().filter(|p| **p != last));
+        return vec![first, second];
+    }
+}
+
+/// Generates the drones of a wave.
+/// Difficulty 0 is a single simple drone, and each point of difficulty adds roughly 25% of hp.
+pub fn gen_wave(difficulty: f32, rng: &mut impl Rng) -> Vec<(f32, Drones)> {
+    const CLASSES: [Drones; 10] = [
+        Drones::Simple1, Drones::Simple2, Drones::Simple3,
+        Drones::Medium1, Drones::Medium2, Drones::Medium3, Drones::Medium4,
+        Drones::Big1, Drones::Big2,
+        Drones::Invader,
+    ];
+    let d = difficulty.max(0.);
+    let weight = |drone: &Drones| match drone {
+        Drones::Simple1 | Drones::Simple2 | Drones::Simple3 => 10. / (1. + d / 4.),
+        Drones::Medium1 | Drones::Medium2 | Drones::Medium3 | Drones::Medium4 => (d - 2.).clamp(0., 8.) / 2.,
+        Drones::Big1 | Drones::Big2 => (d - 8.).clamp(0., 12.) / 2.,
+        Drones::Invader => (d - 16.).clamp(0., 10.) / 4.,
+    };
+
+    let mut budget = Drones::Simple1.get_default_stats().max_hp * 1.25f32.powf(d);
+    let mut wave = vec![];
+    let mut t = 0.;
+    loop {
+        // Only pick drones which fit in the remaining budget
+        let weights: Vec<f32> = CLASSES.iter()
+            .map(|c| if c.get_default_stats().max_hp <= budget { weight(c) } else { 0. })
+            .collect();
+        let Ok(dist) = WeightedIndex::new(&weights) else { break; };
+        let drone = CLASSES[dist.sample(rng)];
+
+        wave.push((t, drone));
+        budget -= drone.get_default_stats().max_hp;
+        t += rng.gen_range(0.8..1.2) * match drone {
+            Drones::Simple1 | Drones::Simple2 | Drones::Simple3 => 2.5,
+            Drones::Medium1 | Drones::Medium2 | Drones::Medium3 | Drones::Medium4 => 5.,
+            Drones::Big1 | Drones::Big2 => 15.,
+            Drones::Invader => 100.,
+        };
+    }
+    wave
+}
+
+/// Returns the cells where towers can be built around [road].
+pub fn buildable_cells(road: &HashSet<Cell>) -> HashSet<Cell> {
+    let mut buildable = HashSet::new();
+    for (x, y) in road.iter() {
+        for (dx, dy) in [
+            (0, -2),
+            (-1, -1), (0, -1), (1, -1),
+            (-2, 0), (-1, 0), (1, 0), (2, 0),
+            (-1, 1), (0, 1), (1, 1),
+            (0, 2),
+        ] {
+            let cell = (x + dx, y + dy);
+            if is_oob(cell.0, cell.1) || road.contains(&cell) { continue }
+            buildable.insert(cell);
+        }
+    }
+    buildable
+}
+
+/// Sum over tower spots of the number of road cells they cover.
+fn score(path: &Vec<Vec2>) -> f32 {
+    let road: HashSet<Cell> = cells_of(path).into_iter().map(|(c, _)| c).collect();
+    buildable_cells(&road).iter()
+        .map(|(x, y)| road.iter()
+            .filter(|(rx, ry)| (rx - x).pow(2) + (ry - y).pow(2) <= COVERAGE_RADIUS.pow(2))
+            .count())
+        .sum::<usize>() as f32
+}
+
+fn satisfies(path: &Vec<Vec2>, params: &GenParams) -> bool {
+    let length: f32 = path.windows(2).map(|w| w[0].distance(w[1])).sum();
+    let directions: Vec<Vec2> = path.windows(2).map(|w| (w[1] - w[0]).normalize()).collect();
+    // Consecutive moves in the same direction are already merged by [walk]
+    let turns = directions.len().saturating_sub(1);
+
+    length >= params.min_length && length <= params.max_length
+        && turns >= params.turns && pockets(path) >= params.loops
+}
+
+/// Number of U-turns of [path] around a pocket of free cells.
+fn pockets(path: &Vec<Vec2>) -> usize {
+    let road: HashSet<Cell> = cells_of(path).into_iter().map(|(c, _)| c).collect();
+    path.windows(4)
+        .filter(|p| {
+            let (leg, middle, back) = (p[1] - p[0], p[2] - p[1], p[3] - p[2]);
+            // The legs of the U must be at least one cell apart
+            if leg.normalize() != -back.normalize() || middle.length() < 2. { return false; }
+            let depth = leg.length().min(back.length()) as isize;
+            (1..=depth).any(|k| (1..middle.length() as isize).any(|m| {
+                let cell = p[1] - leg.normalize() * k as f32 + middle.normalize() * m as f32;
+                let cell = (cell.x as isize, cell.y as isize);
+                cell.0 >= 0 && cell.0 < COLUMNS && cell.1 >= 0 && cell.1 < ROWS && !road.contains(&cell)
+            }))
+        })
+        .count()
+}
+
+fn start_cell(entry: Edge, rng: &mut impl Rng) -> Cell {
+    match entry {
+        Edge::Left => (0, rng.gen_range(1..ROWS - 1)),
+        Edge::Right => (COLUMNS - 1, rng.gen_range(1..ROWS - 1)),
+        Edge::Top => (rng.gen_range(1..COLUMNS - 1), ROWS - 1),
+    }
+}
+
+/// Returns each cell of [path] with the index of the next point.
+fn cells_of(path: &Vec<Vec2>) -> Vec<(Cell, usize)> {
+    let mut cells = vec![];
+    for i in 0..path.len() - 1 {
+        let (p1, p2) = (path[i], path[i + 1]);
+        let steps = p1.distance(p2) as usize;
+        for step in 0..steps {
+            let p = p1 + (p2 - p1) * step as f32 / steps as f32;
+            cells.push(((p.x as isize, p.y as isize), i + 1));
+        }
+    }
+    if let Some(last) = path.last() {
+        cells.push(((last.x as isize, last.y as isize), path.len()));
+    }
+    cells
+}
+
+/// Random walk from [start] until it leaves the grid through the [exit] edge, ending one cell past it.
+/// The walk stops on the first cell of [merge_into] it reaches, and returns this cell.
+/// Returns [None] if the walk gets stuck.
+fn walk(start: Cell, exit: Edge, merge_into: &HashSet<Cell>, rng: &mut impl Rng) -> Option<(Vec<Vec2>, Option<Cell>)> {
+    // The walk stays one cell away from the edges, except through the exit
+    let (min_x, max_x) = (if exit == Edge::Left { isize::MIN } else { 1 }, if exit == Edge::Right { isize::MAX } else { COLUMNS - 2 });
+    let (min_y, max_y) = (1, if exit == Edge::Top { isize::MAX } else { ROWS - 2 });
+    let exited = |(x, y): Cell| match exit {
+        Edge::Left => x < 0,
+        Edge::Right => x >= COLUMNS,
+        Edge::Top => y >= ROWS,
+    };
+
+    let mut current = start;
+    let mut covered: HashSet<Cell> = HashSet::new();
+    covered.insert(current);
+
+    let mut path: Vec<Vec2> = vec![vec2(current.0 as f32, current.1 as f32)];
+    let mut last_direction: Option<Cell> = None;
 
     let mut tries = 0;
-    while current_x < 20 {
+    while !exited(current) {
         tries += 1;
-        if tries > 1000 { return gen_path(); }
+        if tries > 1000 { return None; }
 
-        let amount = (thread_rng().next_u32() % 3 + 1) as u8;
-        match thread_rng().next_u32() % 4 {
-            0 => {
-                // Move right
-                let valid = ((current_x + 1)..=(current_x + amount)).all(|x| !covered.contains(&(x, current_y)));
-                if !valid { continue; }
+        let amount = rng.gen_range(1..=3);
+        let direction = [(1, 0), (0, -1), (-1, 0), (0, 1)][rng.gen_range(0..4)];
+        let cells: Vec<Cell> = (1..=amount)
+            .map(|i| (current.0 + direction.0 * i, current.1 + direction.1 * i))
+            .collect();
 
-                ((current_x + 1)..=(current_x + amount)).for_each(|x| { covered.insert((x, current_y)); });
-                current_x += amount;
-            }
-            1 => {
-                // Move down
-                let valid = current_y > amount
-                    && ((current_y - amount)..(current_y)).all(|y| !covered.contains(&(current_x, y)));
-                if !valid { continue; }
-
-                ((current_y - amount)..(current_y)).for_each(|y| { covered.insert((current_x, y)); });
-                current_y -= amount;
-            }
-            2 => {
-                // Move left
-                let valid = current_x > amount
-                    && ((current_x - amount)..(current_x)).all(|x| !covered.contains(&(x, current_y)));
-                if !valid { continue; }
-
-                ((current_x - amount)..(current_x)).for_each(|x| { covered.insert((x, current_y)); });
-                current_x -= amount;
-            }
-            _ => {
-                // Move up
-                let valid = current_y + amount + 1 < (util::size::HEIGHT - util::size::GUI_HEIGHT) as u8 / 2
-                    && ((current_y + 1)..=(current_y + amount)).all(|y| !covered.contains(&(current_x, y)));
-                if !valid { continue; }
-
-                ((current_y + 1)..=(current_y + amount)).for_each(|y| { covered.insert((current_x, y)); });
-                current_y += amount;
+        let in_bounds = |(x, y): &Cell| *x >= min_x && *x <= max_x && *y >= min_y && *y <= max_y;
+        if !cells.iter().all(|c| in_bounds(c) || exited(*c)) { continue; }
+
+        // Stop on the first cell of another road, or out of the grid
+        let merge = cells.iter().position(|c| merge_into.contains(c));
+        let cells = match merge.or_else(|| cells.iter().position(|c| exited(*c))) {
+            Some(i) => &cells[..=i],
+            None => &cells[..],
+        };
+
+        let valid = cells.iter().all(|c| !covered.contains(c));
+        if !valid { continue; }
+
+        cells.iter().for_each(|c| { covered.insert(*c); });
+        current = *cells.last().unwrap();
+
+        // Extend the last segment when going straight
+        let point = vec2(current.0 as f32, current.1 as f32);
+        if last_direction == Some(direction) && path.len() > 1 {
+            *path.last_mut().unwrap() = point;
+        } else {
+            path.push(point);
+        }
+        last_direction = Some(direction);
+
+        if merge.is_some() { return Some((path, Some(current))); }
+    }
+    Some((path, None))
+}
+
+#[test]
+fn test_gen_path() {
+    let mut rng = StdRng::seed_from_u64(30);
+    for level in 7..20 {
+        let params = GenParams::for_level(level);
+        let path = gen_path(&params, &mut rng).expect("no path generated");
+        assert!(satisfies(&path, &params));
+        for i in 0..path.len() - 1 {
+            assert!(path[i].x == path[i + 1].x || path[i].y == path[i + 1].y);
+        }
+        // Paths end one cell past the exit, away from the GUI
+        let end = path[path.len() - 1];
+        assert!(end.y >= 0. && (end.x == COLUMNS as f32 || end.y == ROWS as f32));
+    }
+
+    let impossible = GenParams { min_length: 500., ..GenParams::default() };
+    assert_eq!(gen_path(&impossible, &mut rng), None);
+    assert!(!gen_relaxed_path(&GenParams { turns: 30, ..GenParams::default() }, &mut rng).is_empty());
+}
+
+#[test]
+fn test_pockets() {
+    // U-turn around the cells (3, 3) and (3, 4)
+    let u = vec![vec2(0., 2.), vec2(4., 2.), vec2(4., 5.), vec2(0., 5.)];
+    assert_eq!(pockets(&u), 1);
+    // Legs side by side leave no room for a tower
+    let tight = vec![vec2(0., 2.), vec2(4., 2.), vec2(4., 3.), vec2(0., 3.)];
+    assert_eq!(pockets(&tight), 0);
+    // The pocket is filled by another part of the road
+    let filled = vec![vec2(0., 2.), vec2(4., 2.), vec2(4., 4.), vec2(0., 4.), vec2(0., 3.), vec2(3., 3.)];
+    assert_eq!(pockets(&filled), 0);
+}
+
+#[test]
+fn test_gen_two_lanes() {
+    let mut rng = StdRng::seed_from_u64(30);
+    for _ in 0..20 {
+        let lanes = gen_two_lanes(&GenParams::default(), &mut rng);
+        assert_eq!(lanes.len(), 2);
+        assert_ne!(lanes[0][0], lanes[1][0]);
+        assert_eq!(lanes[0].last(), lanes[1].last());
+        for lane in lanes {
+            for i in 0..lane.len() - 1 {
+                assert!(lane[i].x == lane[i + 1].x || lane[i].y == lane[i + 1].y);
             }
         }
-        path.push(vec2(current_x as f32, current_y as f32));
     }
-    path
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_gen_wave() {
+    let mut rng = StdRng::seed_from_u64(30);
+    let hp = |wave: &Vec<(f32, Drones)>| wave.iter().map(|(_, d)| d.get_default_stats().max_hp).sum::<f32>();
+    assert_eq!(gen_wave(0., &mut rng).len(), 1);
+    for d in 0..30 {
+        let wave = gen_wave(d as f32, &mut rng);
+        assert!(!wave.is_empty());
+        assert!(hp(&wave) <= 25. * 1.25f32.powf(d as f32));
+        assert!(wave.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+}
@@ -0,0 +1,88 @@
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::logic::battle_log::add;
+
+/// File written when the heatmap is exported
+pub const HEATMAP_FILE: &str = "heatmap.csv";
+
+/// Length of the stretches of road deaths are counted on, in cells
+pub const ADVANCE_STEP: f32 = 1.;
+
+type Cell = (usize, usize);
+
+/// Where drones die along the road, and which cells the damage comes from.
+#[derive(Resource, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Heatmap {
+    /// Number of battles recorded
+    pub runs: u32,
+    /// Deaths per (route, stretch of [ADVANCE_STEP] cells)
+    pub deaths: Vec<((usize, usize), u32)>,
+    pub escapes: u32,
+    /// Damage dealt by the towers of each cell
+    pub damage: Vec<(Cell, f32)>,
+}
+
+impl Heatmap {
+    pub fn death(&mut self, route: usize, advance: f32) {
+        add(&mut self.deaths, (route, (advance.max(0.) / ADVANCE_STEP) as usize), 1);
+    }
+
+    pub fn escape(&mut self) { self.escapes += 1; }
+
+    pub fn damage(&mut self, cell: Cell, amount: f32) { add(&mut self.damage, cell, amount); }
+
+    /// Adds the records of [other] to this heatmap.
+    pub fn merge(&mut self, other: &Heatmap) {
+        self.runs += other.runs;
+        self.escapes += other.escapes;
+        for (key, n) in other.deaths.iter() { add(&mut self.deaths, *key, *n); }
+        for (cell, amount) in other.damage.iter() { add(&mut self.damage, *cell, *amount); }
+    }
+
+    pub fn max_damage(&self) -> f32 {
+        self.damage.iter().map(|(_, d)| *d).fold(0., f32::max)
+    }
+
+    /// One row per stretch of road and per cell, sorted.
+    pub fn to_csv(&self) -> String {
+        let mut deaths = self.deaths.clone();
+        deaths.sort_by_key(|(key, _)| *key);
+        let mut damage = self.damage.clone();
+        damage.sort_by_key(|(cell, _)| *cell);
+
+        let mut csv = "kind,route,advance,x,y,value\n".to_string();
+        for ((route, step), n) in deaths {
+            csv += &format!("death,{},{},,,{}\n", route, step as f32 * ADVANCE_STEP, n);
+        }
+        csv += &format!("escape,,,,,{}\n", self.escapes);
+        for ((x, y), amount) in damage {
+            csv += &format!("damage,,,{},{},{:.1}\n", x, y, amount);
+        }
+        csv
+    }
+}
+
+#[test]
+fn test_heatmap() {
+    let mut run = Heatmap { runs: 1, ..Heatmap::default() };
+    run.death(0, 3.4);
+    run.death(0, 3.9);
+    run.death(1, 0.5);
+    run.escape();
+    run.damage((2, 3), 10.);
+    run.damage((2, 3), 5.5);
+    assert_eq!(run.deaths, vec![((0, 3), 2), ((1, 0), 1)]);
+    assert_eq!(run.max_damage(), 15.5);
+
+    let mut total = Heatmap::default();
+    total.merge(&run);
+    total.merge(&run);
+    assert_eq!(total.runs, 2);
+    assert_eq!(total.deaths, vec![((0, 3), 4), ((1, 0), 2)]);
+    assert_eq!(total.to_csv(), "kind,route,advance,x,y,value\n\
+        death,0,3,,,4\n\
+        death,1,0,,,2\n\
+        escape,,,,,2\n\
+        damage,,,2,3,31.0\n");
+}
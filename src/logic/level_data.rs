@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use bevy::math::vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::logic::gen::{COLUMNS, ROWS};
+use crate::logic::path::Network;
+use crate::logic::waves::{Wave, waves_of_level};
+use crate::level_select::LEVELS;
+use crate::music::BGM;
+use crate::util::data_path;
+
+type Cell = (isize, isize);
+
+/// File written and read by the level editor
+pub const LEVEL_FILE: &str = "custom_level.ron";
+
+/// File of the level saved in [slot], in the data directory.
+/// Slot 0 is [LEVEL_FILE], the other slots replace the campaign level of the same number.
+pub fn level_file(slot: u8) -> String {
+    match slot {
+        0 => LEVEL_FILE.to_string(),
+        n => format!("level_{}.ron", n),
+    }
+}
+
+/// A level made in the editor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelData {
+    pub name: String,
+    /// Corners of the road in cells, from the spawn point to the exit
+    pub path: Vec<Cell>,
+    pub money: u16,
+    pub lives: u16,
+    pub bgm: BGM,
+    pub waves: Vec<Wave>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LevelError {
+    /// The road needs a spawn point and an exit
+    TooShort,
+    /// The segment ending at this point is not horizontal, vertical or diagonal
+    NotAligned(usize),
+    /// The point is outside of the grid
+    OutOfGrid(usize),
+    /// The road goes twice through this cell
+    Overlap(Cell),
+    /// The exit is not on the right or top edge of the grid
+    NoExit,
+    NoWaves,
+    /// This wave has no drone
+    EmptyWave(usize),
+}
+
+impl LevelError {
+    pub fn get_message(&self) -> String {
+        match self {
+            LevelError::TooShort => "The road needs a spawn and an exit.".to_string(),
+            LevelError::NotAligned(i) => format!("Segment {} is not straight or diagonal.", i),
+            LevelError::OutOfGrid(i) => format!("Point {} is out of the grid.", i + 1),
+            LevelError::Overlap((x, y)) => format!("The road crosses itself at ({}, {}).", x, y),
+            LevelError::NoExit => "The exit must be on the right or top edge.".to_string(),
+            LevelError::NoWaves => "The level has no wave.".to_string(),
+            LevelError::EmptyWave(i) => format!("Wave {} has no drone.", i + 1),
+        }
+    }
+}
+
+impl Default for LevelData {
+    fn default() -> Self {
+        LevelData {
+            name: "Custom level".to_string(),
+            path: vec![],
+            money: 200,
+            lives: 10,
+            bgm: BGM::Theme,
+            waves: waves_of_level(1),
+        }
+    }
+}
+
+impl LevelData {
+    /// Appends a corner to the road, merging it with the last segment when they are collinear.
+    pub fn add_point(&mut self, cell: Cell) -> Result<(), LevelError> {
+        if !in_grid(cell) { return Err(LevelError::OutOfGrid(self.path.len())); }
+        let Some(&last) = self.path.last() else {
+            self.path.push(cell);
+            return Ok(());
+        };
+        if last == cell { return Ok(()); }
+        let Some(step) = direction(last, cell) else { return Err(LevelError::NotAligned(self.path.len())); };
+        if self.path.len() >= 2 && direction(self.path[self.path.len() - 2], last) == Some(step) {
+            self.path.pop();
+        }
+        self.path.push(cell);
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), LevelError> {
+        validate_path(&self.path)?;
+        if self.waves.is_empty() { return Err(LevelError::NoWaves); }
+        match self.waves.iter().position(|w| w.timed_departures.is_empty()) {
+            Some(i) => Err(LevelError::EmptyWave(i)),
+            None => Ok(()),
+        }
+    }
+
+    /// Road of the level, the path should be valid.
+    pub fn network(&self) -> Network {
+        Network::single(self.path.iter().map(|(x, y)| vec2(*x as f32, *y as f32)).collect())
+    }
+
+    pub fn to_ron(&self) -> Result<String, String> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|e| e.to_string())
+    }
+
+    pub fn from_ron(data: &str) -> Result<Self, String> {
+        ron::from_str(data).map_err(|e| e.to_string())
+    }
+
+    /// Reads the level saved in [slot].
+    pub fn load(slot: u8) -> Result<Self, String> {
+        let path = data_path(&level_file(slot)).ok_or("No data folder")?;
+        std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|data| LevelData::from_ron(&data))
+    }
+
+    pub fn save(&self, slot: u8) -> Result<(), String> {
+        let path = data_path(&level_file(slot)).ok_or("No data folder")?;
+        std::fs::write(path, self.to_ron()?).map_err(|e| e.to_string())
+    }
+
+    /// Valid level saved in place of the campaign level [level], if any.
+    pub fn replacing(level: u8) -> Option<Self> {
+        if level == 0 || level > LEVELS { return None; }
+        LevelData::load(level).ok().filter(|l| l.validate().is_ok())
+    }
+}
+
+/// Paths may end one cell past the right or top edge, so drones leave the screen.
+fn in_grid((x, y): Cell) -> bool {
+    x >= 0 && x <= COLUMNS && y >= 0 && y <= ROWS
+}
+
+/// Exits are on the right or top edge like campaign roads, never next to the GUI.
+fn on_edge((x, y): Cell) -> bool {
+    x >= COLUMNS - 1 || y >= ROWS - 1
+}
+
+/// Unit step from [a] to [b], if they are on the same row, column or diagonal.
+fn direction(a: Cell, b: Cell) -> Option<Cell> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    if dx == 0 && dy == 0 { return None; }
+    if dx == 0 || dy == 0 || dx.abs() == dy.abs() { Some((dx.signum(), dy.signum())) } else { None }
+}
+
+/// Checks that the road is made of straight segments inside the grid,
+/// never goes twice through the same cell nor crosses itself diagonally, and ends on the right or top edge.
+pub fn validate_path(path: &[Cell]) -> Result<(), LevelError> {
+    if path.len() < 2 { return Err(LevelError::TooShort); }
+    if let Some(i) = path.iter().position(|c| !in_grid(*c)) { return Err(LevelError::OutOfGrid(i)); }
+
+    let mut visited: HashSet<Cell> = HashSet::from([path[0]]);
+    // Middle of the diagonal steps, in half cells: crossing diagonals share it
+    let mut crossings: HashSet<Cell> = HashSet::new();
+    for (i, w) in path.windows(2).enumerate() {
+        if w[0] == w[1] { continue; }
+        let Some((dx, dy)) = direction(w[0], w[1]) else { return Err(LevelError::NotAligned(i + 1)); };
+        let mut cell = w[0];
+        while cell != w[1] {
+            let middle = (2 * cell.0 + dx, 2 * cell.1 + dy);
+            cell = (cell.0 + dx, cell.1 + dy);
+            if !visited.insert(cell) { return Err(LevelError::Overlap(cell)); }
+            if dx != 0 && dy != 0 && !crossings.insert(middle) { return Err(LevelError::Overlap(cell)); }
+        }
+    }
+
+    if !on_edge(path[path.len() - 1]) { return Err(LevelError::NoExit); }
+    Ok(())
+}
+
+#[test]
+fn test_validate_path() {
+    use crate::logic::path::PATH_1;
+
+    let campaign: Vec<Cell> = PATH_1.iter().map(|p| (p.x as isize, p.y as isize)).collect();
+    assert_eq!(validate_path(&campaign), Ok(()));
+    assert_eq!(validate_path(&[(0, 4)]), Err(LevelError::TooShort));
+    assert_eq!(validate_path(&[(0, 4), (3, 6), (19, 6)]), Err(LevelError::NotAligned(1)));
+    assert_eq!(validate_path(&[(0, 4), (-1, 4)]), Err(LevelError::OutOfGrid(1)));
+    assert_eq!(validate_path(&[(0, 4), (5, 4), (5, 6), (3, 6), (3, 2), (19, 2)]), Err(LevelError::Overlap((3, 4))));
+    assert_eq!(validate_path(&[(0, 4), (5, 4), (5, 6), (3, 6), (3, 5)]), Err(LevelError::NoExit));
+    assert_eq!(validate_path(&[(0, 4), (5, 4), (5, 0)]), Err(LevelError::NoExit));
+    assert_eq!(validate_path(&[(5, 8), (5, 4), (0, 4)]), Err(LevelError::NoExit));
+    assert_eq!(validate_path(&[(0, 0), (8, 8), (19, 8)]), Ok(()));
+    // The diagonals cross between (2, 2), (3, 2), (2, 3) and (3, 3)
+    assert_eq!(validate_path(&[(0, 2), (2, 2), (3, 3), (3, 4), (5, 4), (5, 2), (3, 2), (2, 3), (2, 8)]), Err(LevelError::Overlap((2, 3))));
+    assert_eq!(validate_path(&[(0, 2), (2, 2), (3, 3), (3, 4), (5, 4), (5, 2), (4, 2), (4, 1), (19, 1)]), Ok(()));
+}
+
+#[test]
+fn test_add_point() {
+    let mut level = LevelData::default();
+    assert_eq!(level.add_point((0, 4)), Ok(()));
+    assert_eq!(level.add_point((3, 4)), Ok(()));
+    assert_eq!(level.add_point((6, 4)), Ok(()));
+    assert_eq!(level.path, vec![(0, 4), (6, 4)]);
+    assert_eq!(level.add_point((8, 5)), Err(LevelError::NotAligned(2)));
+    assert_eq!(level.add_point((6, 2)), Ok(()));
+    assert_eq!(level.add_point((19, 2)), Ok(()));
+    assert_eq!(level.validate(), Ok(()));
+
+    let data = level.to_ron().unwrap();
+    let loaded = LevelData::from_ron(&data).unwrap();
+    assert_eq!(loaded.path, level.path);
+    assert_eq!(loaded.waves.len(), level.waves.len());
+
+    assert_eq!(level_file(0), LEVEL_FILE);
+    assert_eq!(level_file(3), "level_3.ron");
+    assert!(LevelData::replacing(0).is_none() && LevelData::replacing(LEVELS + 1).is_none());
+}
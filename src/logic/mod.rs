@@ -1,4 +1,17 @@
+pub mod battle_log;
+pub mod difficulty;
+pub mod dps;
+pub mod economy;
+pub mod event_log;
 pub mod path;
+pub mod score;
+pub mod sim;
 pub mod tower_stats;
 pub mod waves;
-pub mod gen;
\ No newline at end of file
+pub mod gen;
+pub mod heatmap;
+pub mod level_data;
+pub mod optimizer;
+pub mod rules;
+#[cfg(feature = "dev")]
+pub mod console;
@@ -0,0 +1,371 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+use strum::IntoEnumIterator;
+
+use crate::logic::difficulty::Difficulty;
+use crate::logic::gen::{COLUMNS, ROWS};
+use crate::logic::sim::{Action, BattleView, collect_packages, coverage, Outcome, Sim, Strategy};
+use crate::logic::waves::waves_of_level;
+use crate::tower::{Tower, Towers};
+
+/// Battles played to score a plan, since drones pick random routes and packages
+const RUNS: u32 = 3;
+
+/// File in the data folder the best plan is saved to, and read back by [replay] and the console
+pub const PLAN_FILE: &str = "plan.txt";
+
+/// Euros a plan may spend by the start of each wave, the last value holding for the next waves.
+/// The money earned during the battle is needed too.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Budget(pub Vec<u16>);
+
+impl Budget {
+    pub fn at(&self, wave: usize) -> u16 {
+        self.0.get(wave).or(self.0.last()).copied().unwrap_or(u16::MAX)
+    }
+
+    /// Reads comma separated amounts, e.g. `200,300,500`.
+    pub fn parse(text: &str) -> Self {
+        Budget(text.split(',').filter_map(|n| n.trim().parse().ok()).collect())
+    }
+}
+
+/// A tower built or upgraded as soon as [wave] has started, wave 0 being before the first one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Step {
+    pub wave: usize,
+    pub action: Action,
+}
+
+/// Tower layout and upgrade timings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    /// Towers at the end of the plan.
+    pub fn towers(&self) -> Vec<Tower> {
+        let mut towers: Vec<Tower> = vec![];
+        for step in self.steps.iter() {
+            match step.action {
+                Action::Build(model, x, y) => towers.push(model.instantiate(x, y)),
+                Action::Upgrade(x, y) => {
+                    if let Some(t) = towers.iter_mut().find(|t| t.x == x && t.y == y) { t.rank += 1; }
+                }
+                Action::Collect(_) => {}
+            }
+        }
+        towers
+    }
+
+    pub fn cost(&self) -> u16 {
+        let mut towers: Vec<Tower> = vec![];
+        let mut cost = 0;
+        for step in self.steps.iter() {
+            match step.action {
+                Action::Build(model, x, y) => {
+                    cost += model.get_cost();
+                    towers.push(model.instantiate(x, y));
+                }
+                Action::Upgrade(x, y) => {
+                    let Some(t) = towers.iter_mut().find(|t| t.x == x && t.y == y) else { continue; };
+                    cost += t.upgrade_cost().unwrap_or(0);
+                    t.rank += 1;
+                }
+                Action::Collect(_) => {}
+            }
+        }
+        cost
+    }
+
+    /// Sorts the steps by wave and drops the ones which are not possible or over [budget].
+    pub fn fit(&mut self, budget: &Budget) {
+        self.steps.sort_by_key(|s| s.wave);
+        let mut towers: Vec<Tower> = vec![];
+        let mut spent: u16 = 0;
+        self.steps.retain(|step| {
+            let cost = match step.action {
+                Action::Build(model, x, y) if !towers.iter().any(|t| t.x == x && t.y == y) => model.get_cost(),
+                Action::Upgrade(x, y) => match towers.iter().find(|t| t.x == x && t.y == y).and_then(|t| t.upgrade_cost()) {
+                    Some(cost) => cost,
+                    None => return false,
+                },
+                _ => return false,
+            };
+            if spent.saturating_add(cost) > budget.at(step.wave) { return false; }
+            spent += cost;
+            match step.action {
+                Action::Build(model, x, y) => towers.push(model.instantiate(x, y)),
+                Action::Upgrade(x, y) => towers.iter_mut().filter(|t| t.x == x && t.y == y).for_each(|t| t.rank += 1),
+                Action::Collect(_) => {}
+            }
+            true
+        });
+    }
+
+    /// One step per line: `wave build Model x y` or `wave upgrade x y`.
+    pub fn to_script(&self) -> String {
+        self.steps.iter()
+            .map(|step| match step.action {
+                Action::Build(model, x, y) => format!("{} build {:?} {} {}\n", step.wave, model, x, y),
+                Action::Upgrade(x, y) => format!("{} upgrade {} {}\n", step.wave, x, y),
+                Action::Collect(_) => String::new(),
+            })
+            .collect()
+    }
+
+    /// Reads a script written by [Plan::to_script], ignoring empty lines and `#` comments.
+    pub fn parse(script: &str) -> Result<Plan, String> {
+        let mut steps = vec![];
+        for line in script.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |i: usize| words.get(i).and_then(|w| w.parse::<usize>().ok()).ok_or_else(|| format!("Invalid line: {}", line));
+            let action = match words.get(1) {
+                Some(&"build") => {
+                    let model = words.get(2)
+                        .and_then(|name| Towers::iter().find(|t| format!("{:?}", t).eq_ignore_ascii_case(name)))
+                        .ok_or_else(|| format!("Unknown tower in: {}", line))?;
+                    Action::Build(model, number(3)?, number(4)?)
+                }
+                Some(&"upgrade") => Action::Upgrade(number(2)?, number(3)?),
+                _ => return Err(format!("Invalid line: {}", line)),
+            };
+            steps.push(Step { wave: number(0)?, action });
+        }
+        Ok(Plan { steps })
+    }
+}
+
+/// Plays a [Plan]: each step waits for its wave and enough money, in order.
+pub struct Scripted {
+    plan: Plan,
+    next: usize,
+}
+
+impl Scripted {
+    pub fn new(plan: Plan) -> Self { Scripted { plan, next: 0 } }
+}
+
+impl Strategy for Scripted {
+    fn name(&self) -> &'static str { "script" }
+
+    fn act(&mut self, view: &BattleView) -> Vec<Action> {
+        let mut actions = collect_packages(view);
+        let Some(step) = self.plan.steps.get(self.next) else { return actions; };
+        if step.wave > view.wave { return actions; }
+        let cost = match step.action {
+            Action::Build(model, x, y) if view.free.contains(&(x, y)) => Some(model.get_cost()),
+            Action::Upgrade(x, y) => view.towers.iter().find(|t| t.x == x && t.y == y).and_then(|t| t.upgrade_cost()),
+            _ => None,
+        };
+        match cost {
+            // Waits for the money
+            Some(cost) if cost > view.money => {}
+            Some(_) => {
+                actions.push(step.action);
+                self.next += 1;
+            }
+            // The step is not possible, skip it
+            None => self.next += 1,
+        }
+        actions
+    }
+}
+
+/// What the search needs to know about a level.
+struct Level {
+    level: u8,
+    difficulty: Difficulty,
+    waves: usize,
+    unlocked: Vec<Towers>,
+    /// Free cells in range of the road
+    cells: Vec<(usize, usize)>,
+}
+
+impl Level {
+    fn new(level: u8, difficulty: Difficulty) -> Self {
+        let sim = Sim::campaign(level, difficulty);
+        let view = sim.view();
+        let cells = view.free.iter()
+            .copied()
+            .filter(|(x, y)| coverage(view.network, *x, *y, Towers::Lightning.instantiate(*x, *y).range()) > 0.)
+            .collect();
+        Level { level, difficulty, waves: waves_of_level(level).len(), unlocked: view.unlocked.clone(), cells }
+    }
+
+    /// Average drones through, the cost breaking ties.
+    /// Lives are unlimited so that every wave is played.
+    fn score(&self, plan: &Plan) -> f32 {
+        let survived: u32 = (0..RUNS)
+            .map(|_| {
+                let mut sim = Sim::campaign(self.level, self.difficulty);
+                sim.unlimited_lives();
+                sim.run(&mut Scripted::new(plan.clone())).survived
+            })
+            .sum();
+        survived as f32 / RUNS as f32 + plan.cost() as f32 * 1e-5
+    }
+
+    fn random_build(&self, plan: &Plan, rng: &mut impl Rng) -> Option<Step> {
+        let used: HashSet<(usize, usize)> = plan.towers().iter().map(|t| (t.x, t.y)).collect();
+        let cells: Vec<&(usize, usize)> = self.cells.iter().filter(|c| !used.contains(c)).collect();
+        let &&(x, y) = cells.choose(rng)?;
+        let model = *self.unlocked.choose(rng)?;
+        Some(Step { wave: rng.gen_range(0..=self.waves / 2), action: Action::Build(model, x, y) })
+    }
+
+    fn random_upgrade(&self, plan: &Plan, rng: &mut impl Rng) -> Option<Step> {
+        let builds: Vec<&Step> = plan.steps.iter().filter(|s| matches!(s.action, Action::Build(..))).collect();
+        let build = builds.choose(rng)?;
+        let Action::Build(_, x, y) = build.action else { return None; };
+        Some(Step { wave: rng.gen_range(build.wave..=self.waves), action: Action::Upgrade(x, y) })
+    }
+
+    fn random_plan(&self, budget: &Budget, rng: &mut impl Rng) -> Plan {
+        let mut plan = Plan::default();
+        for _ in 0..rng.gen_range(2..=8) {
+            if let Some(step) = self.random_build(&plan, rng) { plan.steps.push(step); }
+        }
+        for _ in 0..rng.gen_range(0..=4) {
+            if let Some(step) = self.random_upgrade(&plan, rng) { plan.steps.push(step); }
+        }
+        plan.fit(budget);
+        plan
+    }
+
+    /// Adds, removes, moves, changes or delays a step.
+    fn mutate(&self, plan: &Plan, budget: &Budget, rng: &mut impl Rng) -> Plan {
+        let mut plan = plan.clone();
+        let i = if plan.steps.is_empty() { 0 } else { rng.gen_range(0..plan.steps.len()) };
+        match rng.gen_range(0..6) {
+            0 => if let Some(step) = self.random_build(&plan, rng) { plan.steps.push(step); },
+            1 => if let Some(step) = self.random_upgrade(&plan, rng) { plan.steps.push(step); },
+            2 if !plan.steps.is_empty() => { plan.steps.remove(i); }
+            3 => if let Some(Step { action: Action::Build(model, x, y), wave }) = plan.steps.get(i).copied() {
+                // Moves the tower and its upgrades
+                if let Some(Step { action: Action::Build(_, nx, ny), .. }) = self.random_build(&plan, rng) {
+                    plan.steps[i] = Step { wave, action: Action::Build(model, nx, ny) };
+                    for step in plan.steps.iter_mut() {
+                        if step.action == Action::Upgrade(x, y) { step.action = Action::Upgrade(nx, ny); }
+                    }
+                }
+            },
+            4 => if let Some(Step { action: Action::Build(_, x, y), wave }) = plan.steps.get(i).copied() {
+                if let Some(model) = self.unlocked.choose(rng) { plan.steps[i] = Step { wave, action: Action::Build(*model, x, y) }; }
+            },
+            _ => if let Some(step) = plan.steps.get_mut(i) {
+                step.wave = if rng.gen_bool(0.5) { step.wave.saturating_sub(1) } else { (step.wave + 1).min(self.waves) };
+            },
+        }
+        plan.fit(budget);
+        plan
+    }
+}
+
+/// Searches the plan letting the fewest drones through a campaign level,
+/// hill climbing from [restarts] random plans. Returns the plan and its average survivors.
+pub fn optimize(level: u8, difficulty: Difficulty, budget: &Budget, restarts: u32, iterations: u32, rng: &mut impl Rng) -> (Plan, f32) {
+    let level = Level::new(level, difficulty);
+    let mut best = (Plan::default(), level.score(&Plan::default()));
+    for _ in 0..restarts {
+        let mut plan = level.random_plan(budget, rng);
+        let mut score = level.score(&plan);
+        for _ in 0..iterations {
+            let candidate = level.mutate(&plan, budget, rng);
+            let candidate_score = level.score(&candidate);
+            if candidate_score <= score { (plan, score) = (candidate, candidate_score); }
+        }
+        if score < best.1 { best = (plan, score); }
+    }
+    best
+}
+
+/// The grid with two characters per cell: road `==`, buildable cells `::`,
+/// and towers as their initial and rank, e.g. `L2`.
+pub fn picture(level: u8, difficulty: Difficulty, plan: &Plan) -> String {
+    let sim = Sim::campaign(level, difficulty);
+    let view = sim.view();
+    let road: HashSet<(isize, isize)> = view.network.routes.iter().flat_map(|r| r.path.cells()).collect();
+    let towers: HashMap<(usize, usize), Tower> = plan.towers().into_iter().map(|t| ((t.x, t.y), t)).collect();
+    let mut picture = String::new();
+    for y in (0..ROWS).rev() {
+        for x in 0..COLUMNS {
+            let cell = (x as usize, y as usize);
+            picture += &match towers.get(&cell) {
+                Some(t) => format!("{}{}", &format!("{:?}", t.model)[..1], t.rank),
+                None if road.contains(&(x, y)) => "==".to_string(),
+                None if view.free.contains(&cell) => "::".to_string(),
+                None => "..".to_string(),
+            };
+        }
+        picture += "\n";
+    }
+    picture
+}
+
+/// Best plan found for a level, as a commented placement script followed by the picture of the grid.
+pub fn report(level: u8, difficulty: Difficulty, budget: &Budget) -> String {
+    let (plan, survived) = optimize(level, difficulty, budget, 8, 150, &mut rand::thread_rng());
+    let mut report = format!("# Level {}, {}: {:.1} drones through, €{} spent\n", level, difficulty.get_name(), survived, plan.cost());
+    report += &plan.to_script();
+    for line in picture(level, difficulty, &plan).lines() { report += &format!("# {}\n", line); }
+    report
+}
+
+/// Plays a placement script, e.g. a [report] edited by hand, on a campaign level [runs] times.
+pub fn replay(level: u8, difficulty: Difficulty, script: &str, runs: u32) -> Result<String, String> {
+    let plan = Plan::parse(script)?;
+    let outcomes: Vec<Outcome> = (0..runs)
+        .map(|_| Sim::campaign(level, difficulty).run(&mut Scripted::new(plan.clone())))
+        .collect();
+    let won = outcomes.iter().filter(|o| o.won).count();
+    let survived = outcomes.iter().map(|o| o.survived).sum::<u32>() as f32 / runs.max(1) as f32;
+    Ok(format!("# Level {}, {}: won {}/{}, {:.1} drones through, €{} spent\n", level, difficulty.get_name(), won, runs, survived, plan.cost()))
+}
+
+#[test]
+fn test_plan_script() {
+    let plan = Plan { steps: vec![
+        Step { wave: 0, action: Action::Build(Towers::Lightning, 4, 3) },
+        Step { wave: 2, action: Action::Build(Towers::PaintBomb, 6, 5) },
+        Step { wave: 3, action: Action::Upgrade(4, 3) },
+    ] };
+    assert_eq!(plan.to_script(), "0 build Lightning 4 3\n2 build PaintBomb 6 5\n3 upgrade 4 3\n");
+    assert_eq!(Plan::parse(&format!("# comment\n\n{}", plan.to_script())), Ok(plan.clone()));
+    assert!(Plan::parse("1 build Cannon 2 2").is_err());
+    assert!(replay(1, Difficulty::Easy, &plan.to_script(), 1).is_ok());
+    assert_eq!(plan.cost(), 40 + 60 + 80);
+    assert_eq!(plan.towers()[0].rank, 2);
+}
+
+#[test]
+fn test_plan_fit() {
+    let mut plan = Plan { steps: vec![
+        Step { wave: 1, action: Action::Upgrade(4, 3) },
+        Step { wave: 0, action: Action::Build(Towers::Lightning, 4, 3) },
+        Step { wave: 0, action: Action::Build(Towers::Lightning, 4, 3) },
+        Step { wave: 0, action: Action::Upgrade(1, 1) },
+        Step { wave: 0, action: Action::Build(Towers::PaintBomb, 6, 5) },
+    ] };
+    plan.fit(&Budget(vec![100, 150]));
+    assert_eq!(plan.steps, vec![
+        Step { wave: 0, action: Action::Build(Towers::Lightning, 4, 3) },
+        Step { wave: 0, action: Action::Build(Towers::PaintBomb, 6, 5) },
+    ]);
+    assert_eq!(Budget::parse("200, 300,x").0, vec![200, 300]);
+    assert_eq!(Budget::default().at(3), u16::MAX);
+}
+
+#[test]
+fn test_optimize() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let empty = Level::new(1, Difficulty::Easy).score(&Plan::default());
+    let (plan, survived) = optimize(1, Difficulty::Easy, &Budget::default(), 1, 3, &mut StdRng::seed_from_u64(49));
+    assert!(survived <= empty);
+    assert_eq!(picture(1, Difficulty::Easy, &plan).lines().count(), ROWS as usize);
+}
@@ -1,7 +1,11 @@
+use std::f32::consts::TAU;
+
 use bevy::math::vec2;
 use bevy::prelude::*;
+use rand::{Rng, SeedableRng, thread_rng};
+use rand::rngs::StdRng;
 
-use crate::logic::gen::gen_path;
+use crate::logic::gen::{gen_relaxed_path, gen_two_lanes, GenParams};
 
 pub struct Path {
     /// Vec2(x, y) -> (1., 2.) is the center of the tile (1, 2)
@@ -12,56 +16,309 @@ pub struct Path {
     segments: Vec<f32>,
     /// Combined segments length
     combined: Vec<f32>,
+    /// Shape of each segment
+    shapes: Vec<Shape>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Shape {
+    Line(Vec2, Vec2),
+    /// Circular arc going from [start] angle to [start] + [sweep] (radians)
+    Arc { center: Vec2, radius: f32, start: f32, sweep: f32 },
 }
 
+impl Shape {
+    fn length(&self) -> f32 {
+        match self {
+            Shape::Line(p1, p2) => p1.distance(*p2),
+            Shape::Arc { radius, sweep, .. } => radius * sweep.abs(),
+        }
+    }
+
+    /// Returns the position after walking [proportion] of the shape.
+    /// Arcs are parameterized by their length, so the speed is constant.
+    fn at(&self, proportion: f32) -> Vec2 {
+        match *self {
+            Shape::Line(p1, p2) => p1 + proportion * (p2 - p1),
+            Shape::Arc { center, radius, start, sweep } => {
+                let angle = start + proportion * sweep;
+                center + radius * Vec2::new(angle.cos(), angle.sin())
+            }
+        }
+    }
+
+    /// Returns the proportion of the shape which is the closest to [p].
+    fn project(&self, p: Vec2) -> f32 {
+        match *self {
+            Shape::Line(p1, p2) => {
+                let d = p2 - p1;
+                if d.length_squared() == 0. { return 0.; }
+                ((p - p1).dot(d) / d.length_squared()).clamp(0., 1.)
+            }
+            Shape::Arc { center, start, sweep, .. } => {
+                let v = p - center;
+                // Angle from the start in the direction of the arc, in [0, 2π[
+                let delta = ((v.y.atan2(v.x) - start) * sweep.signum()).rem_euclid(TAU);
+                if delta <= sweep.abs() { return delta / sweep.abs(); }
+                // Outside of the arc: closest end
+                if delta - sweep.abs() < TAU - delta { 1. } else { 0. }
+            }
+        }
+    }
+
+    /// Returns the parts of the shape inside the circle, as (start, end) proportion intervals.
+    fn inside_circle(&self, c: Vec2, r: f32) -> Vec<(f32, f32)> {
+        match *self {
+            Shape::Line(p1, p2) => {
+                // Solve |p1 + t * (p2 - p1) - c|² <= r²
+                let (d, f) = (p2 - p1, p1 - c);
+                let (a, b, k) = (d.dot(d), 2. * f.dot(d), f.dot(f) - r * r);
+                if a == 0. { return if k <= 0. { vec![(0., 1.)] } else { vec![] }; }
+                let delta = b * b - 4. * a * k;
+                if delta < 0. { return vec![]; }
+                let t1 = ((-b - delta.sqrt()) / (2. * a)).max(0.);
+                let t2 = ((-b + delta.sqrt()) / (2. * a)).min(1.);
+                if t1 <= t2 { vec![(t1, t2)] } else { vec![] }
+            }
+            Shape::Arc { .. } => {
+                let mut intervals = vec![];
+                let mut current: Option<(f32, f32)> = None;
+                for i in 0..=ARC_SAMPLES {
+                    let t = i as f32 / ARC_SAMPLES as f32;
+                    let inside = self.at(t).distance_squared(c) <= r * r;
+                    match (inside, current) {
+                        (true, Some((start, _))) => current = Some((start, t)),
+                        (true, None) => current = Some((t, t)),
+                        (false, Some(interval)) => {
+                            intervals.push(interval);
+                            current = None;
+                        }
+                        (false, None) => {}
+                    }
+                }
+                if let Some(interval) = current { intervals.push(interval); }
+                intervals
+            }
+        }
+    }
+}
+
+/// Number of samples used to intersect arcs with circles
+const ARC_SAMPLES: usize = 64;
+
 impl Path {
+    /// Builds a path made of straight segments, which must be horizontal, vertical or diagonal.
     pub fn from_points(points: Vec<Vec2>) -> Self {
+        let shapes = points
+            .windows(2)
+            .map(|w| {
+                let (p1, p2) = (w[0], w[1]);
+                assert!(p1.x == p2.x || p1.y == p2.y || (p2.x - p1.x).abs() == (p2.y - p1.y).abs());
+                Shape::Line(p1, p2)
+            })
+            .collect();
+        Path::from_shapes(points, shapes)
+    }
+
+    /// Builds a path where corners are replaced by circular arcs of at most [radius].
+    pub fn from_points_smooth(points: Vec<Vec2>, radius: f32) -> Self {
+        let mut shapes = vec![];
+        let mut current = points[0];
+
+        for i in 1..points.len() - 1 {
+            let (a, b, c) = (points[i - 1], points[i], points[i + 1]);
+            let (d1, d2) = ((b - a).normalize(), (c - b).normalize());
+            let turn = d1.angle_between(d2);
+            if turn.abs() < 1e-3 { continue; }
+
+            // Distance from the corner to the tangent points, arcs use at most half of each segment
+            let tangent = (radius * (turn.abs() / 2.).tan())
+                .min(a.distance(b) / 2.)
+                .min(b.distance(c) / 2.);
+            let radius = tangent / (turn.abs() / 2.).tan();
+            let (t1, t2) = (b - d1 * tangent, b + d2 * tangent);
+
+            // The center is on the inner side of the turn
+            let normal = turn.signum() * d1.perp();
+            let center = t1 + normal * radius;
+
+            if current.distance(t1) > 1e-6 { shapes.push(Shape::Line(current, t1)); }
+            shapes.push(Shape::Arc {
+                center,
+                radius,
+                start: (t1 - center).y.atan2((t1 - center).x),
+                sweep: turn,
+            });
+            current = t2;
+        }
+        shapes.push(Shape::Line(current, points[points.len() - 1]));
+
+        Path::from_shapes(points, shapes)
+    }
+
+    fn from_shapes(points: Vec<Vec2>, shapes: Vec<Shape>) -> Self {
         let mut length: f32 = 0.;
         let mut segments = vec![];
         let mut combined = vec![];
 
-
-        for i in 0..points.len() - 1 {
-            let (p1, p2) = (points[i], points[i + 1]);
-            assert!(p1.x == p2.x || p1.y == p2.y);
-            let distance = p1.distance(p2);
+        for shape in shapes.iter() {
+            let distance = shape.length();
             length += distance;
             segments.push(distance);
             combined.push(length);
         }
 
-        Path { points, length, segments, combined }
+        Path { points, length, segments, combined, shapes }
     }
 
     /// Returns the position on the path after walking [length] (1. = tile side).
     pub fn pos(&self, length: f32) -> Option<Vec2> {
         if length < 0. { return None; }
-        if length >= self.length {
-            return match self.points.last() {
-                Some(pos) => Some(*pos),
-                None => None,
-            }
-        }
+        if length >= self.length { return self.points.last().copied(); }
 
         // Current segment nb
-        let Some((i, _)) = self.combined.iter().enumerate().find(|(_, d)| **d > length) else { return None };
+        let i = self.combined.partition_point(|d| *d <= length);
+        if i >= self.combined.len() { return None; }
 
+        return Some(self.pos_in_segment(i, length));
+    }
+
+    /// Same as [Path::pos], starting the segment search from [segment], which is updated.
+    /// This is O(1) when [length] increases slowly, for instance for a drone.
+    pub fn pos_cached(&self, length: f32, segment: &mut usize) -> Option<Vec2> {
+        if length < 0. { return None; }
+        if length >= self.length { return self.points.last().copied(); }
+
+        let mut i = (*segment).min(self.combined.len() - 1);
+        while self.combined[i] <= length { i += 1; }
+        while i > 0 && self.combined[i - 1] > length { i -= 1; }
+        *segment = i;
+
+        return Some(self.pos_in_segment(i, length));
+    }
+
+    fn pos_in_segment(&self, i: usize, length: f32) -> Vec2 {
         // Proportion of the current segment
-        let in_progress = length - if i > 0 { self.combined[i-1] } else { 0. };
-        let proportion = in_progress / self.segments[i];
+        let proportion = (length - self.start_of(i)) / self.segments[i];
+        self.shapes[i].at(proportion)
+    }
+
+    /// Length of the path before the segment [i]
+    fn start_of(&self, i: usize) -> f32 {
+        if i > 0 { self.combined[i - 1] } else { 0. }
+    }
 
-        // Compute position
-        let Vec2 { x: x1, y: y1 } = self.points[i];
-        let Vec2 { x: x2, y: y2 } = self.points[i + 1];
-        let x = x1 + proportion * (x2 - x1);
-        let y = y1 + proportion * (y2 - y1);
+    /// Returns the advance of the point of the path which is the closest to [p].
+    pub fn nearest_advance(&self, p: Vec2) -> f32 {
+        let mut best = (f32::MAX, 0.);
+        for (i, shape) in self.shapes.iter().enumerate() {
+            let t = shape.project(p);
+            let distance = shape.at(t).distance_squared(p);
+            if distance < best.0 { best = (distance, self.start_of(i) + t * self.segments[i]); }
+        }
+        best.1
+    }
 
-        return Some(Vec2::new(x, y));
+    /// Returns the stretches of the path inside a circle (e.g. a tower range), as (start, end) advance intervals.
+    pub fn advances_in_circle(&self, center: Vec2, radius: f32) -> Vec<(f32, f32)> {
+        let mut stretches: Vec<(f32, f32)> = vec![];
+        for (i, shape) in self.shapes.iter().enumerate() {
+            let start = self.start_of(i);
+            for (t1, t2) in shape.inside_circle(center, radius) {
+                let (a1, a2) = (start + t1 * self.segments[i], start + t2 * self.segments[i]);
+                match stretches.last_mut() {
+                    // The stretch continues on this segment
+                    Some((_, end)) if (a1 - *end).abs() < 1e-4 => *end = a2,
+                    _ => stretches.push((a1, a2)),
+                }
+            }
+        }
+        stretches
     }
 
     pub fn drone_won(&self, advance: f32) -> bool {
         advance >= self.length
     }
+
+    /// Returns the cells covered by the path, in order.
+    pub fn cells(&self) -> Vec<(isize, isize)> {
+        let mut cells: Vec<(isize, isize)> = vec![];
+        let steps = ((self.length / CELLS_STEP).ceil() as usize).max(1);
+        for i in 0..=steps {
+            let Some(p) = self.pos(self.length * i as f32 / steps as f32) else { continue };
+            let cell = (p.x.round() as isize, p.y.round() as isize);
+            if cells.last() != Some(&cell) { cells.push(cell); }
+        }
+        cells
+    }
+}
+
+/// Sampling step used to find the cells covered by a path
+const CELLS_STEP: f32 = 0.1;
+
+/// A road from a spawn point to an exit.
+pub struct Route {
+    pub path: Path,
+    /// Index of the spawn point
+    pub lane: usize,
+    /// Odds to take this route among the routes starting from the same lane
+    pub weight: u32,
+}
+
+impl Route {
+    pub fn new(lane: usize, weight: u32, points: Vec<Vec2>) -> Self {
+        Route { path: Path::from_points(points), lane, weight }
+    }
+
+    pub fn smooth(lane: usize, weight: u32, points: Vec<Vec2>, radius: f32) -> Self {
+        Route { path: Path::from_points_smooth(points, radius), lane, weight }
+    }
+}
+
+/// Every road of a level.
+/// Routes of the same lane fork where they diverge, and routes of different lanes merge where they join.
+pub struct Network {
+    pub routes: Vec<Route>,
+}
+
+impl Network {
+    pub fn single(points: Vec<Vec2>) -> Self {
+        Network { routes: vec![Route::new(0, 1, points)] }
+    }
+
+    pub fn lanes(&self) -> usize {
+        self.routes.iter().map(|r| r.lane + 1).max().unwrap_or(1)
+    }
+
+    pub fn route(&self, i: usize) -> &Path {
+        &self.routes[i].path
+    }
+
+    /// Returns the point of the network which is the closest to [p].
+    pub fn nearest_point(&self, p: Vec2) -> Vec2 {
+        self.routes.iter()
+            .filter_map(|r| r.path.pos(r.path.nearest_advance(p)))
+            .min_by(|a, b| a.distance_squared(p).total_cmp(&b.distance_squared(p)))
+            .unwrap_or(p)
+    }
+
+    /// Returns the index of a weighted random route starting from [lane], or from any lane if [None].
+    pub fn pick_route(&self, lane: Option<usize>) -> usize {
+        let lane = lane.map(|l| l % self.lanes());
+        let candidates = self.routes.iter()
+            .enumerate()
+            .filter(|(_, r)| lane.is_none() || lane == Some(r.lane))
+            .collect::<Vec<_>>();
+        let total: u32 = candidates.iter().map(|(_, r)| r.weight).sum();
+        if total == 0 { return candidates.first().map(|(i, _)| *i).unwrap_or(0); }
+
+        let mut roll = thread_rng().gen_range(0..total);
+        for (i, route) in candidates {
+            if roll < route.weight { return i; }
+            roll -= route.weight;
+        }
+        0
+    }
 }
 
 #[test]
@@ -98,6 +355,167 @@ fn test_path_position() {
     assert_eq!(path.pos(5.), Some(Vec2::new(2., 4.)));
 }
 
+#[test]
+fn test_path_diagonal() {
+    let path = Path::from_points(vec![
+        Vec2::new(0., 0.),
+        Vec2::new(2., 0.),
+        Vec2::new(4., 2.),
+    ]);
+    assert!((path.length - (2. + 8_f32.sqrt())).abs() < 1e-5);
+    assert!(path.pos(2. + 2_f32.sqrt()).unwrap().distance(Vec2::new(3., 1.)) < 1e-5);
+}
+
+#[test]
+#[should_panic]
+fn test_path_invalid_segment() {
+    Path::from_points(vec![
+        Vec2::new(0., 0.),
+        Vec2::new(2., 1.),
+    ]);
+}
+
+#[test]
+fn test_path_smooth() {
+    let path = Path::from_points_smooth(vec![
+        Vec2::new(0., 0.),
+        Vec2::new(4., 0.),
+        Vec2::new(4., 4.),
+    ], 1.);
+    // Line, quarter circle, line
+    assert_eq!(path.segments.len(), 3);
+    assert!((path.length - (6. + std::f32::consts::FRAC_PI_2)).abs() < 1e-5);
+    assert_eq!(path.pos(0.), Some(Vec2::new(0., 0.)));
+    assert_eq!(path.pos(path.length), Some(Vec2::new(4., 4.)));
+
+    // Constant speed along the path
+    let step = path.length / 1000.;
+    for i in 0..999 {
+        let (p1, p2) = (path.pos(i as f32 * step).unwrap(), path.pos((i + 1) as f32 * step).unwrap());
+        assert!((p1.distance(p2) - step).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn test_path_cells() {
+    let path = Path::from_points(vec![
+        Vec2::new(0., 1.),
+        Vec2::new(2., 1.),
+        Vec2::new(2., 0.),
+        Vec2::new(4., 2.),
+    ]);
+    assert_eq!(path.cells(), vec![(0, 1), (1, 1), (2, 1), (2, 0), (3, 1), (4, 2)]);
+}
+
+#[test]
+fn test_network_pick_route() {
+    let network = Network {
+        routes: vec![
+            Route::new(0, 1, vec![Vec2::new(0., 1.), Vec2::new(4., 1.)]),
+            Route::new(0, 0, vec![Vec2::new(0., 1.), Vec2::new(2., 1.), Vec2::new(2., 4.)]),
+            Route::new(1, 3, vec![Vec2::new(0., 5.), Vec2::new(4., 5.)]),
+        ],
+    };
+    assert_eq!(network.lanes(), 2);
+    for _ in 0..100 {
+        assert_eq!(network.pick_route(Some(0)), 0);
+        assert_eq!(network.pick_route(Some(1)), 2);
+        assert_eq!(network.pick_route(Some(3)), 2);
+        assert_ne!(network.pick_route(None), 1);
+    }
+}
+
+#[test]
+fn test_level_fork() {
+    let network = path_of_level_n(6);
+    assert_eq!(network.lanes(), 1);
+    let (road, detour) = (network.route(0), network.route(1));
+    assert_eq!(road.pos(0.), detour.pos(0.));
+    assert!(detour.drone_won(road.length + 6.) && !detour.drone_won(road.length + 5.9));
+    // The routes share the road before the fork and after the junction
+    assert_eq!(road.cells()[..7], detour.cells()[..7]);
+    assert_eq!(road.cells()[14..], detour.cells()[detour.cells().len() - 7..]);
+    let detours = (0..3000).filter(|_| network.pick_route(Some(0)) == 1).count();
+    assert!((800..1200).contains(&detours));
+}
+
+/// Former implementation of [Path::pos], with a linear search
+#[cfg(test)]
+fn pos_linear(path: &Path, length: f32) -> Option<Vec2> {
+    if length < 0. { return None; }
+    if length >= path.length { return path.points.last().copied(); }
+    let Some((i, _)) = path.combined.iter().enumerate().find(|(_, d)| **d > length) else { return None };
+    let in_progress = length - if i > 0 { path.combined[i - 1] } else { 0. };
+    Some(path.shapes[i].at(in_progress / path.segments[i]))
+}
+
+#[cfg(test)]
+fn test_paths() -> Vec<Path> {
+    vec![
+        Path::from_points(PATH_1.to_vec()),
+        Path::from_points(PATH_2.to_vec()),
+        Path::from_points(PATH_3.to_vec()),
+        Path::from_points(PATH_4.to_vec()),
+        Path::from_points(PATH_5.to_vec()),
+        Path::from_points(PATH_6.to_vec()),
+        Path::from_points_smooth(PATH_2.to_vec(), 1.),
+        Path::from_points_smooth(PATH_3.to_vec(), 0.5),
+    ]
+}
+
+#[test]
+fn test_path_pos_matches_linear_search() {
+    let mut rng = StdRng::seed_from_u64(29);
+    for path in test_paths() {
+        let mut segment = 0;
+        let mut advance = 0.;
+        for _ in 0..10000 {
+            let length = rng.gen_range(-1.0..path.length + 1.);
+            assert_eq!(path.pos(length), pos_linear(&path, length));
+
+            advance += rng.gen_range(0.0..0.1);
+            assert_eq!(path.pos_cached(advance, &mut segment), pos_linear(&path, advance));
+        }
+
+        // Going backwards also works
+        for _ in 0..1000 {
+            let length = rng.gen_range(0.0..path.length);
+            assert_eq!(path.pos_cached(length, &mut segment), pos_linear(&path, length));
+        }
+    }
+}
+
+#[test]
+fn test_path_nearest_advance() {
+    let mut rng = StdRng::seed_from_u64(29);
+    for path in test_paths() {
+        for _ in 0..1000 {
+            let p = path.pos(rng.gen_range(0.0..path.length)).unwrap();
+            let nearest = path.pos(path.nearest_advance(p)).unwrap();
+            assert!(nearest.distance(p) < 1e-3);
+        }
+    }
+}
+
+#[test]
+fn test_path_advances_in_circle() {
+    let mut rng = StdRng::seed_from_u64(29);
+    for path in test_paths() {
+        for _ in 0..100 {
+            let center = Vec2::new(rng.gen_range(0.0..20.), rng.gen_range(0.0..9.));
+            let radius = rng.gen_range(0.5..4.);
+            let stretches = path.advances_in_circle(center, radius);
+
+            for _ in 0..100 {
+                let advance = rng.gen_range(0.0..path.length);
+                let distance = pos_linear(&path, advance).unwrap().distance(center);
+                let in_stretch = stretches.iter().any(|(a1, a2)| *a1 <= advance && advance <= *a2);
+                if in_stretch { assert!(distance <= radius + 0.05); } else { assert!(distance >= radius - 0.05); }
+            }
+        }
+    }
+}
+
 #[test]
 fn test_path_some() {
     let path = Path::from_points(vec![
@@ -187,14 +605,51 @@ const PATH_6: [Vec2; 2] = [
     vec2(GRID_WIDTH, 4.),
 ];
 
-pub fn path_of_level_n(level: u8) -> Vec<Vec2> {
+/// Detour forking from [PATH_6] and joining it again
+const PATH_6_DETOUR: [Vec2; 6] = [
+    vec2(0., 4.),
+    vec2(6., 4.),
+    vec2(6., 7.),
+    vec2(14., 7.),
+    vec2(14., 4.),
+    vec2(GRID_WIDTH, 4.),
+];
+
+/// Path of endless mode, generated from its seed.
+pub fn path_of_endless(seed: u64) -> Network {
+    let mut rng = StdRng::seed_from_u64(seed);
+    Network { routes: vec![Route::smooth(0, 1, gen_relaxed_path(&GenParams::default(), &mut rng), 1.)] }
+}
+
+/// Path of a level in the sandbox, level 0 being a straight road on a blank grid.
+pub fn path_of_sandbox(level: u8) -> Network {
+    match level {
+        0 => Network::single(PATH_6.to_vec()),
+        n => path_of_level_n(n),
+    }
+}
+
+pub fn path_of_level_n(level: u8) -> Network {
     match level {
-        1 => PATH_1.to_vec(),
-        2 => PATH_2.to_vec(),
-        3 => PATH_3.to_vec(),
-        4 => PATH_4.to_vec(),
-        5 => PATH_5.to_vec(),
-        6 => PATH_6.to_vec(),
-        _ => gen_path(),
+        1 => Network::single(PATH_1.to_vec()),
+        2 => Network::single(PATH_2.to_vec()),
+        3 => Network::single(PATH_3.to_vec()),
+        4 => Network::single(PATH_4.to_vec()),
+        5 => Network::single(PATH_5.to_vec()),
+        // One drone out of three takes the detour
+        6 => Network {
+            routes: vec![
+                Route::new(0, 2, PATH_6.to_vec()),
+                Route::new(0, 1, PATH_6_DETOUR.to_vec()),
+            ],
+        },
+        n if n % 2 == 0 => Network {
+            routes: gen_two_lanes(&GenParams::for_level(n), &mut thread_rng())
+                .into_iter()
+                .enumerate()
+                .map(|(lane, points)| Route::new(lane, 1, points))
+                .collect(),
+        },
+        n => Network { routes: vec![Route::smooth(0, 1, gen_relaxed_path(&GenParams::for_level(n), &mut thread_rng()), 1.)] },
     }
 }
@@ -0,0 +1,142 @@
+use bevy::math::{vec2, Vec2};
+use bevy::prelude::Component;
+use strum::IntoEnumIterator;
+
+use crate::collision::body_size;
+use crate::drones::Drones;
+use crate::graphics::package::PackageKind;
+use crate::logic::path::Network;
+use crate::tower::Tower;
+use crate::util;
+use crate::util::misc::SLOW_DOWN_DELAY;
+use crate::util::package::{COFFEE_RELOAD, DECOY_SLOW, MONEY_BIG, MONEY_CURSE, MONEY_SMALL};
+use crate::util::size::{f32_tile_to_f32, tile_to_f32};
+use crate::util::size::battle::DECOY_RANGE;
+
+/// Stretches of the routes in range of a tower, as advance intervals for each drone class and route.
+/// A drone is in range when the distance between its center and the center of the tower is at most the tower range.
+#[derive(Component, Clone, Debug, Default)]
+pub struct Coverage(Vec<(Drones, Vec<Vec<(f32, f32)>>)>);
+
+impl Coverage {
+    pub fn new(tower: &Tower, network: &Network) -> Self {
+        let radius = tower.range() / tile_to_f32(2);
+        Coverage(Drones::iter().map(|class| {
+            // Drones levitate over the road
+            let offset = f32_tile_to_f32(0.5) + body_size(class.get_tiles()).y / 2.;
+            let center = util::world_to_path(util::tower_center(tower.x, tower.y) - vec2(0., offset));
+            (class, network.routes.iter().map(|r| r.path.advances_in_circle(center, radius)).collect())
+        }).collect())
+    }
+
+    /// Returns true if a drone of [class] at [advance] on [route] is in range.
+    pub fn covers(&self, class: Drones, route: usize, advance: f32) -> bool {
+        let Some((_, routes)) = self.0.iter().find(|(c, _)| *c == class) else { return false; };
+        routes.get(route).map_or(false, |stretches| stretches.iter().any(|(a, b)| advance >= *a && advance <= *b))
+    }
+}
+
+/// Drone a Lightning or PaintBomb tower aims at, among (drone, advance) pairs in range:
+/// the one furthest along its route.
+pub fn pick_target<T>(in_range: impl Iterator<Item = (T, f32)>) -> Option<T> {
+    in_range.max_by(|(_, a), (_, b)| a.total_cmp(b)).map(|(drone, _)| drone)
+}
+
+/// Position of a decoy used at [pos]: the closest point of the road, where drones can't miss it.
+pub fn decoy_position(network: &Network, pos: Vec2) -> Vec2 {
+    util::path_to_world(network.nearest_point(util::world_to_path(pos)))
+}
+
+/// Returns true if a drone at [drone] is caught in a blast of [radius] at [center].
+pub fn in_blast(drone: Vec2, center: Vec2, radius: f32) -> bool {
+    drone.distance_squared(center) <= radius * radius
+}
+
+/// Removes [amount] from [hp], unless the drone is invincible. Returns the damage dealt.
+pub fn damage(hp: &mut f32, amount: f32, invincible: bool) -> f32 {
+    if invincible { return 0.; }
+    let dealt = hp.min(amount);
+    *hp -= dealt;
+    dealt
+}
+
+/// Seconds before [tower] fires again.
+pub fn reload_delay(tower: &Tower, coffee: bool, no_cooldown: bool) -> f32 {
+    match (no_cooldown, coffee) {
+        (true, _) => 0.,
+        (_, true) => tower.reload_delay() * COFFEE_RELOAD,
+        _ => tower.reload_delay(),
+    }
+}
+
+/// Speed factor and duration in seconds of the slowdown of the drones in range of a Scrambler.
+pub fn scramble(tower: &Tower) -> (f32, f32) {
+    (tower.slow_factor(), SLOW_DOWN_DELAY)
+}
+
+/// Speed factor of a drone at [drone] close to a decoy at [decoy], without replacing a stronger [slow]down.
+pub fn decoy_slow(drone: Vec2, decoy: Vec2, slow: Option<f32>) -> Option<f32> {
+    if drone.distance(decoy) > DECOY_RANGE || slow.map_or(false, |s| s < DECOY_SLOW) { return None; }
+    Some(DECOY_SLOW)
+}
+
+/// Money won or lost when a package is used, a curse taking at most the money left.
+pub fn package_money(kind: PackageKind, money: u16) -> i32 {
+    match kind {
+        PackageKind::Common => MONEY_SMALL as i32,
+        PackageKind::Money => MONEY_BIG as i32,
+        PackageKind::Cursed => -(money.min(MONEY_CURSE) as i32),
+        _ => 0,
+    }
+}
+
+/// Lives after a Repair package, or None when they are already full.
+pub fn repair(lives: u16, max: u16) -> Option<u16> {
+    (lives < max).then(|| lives + 1)
+}
+
+#[test]
+fn test_rules() {
+    assert_eq!(pick_target(vec![('a', 2.), ('b', 5.5), ('c', 1.)].into_iter()), Some('b'));
+    assert_eq!(pick_target(Vec::<(char, f32)>::new().into_iter()), None);
+
+    let mut hp = 10.;
+    assert_eq!(damage(&mut hp, 4., false), 4.);
+    assert_eq!(damage(&mut hp, 8., false), 6.);
+    assert_eq!(hp, 0.);
+    assert_eq!(damage(&mut hp, 3., true), 0.);
+
+    assert_eq!(package_money(PackageKind::Cursed, 12), -12);
+    assert_eq!(package_money(PackageKind::Money, 0), MONEY_BIG as i32);
+    assert_eq!(repair(3, 4), Some(4));
+    assert_eq!(repair(4, 4), None);
+}
+
+#[test]
+fn test_coverage() {
+    use crate::logic::path::path_of_level_n;
+    use crate::tower::Towers;
+
+    let network = path_of_level_n(2);
+    for (model, x, y) in [(Towers::Lightning, 3, 2), (Towers::PaintBomb, 8, 5), (Towers::Scrambler, 12, 1)] {
+        let mut tower = model.instantiate(x, y);
+        tower.rank = 2;
+        let coverage = Coverage::new(&tower, &network);
+        for class in Drones::iter() {
+            let path = network.route(0);
+            for advance in (0..).map(|i| i as f32 * 0.05).take_while(|a| !path.drone_won(*a)) {
+                // Same as the drone sprites placed by [crate::drones::update_drones]
+                let p = path.pos(advance).unwrap();
+                let center = util::path_to_world(p) + vec2(0., f32_tile_to_f32(0.5) + body_size(class.get_tiles()).y / 2.);
+                let distance = util::tower_center(x, y).distance(center);
+                // Ignore the rounding errors on the edge of the range
+                if (distance - tower.range()).abs() < 0.01 { continue; }
+                assert_eq!(coverage.covers(class, 0, advance), distance <= tower.range());
+            }
+        }
+    }
+
+    let decoy = decoy_position(&network, util::tower_center(3, 2));
+    let road = util::world_to_path(decoy);
+    assert!(network.route(0).pos(network.route(0).nearest_advance(road)).unwrap().distance(road) < 1e-3);
+}
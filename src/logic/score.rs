@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use crate::logic::difficulty::Difficulty;
+
+const KILL: u32 = 100;
+const SURVIVED: u32 = 150;
+const PACKAGE: u32 = 50;
+/// The time bonus decreases until this battle duration (in seconds)
+const TIME_LIMIT: f32 = 900.;
+/// Points per second under [TIME_LIMIT]
+const TIME_BONUS: f32 = 2.;
+
+/// Summary of a battle, used to compute its score.
+pub struct BattleResult {
+    pub killed: u16,
+    pub survived: u16,
+    /// Money left at the end of the battle
+    pub money: u16,
+    pub packages: u16,
+    /// Duration of the battle in seconds
+    pub time: f32,
+}
+
+impl BattleResult {
+    pub fn score(&self) -> u32 {
+        let bonus = self.killed as u32 * KILL
+            + self.money as u32
+            + self.packages as u32 * PACKAGE
+            + ((TIME_LIMIT - self.time).max(0.) * TIME_BONUS) as u32;
+        bonus.saturating_sub(self.survived as u32 * SURVIVED)
+    }
+}
+
+/// Scores needed for 2 and 3 stars on a level.
+const fn star_thresholds(level: u8) -> [u32; 2] {
+    match level {
+        1 => [2400, 3400],
+        2 => [5500, 7500],
+        3 => [6500, 9000],
+        4 => [4500, 6000],
+        5 => [6500, 9000],
+        6 => [8500, 11500],
+        _ => [5000, 7000],
+    }
+}
+
+/// Returns 0 stars for a defeat, and 1 to 3 stars for a victory.
+pub fn stars(level: u8, won: bool, score: u32) -> u8 {
+    if !won { return 0; }
+    1 + star_thresholds(level).iter().filter(|t| score >= **t).count() as u8
+}
+
+/// Best result on a level and difficulty, stored in [crate::Progress].
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub level: u8,
+    pub difficulty: Difficulty,
+    pub score: u32,
+    pub stars: u8,
+}
+
+#[test]
+fn test_score() {
+    let result = |killed, survived| BattleResult { killed, survived, money: 100, packages: 2, time: 1000. };
+    assert_eq!(result(10, 0).score(), 10 * KILL + 100 + 2 * PACKAGE);
+    assert!(result(10, 1).score() < result(10, 0).score());
+    assert_eq!(result(0, 10).score(), 0);
+
+    let fast = BattleResult { time: 100., ..result(10, 0) };
+    assert!(fast.score() > result(10, 0).score());
+
+    assert_eq!(stars(1, false, 100000), 0);
+    assert_eq!(stars(1, true, 0), 1);
+    assert_eq!(stars(1, true, 100000), 3);
+}
@@ -0,0 +1,589 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use bevy::math::vec2;
+use bevy::prelude::{Timer, Vec2};
+use bevy::time::TimerMode;
+use rand::thread_rng;
+use strum::IntoEnumIterator;
+
+use crate::collision::body_size;
+use crate::drones::{Drones, Stats};
+use crate::graphics::package::{PackageKind, PackageOdds};
+use crate::level_select::CurrentLevel;
+use crate::logic::difficulty::Difficulty;
+use crate::logic::economy::Economy;
+use crate::logic::gen::{buildable_cells, COLUMNS, ROWS};
+use crate::logic::path::{Network, path_of_level_n};
+use crate::logic::rules;
+use crate::logic::rules::Coverage;
+use crate::logic::tower_stats::OMEGA_DAMAGES;
+use crate::logic::waves::{WaveIterator, WaveIteratorElement};
+use crate::shot::Shots;
+use crate::tower::{Tower, Towers};
+use crate::util;
+use crate::util::package::{COFFEE_DURATION, DECOY_DURATION, FREEZE_DURATION, PACKAGE_LIFETIME};
+use crate::util::size::{f32_tile_to_f32, GUI_HEIGHT, is_oob};
+use crate::util::size::battle::OMEGA_RANGE;
+
+/// Seconds simulated per step
+pub const STEP: f32 = 0.1;
+
+/// Seconds between two decisions of a [Strategy]
+pub const THINK_DELAY: f32 = 0.5;
+
+/// Battles still going on after this many seconds are lost
+const MAX_DURATION: f32 = 3600.;
+
+/// Something the player does during a battle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    Build(Towers, usize, usize),
+    Upgrade(usize, usize),
+    /// Collects the package at this index of [BattleView::packages]
+    Collect(usize),
+}
+
+/// What a [Strategy] knows about the battle.
+pub struct BattleView<'a> {
+    pub network: &'a Network,
+    /// Number of waves started so far
+    pub wave: usize,
+    pub money: u16,
+    /// Towers which can be built
+    pub unlocked: Vec<Towers>,
+    /// Cells where a tower can be built
+    pub free: Vec<(usize, usize)>,
+    pub towers: Vec<Tower>,
+    /// Packages lying on the road
+    pub packages: Vec<PackageKind>,
+}
+
+/// Plays a battle, either headless in a [Sim] or live with [crate::bot::Bot].
+pub trait Strategy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Actions to take now, called every [THINK_DELAY] seconds.
+    fn act(&mut self, view: &BattleView) -> Vec<Action>;
+}
+
+/// Spends the money on the tower or upgrade adding the most firepower on the road per euro,
+/// saving up when the best option is not affordable yet. Collects every package but cursed ones.
+#[derive(Default)]
+pub struct Heuristic;
+
+/// At most one scrambler every [SCRAMBLER_SHARE] towers
+const SCRAMBLER_SHARE: usize = 4;
+
+impl Heuristic {
+    /// Best use of the money, affordable or not, with its cost.
+    fn best(view: &BattleView) -> Option<(Action, u16)> {
+        let mut best: Option<(f32, Action, u16)> = None;
+        let mut consider = |score: f32, action: Action, cost: u16| {
+            if best.map_or(true, |(s, _, _)| score > s) { best = Some((score, action, cost)); }
+        };
+
+        let scramblers = view.towers.iter().filter(|t| t.model == Towers::Scrambler).count();
+        for model in view.unlocked.iter() {
+            if *model == Towers::Scrambler && (scramblers + 1) * SCRAMBLER_SHARE > view.towers.len() + 1 { continue; }
+            for &(x, y) in view.free.iter() {
+                let tower = model.instantiate(x, y);
+                let value = coverage(view.network, x, y, tower.range()) * power(&tower);
+                consider(value / model.get_cost() as f32, Action::Build(*model, x, y), model.get_cost());
+            }
+        }
+
+        for tower in view.towers.iter() {
+            let Some(cost) = tower.upgrade_cost() else { continue; };
+            let upgraded = Tower { rank: tower.rank + 1, ..tower.clone() };
+            let gain = coverage(view.network, tower.x, tower.y, upgraded.range()) * power(&upgraded)
+                - coverage(view.network, tower.x, tower.y, tower.range()) * power(tower);
+            consider(gain / cost as f32, Action::Upgrade(tower.x, tower.y), cost);
+        }
+
+        best.filter(|(score, _, _)| *score > 0.).map(|(_, action, cost)| (action, cost))
+    }
+}
+
+impl Strategy for Heuristic {
+    fn name(&self) -> &'static str { "heuristic" }
+
+    fn act(&mut self, view: &BattleView) -> Vec<Action> {
+        let mut actions = collect_packages(view);
+        if let Some((action, cost)) = Heuristic::best(view) {
+            if cost <= view.money { actions.push(action); }
+        }
+        actions
+    }
+}
+
+/// Collects every package but cursed ones.
+pub fn collect_packages(view: &BattleView) -> Vec<Action> {
+    view.packages.iter()
+        .enumerate()
+        .filter(|(_, kind)| **kind != PackageKind::Cursed)
+        .map(|(i, _)| Action::Collect(i))
+        .collect()
+}
+
+/// Length of road in range of a tower on (x, y), in cells.
+pub fn coverage(network: &Network, x: usize, y: usize, range: f32) -> f32 {
+    // Drones fly half a cell above the road
+    let center = vec2(x as f32, y as f32 - 0.5);
+    let radius = range / f32_tile_to_f32(2.);
+    network.routes.iter()
+        .flat_map(|route| route.path.advances_in_circle(center, radius))
+        .map(|(start, end)| end - start)
+        .sum()
+}
+
+/// Rough damage per second of a tower on the drones in its range.
+fn power(tower: &Tower) -> f32 {
+    match tower.model {
+        Towers::Lightning => tower.damage() / tower.reload_delay(),
+        // Bombs hit the drones around their target too
+        Towers::PaintBomb => 2. * tower.damage() / tower.reload_delay(),
+        Towers::Scrambler => 2. * (1. - tower.slow_factor()),
+    }
+}
+
+struct SimDrone {
+    id: u32,
+    class: Drones,
+    stats: Stats,
+    route: usize,
+    advance: f32,
+    /// Speed factor and end of the slowdown
+    slow: Option<(f32, f32)>,
+}
+
+struct SimTower {
+    tower: Tower,
+    coverage: Coverage,
+    /// Seconds before the next shot
+    reload: f32,
+}
+
+/// Shot flying towards its target.
+struct SimShot {
+    kind: Shots,
+    damage: f32,
+    /// Id of the [SimDrone] aimed at
+    target: u32,
+    /// Time of the hit
+    hit: f32,
+}
+
+/// Outcome of a headless battle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Outcome {
+    pub won: bool,
+    pub killed: u32,
+    pub survived: u32,
+    pub lives: u16,
+}
+
+/// Battle rules without rendering: drones follow their route, towers fire when reloaded,
+/// money comes from bounties, interest, wave bonuses and packages.
+/// Shots always hit their target, and stored packages are used at once where they were dropped.
+pub struct Sim {
+    pub network: Network,
+    pub money: u16,
+    pub lives: u16,
+    max_lives: u16,
+    pub time: f32,
+    pub killed: u32,
+    pub survived: u32,
+    unlocked: Vec<Towers>,
+    free: HashSet<(usize, usize)>,
+    towers: Vec<SimTower>,
+    drones: Vec<SimDrone>,
+    next_id: u32,
+    shots: Vec<SimShot>,
+    /// Packages on the road, with their position and the time they expire
+    packages: Vec<(PackageKind, Vec2, f32)>,
+    /// End of the coffee and freeze effects
+    coffee: f32,
+    freeze: f32,
+    /// Decoys and the time they expire
+    decoys: Vec<(Vec2, f32)>,
+    waves: WaveIterator,
+    difficulty: Difficulty,
+    economy: Economy,
+    odds: PackageOdds,
+    /// Wave, survivors before it, and whether its bonus was decided
+    bonus: (usize, u32, bool),
+}
+
+impl Sim {
+    pub fn new(network: Network, waves: WaveIterator, level: u8, difficulty: Difficulty) -> Self {
+        let road: HashSet<(isize, isize)> = network.routes.iter()
+            .flat_map(|route| route.path.cells())
+            .filter(|(x, y)| !is_oob(*x, *y))
+            .collect();
+        let free = buildable_cells(&road).into_iter()
+            .filter(|(x, y)| *x < COLUMNS && *y < ROWS)
+            .map(|(x, y)| (x as usize, y as usize))
+            .collect();
+        let lives = difficulty.scale_lives(CurrentLevel(level).get_lives());
+        Sim {
+            network,
+            money: (200. * difficulty.money_multiplier()) as u16,
+            lives,
+            max_lives: lives,
+            time: 0.,
+            killed: 0,
+            survived: 0,
+            unlocked: Towers::iter().filter(|t| t.unlocked_at() <= level).collect(),
+            free,
+            towers: vec![],
+            drones: vec![],
+            next_id: 0,
+            shots: vec![],
+            packages: vec![],
+            coffee: 0.,
+            freeze: 0.,
+            decoys: vec![],
+            waves,
+            difficulty,
+            economy: Economy::new(level, difficulty),
+            odds: PackageOdds::new(level, difficulty),
+            bonus: (0, 0, false),
+        }
+    }
+
+    /// Battle of a campaign level.
+    pub fn campaign(level: u8, difficulty: Difficulty) -> Self {
+        Sim::new(path_of_level_n(level), WaveIterator::from_level(level), level, difficulty)
+    }
+
+    /// Lets every wave be played, whatever the drones through.
+    pub fn unlimited_lives(&mut self) {
+        self.lives = u16::MAX;
+        self.max_lives = u16::MAX;
+    }
+
+    pub fn view(&self) -> BattleView<'_> {
+        let mut free: Vec<(usize, usize)> = self.free.iter().copied().collect();
+        free.sort();
+        BattleView {
+            network: &self.network,
+            wave: self.waves.wave,
+            money: self.money,
+            unlocked: self.unlocked.clone(),
+            free,
+            towers: self.towers.iter().map(|t| t.tower.clone()).collect(),
+            packages: self.packages.iter().map(|(kind, _, _)| *kind).collect(),
+        }
+    }
+
+    /// Returns false if the action is not possible.
+    pub fn apply(&mut self, action: Action) -> bool {
+        match action {
+            Action::Build(model, x, y) => {
+                if !self.unlocked.contains(&model) || !self.free.contains(&(x, y)) || self.money < model.get_cost() { return false; }
+                self.money -= model.get_cost();
+                self.free.remove(&(x, y));
+                let tower = model.instantiate(x, y);
+                let coverage = Coverage::new(&tower, &self.network);
+                self.towers.push(SimTower { tower, coverage, reload: model.initial_delay() });
+            }
+            Action::Upgrade(x, y) => {
+                let Some(t) = self.towers.iter_mut().find(|t| t.tower.x == x && t.tower.y == y) else { return false; };
+                match t.tower.upgrade_cost() {
+                    Some(cost) if cost <= self.money => {
+                        self.money -= cost;
+                        t.tower.rank += 1;
+                        t.coverage = Coverage::new(&t.tower, &self.network);
+                    }
+                    _ => return false,
+                }
+            }
+            Action::Collect(i) => {
+                let Some((kind, position, expiry)) = self.packages.get_mut(i) else { return false; };
+                if *expiry <= self.time { return false; }
+                // Removed at the next step
+                *expiry = f32::NEG_INFINITY;
+                let (kind, position) = (*kind, *position);
+                self.use_package(kind, position);
+            }
+        }
+        true
+    }
+
+    fn use_package(&mut self, kind: PackageKind, position: Vec2) {
+        let kind = match kind {
+            PackageKind::Mystery => PackageKind::mystery(&mut thread_rng()),
+            kind => kind,
+        };
+        self.money = self.money.saturating_add_signed(rules::package_money(kind, self.money) as i16);
+        match kind {
+            PackageKind::Coffee => self.coffee = self.time + COFFEE_DURATION,
+            PackageKind::Freeze => self.freeze = self.time + FREEZE_DURATION,
+            PackageKind::Repair => if let Some(lives) = rules::repair(self.lives, self.max_lives) { self.lives = lives; },
+            PackageKind::Decoy => self.decoys.push((rules::decoy_position(&self.network, position), self.time + DECOY_DURATION)),
+            PackageKind::Omega => self.blast(position, OMEGA_RANGE, OMEGA_DAMAGES),
+            _ => {}
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.lives == 0 || (self.waves.is_over() && self.drones.is_empty()) || self.time > MAX_DURATION
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        Outcome {
+            won: self.lives > 0 && self.time <= MAX_DURATION,
+            killed: self.killed,
+            survived: self.survived,
+            lives: self.lives,
+        }
+    }
+
+    /// Plays the battle until the end.
+    pub fn run(mut self, strategy: &mut dyn Strategy) -> Outcome {
+        let mut think = 0.;
+        while !self.is_over() {
+            if self.time >= think {
+                think = self.time + THINK_DELAY;
+                let actions = strategy.act(&self.view());
+                for action in actions { self.apply(action); }
+            }
+            self.step(STEP);
+        }
+        self.outcome()
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.time += dt;
+        self.spawn(dt);
+        self.move_drones(dt);
+        self.fire(dt);
+        self.hit();
+        self.remove_drones();
+        self.wave_bonus();
+        let time = self.time;
+        self.packages.retain(|(_, _, expiry)| *expiry > time);
+        self.decoys.retain(|(_, expiry)| *expiry > time);
+    }
+
+    fn move_drones(&mut self, dt: f32) {
+        let positions: Vec<Vec2> = self.drones.iter().map(|d| self.drone_position(d)).collect();
+        for (drone, position) in self.drones.iter_mut().zip(positions) {
+            let slow = drone.slow.filter(|(_, end)| *end > self.time).map(|(factor, _)| factor);
+            for (decoy, _) in self.decoys.iter() {
+                if let Some(factor) = rules::decoy_slow(position, *decoy, slow) { drone.slow = Some((factor, self.time + 0.5)); }
+            }
+        }
+        if self.freeze > self.time { return; }
+        for drone in self.drones.iter_mut() {
+            let factor = match drone.slow {
+                Some((factor, end)) if end > self.time => factor,
+                _ => 1.,
+            };
+            drone.advance += factor * drone.stats.speed * dt;
+        }
+    }
+
+    fn spawn(&mut self, dt: f32) {
+        self.waves.next.tick(Duration::from_secs_f32(dt));
+        if self.waves.next.just_finished() {
+            match self.waves.pop() {
+                Some(WaveIteratorElement::NextWave(t)) => {
+                    self.waves.next = Timer::from_seconds(t, TimerMode::Once);
+                    if self.waves.wave > 1 {
+                        self.money = self.money.saturating_add(self.economy.interest(self.money));
+                    }
+                }
+                Some(WaveIteratorElement::NextDrone(class, lane, t)) => {
+                    self.waves.next = Timer::from_seconds(t, TimerMode::Once);
+                    let mut stats = class.get_stats(self.difficulty);
+                    stats.hp *= self.waves.hp_multiplier;
+                    self.drones.push(SimDrone { id: self.next_id, class, stats, route: self.network.pick_route(lane), advance: 0., slow: None });
+                    self.next_id += 1;
+                }
+                None => {}
+            }
+        }
+
+        // Next wave as soon as the road is clear, as with the auto skip setting
+        if self.drones.is_empty() && self.waves.next.elapsed() > Duration::from_secs(1) && self.waves.next.remaining_secs() >= 2.1 {
+            let elapsed = self.waves.next.duration() - Duration::from_secs_f32(2.);
+            self.waves.next.set_elapsed(elapsed);
+        }
+    }
+
+    fn fire(&mut self, dt: f32) {
+        let centers: Vec<Vec2> = self.drones.iter().map(|d| self.drone_center(d)).collect();
+        let coffee = self.coffee > self.time;
+        for t in self.towers.iter_mut() {
+            t.reload -= dt;
+            if t.reload > 0. { continue; }
+
+            let in_range: Vec<usize> = (0..self.drones.len())
+                .filter(|i| t.coverage.covers(self.drones[*i].class, self.drones[*i].route, self.drones[*i].advance))
+                .collect();
+            let fired = match t.tower.model.get_shot() {
+                Some(kind) => match rules::pick_target(in_range.into_iter().map(|i| (i, self.drones[i].advance))) {
+                    Some(i) => {
+                        let distance = util::tower_center(t.tower.x, t.tower.y).distance(centers[i]);
+                        let hit = self.time + distance / t.tower.shot_speed();
+                        self.shots.push(SimShot { kind, damage: t.tower.damage(), target: self.drones[i].id, hit });
+                        true
+                    }
+                    None => false,
+                },
+                None => {
+                    let (factor, duration) = rules::scramble(&t.tower);
+                    let mut fired = false;
+                    for i in in_range {
+                        self.drones[i].slow = Some((factor, self.time + duration));
+                        fired = true;
+                    }
+                    fired
+                }
+            };
+            if fired { t.reload = rules::reload_delay(&t.tower, coffee, false); }
+        }
+    }
+
+    /// Shots reaching their target, if it is still there.
+    fn hit(&mut self) {
+        let time = self.time;
+        let (landed, flying) = std::mem::take(&mut self.shots).into_iter().partition(|s| s.hit <= time);
+        self.shots = flying;
+        for shot in landed {
+            let Some(i) = self.drones.iter().position(|d| d.id == shot.target) else { continue; };
+            match shot.kind {
+                Shots::Electricity => { rules::damage(&mut self.drones[i].stats.hp, shot.damage, false); }
+                Shots::Bomb => self.blast(self.drone_position(&self.drones[i]), shot.kind.get_bomb_range(), shot.damage),
+            }
+        }
+    }
+
+    fn blast(&mut self, center: Vec2, radius: f32, damage: f32) {
+        let positions: Vec<Vec2> = self.drones.iter().map(|d| self.drone_position(d)).collect();
+        for (drone, position) in self.drones.iter_mut().zip(positions) {
+            if rules::in_blast(position, center, radius) { rules::damage(&mut drone.stats.hp, damage, false); }
+        }
+    }
+
+    /// Bottom left of the drone sprite, where bombs and decoys are measured from.
+    fn drone_position(&self, drone: &SimDrone) -> Vec2 {
+        self.drone_center(drone) - body_size(drone.class.get_tiles()) / 2.
+    }
+
+    /// Center of the drone, in the same coordinates as [util::tower_center].
+    fn drone_center(&self, drone: &SimDrone) -> Vec2 {
+        let p = self.network.route(drone.route).pos(drone.advance).unwrap_or_default();
+        let size = body_size(drone.class.get_tiles());
+        vec2(
+            f32_tile_to_f32(p.x * 2. + 1.),
+            f32_tile_to_f32(p.y * 2. + GUI_HEIGHT as f32 + 1.5) + size.y / 2.,
+        )
+    }
+
+    fn remove_drones(&mut self) {
+        for drone in std::mem::take(&mut self.drones) {
+            if drone.stats.hp <= 0. {
+                self.killed += 1;
+                self.money = self.money.saturating_add(self.economy.bounty(drone.class));
+                let position = self.drone_position(&drone);
+                self.packages.push((self.odds.pick(&mut thread_rng()), position, self.time + PACKAGE_LIFETIME));
+            } else if self.network.route(drone.route).drone_won(drone.advance) {
+                self.survived += 1;
+                self.lives = self.lives.saturating_sub(drone.class.lives_cost());
+            } else {
+                self.drones.push(drone);
+            }
+        }
+    }
+
+    fn wave_bonus(&mut self) {
+        if self.bonus.0 != self.waves.wave { self.bonus = (self.waves.wave, self.survived, false); }
+        if self.bonus.2 || !self.waves.wave_spawned() || !self.drones.is_empty() { return; }
+        self.bonus.2 = true;
+        if self.survived == self.bonus.1 {
+            self.money = self.money.saturating_add(self.economy.wave_bonus);
+        }
+    }
+}
+
+/// Battles won by a strategy.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct WinRate {
+    pub runs: u32,
+    pub wins: u32,
+    pub survived: u32,
+}
+
+impl WinRate {
+    pub fn rate(&self) -> f32 {
+        if self.runs == 0 { 0. } else { self.wins as f32 / self.runs as f32 }
+    }
+}
+
+pub fn win_rate(level: u8, difficulty: Difficulty, strategy: &mut dyn Strategy, runs: u32) -> WinRate {
+    let mut result = WinRate { runs, ..WinRate::default() };
+    for _ in 0..runs {
+        let outcome = Sim::campaign(level, difficulty).run(strategy);
+        if outcome.won { result.wins += 1; }
+        result.survived += outcome.survived;
+    }
+    result
+}
+
+/// Win rate of the [Heuristic] bot on each campaign level and difficulty, as a table.
+pub fn report(levels: u8, runs: u32) -> String {
+    let difficulties = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard, Difficulty::Nightmare];
+    let mut report = format!("{} bot, {} runs", Heuristic.name(), runs);
+    for difficulty in difficulties.iter() { report += &format!(" | {:>9}", difficulty.get_name()); }
+    report += "\n";
+    for level in 1..=levels {
+        report += &format!("{:<24}", CurrentLevel(level).get_name());
+        for difficulty in difficulties.iter() {
+            let rate = win_rate(level, *difficulty, &mut Heuristic, runs);
+            report += &format!(" | {:>8.0}%", rate.rate() * 100.);
+        }
+        report += "\n";
+    }
+    report
+}
+
+#[test]
+fn test_sim_without_towers() {
+    let outcome = Sim::campaign(1, Difficulty::Normal).run(&mut Idle);
+    assert_eq!(outcome.killed, 0);
+    assert!(outcome.survived > 0);
+}
+
+#[test]
+fn test_sim_actions() {
+    let mut sim = Sim::campaign(1, Difficulty::Normal);
+    let money = sim.money;
+    let (x, y) = sim.view().free[0];
+    assert!(!sim.apply(Action::Build(Towers::Scrambler, x, y)));
+    assert!(sim.apply(Action::Build(Towers::Lightning, x, y)));
+    assert!(!sim.apply(Action::Build(Towers::Lightning, x, y)));
+    assert_eq!(sim.money, money - Towers::Lightning.get_cost());
+    assert!(sim.apply(Action::Upgrade(x, y)));
+    assert_eq!(sim.view().towers[0].rank, 2);
+    assert!(!sim.apply(Action::Collect(0)));
+}
+
+#[test]
+fn test_heuristic_plays() {
+    let outcome = Sim::campaign(1, Difficulty::Easy).run(&mut Heuristic);
+    assert!(outcome.killed > 0);
+}
+
+/// Never does anything.
+#[cfg(test)]
+struct Idle;
+
+#[cfg(test)]
+impl Strategy for Idle {
+    fn name(&self) -> &'static str { "idle" }
+
+    fn act(&mut self, _view: &BattleView) -> Vec<Action> { vec![] }
+}
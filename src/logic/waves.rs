@@ -3,34 +3,128 @@ use std::time::Duration;
 use bevy::prelude::{Resource, Timer};
 use bevy::time::TimerMode;
 use lazy_static::lazy_static;
+use rand::{Rng, SeedableRng, thread_rng};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
 use crate::drones::Drones;
+use crate::logic::gen::gen_wave;
 use crate::logic::waves::WaveIteratorElement::{NextDrone, NextWave};
 
-#[derive(Debug, Clone)]
-struct Wave {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Wave {
     /// Spawn time after beginning of wave
     /// first one should be roughly zero
-    timed_departures: Vec<(f32, Drones)>,
+    pub timed_departures: Vec<(f32, Drones)>,
     /// Delay after last spawn
-    end_delay: f32,
+    pub end_delay: f32,
+    /// Spawn lanes of the drones
+    #[serde(default)]
+    pub lanes: Lanes,
+}
+
+/// How the drones of a wave pick their spawn lane.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Lanes {
+    /// Each drone picks a random lane
+    #[default]
+    Any,
+    /// All the drones spawn on the same lane
+    Fixed(usize),
+    /// Drones spawn on each lane in turn
+    Alternate,
+    /// Lane of each drone
+    Each(Vec<usize>),
+}
+
+impl Lanes {
+    fn of_drone(&self, i: usize) -> Option<usize> {
+        match self {
+            Lanes::Any => None,
+            Lanes::Fixed(lane) => Some(*lane),
+            Lanes::Alternate => Some(i),
+            Lanes::Each(lanes) => lanes.get(i).copied(),
+        }
+    }
 }
 
 pub const WAVES_INTERVAL: f32 = 30.;
 
+/// Seconds between the last drone of a wave and the next one, when it is called before the end of the wave
+pub const CALL_DELAY: f32 = 1.;
+
+/// In endless mode, a boss ends every [BOSS_EVERY]th wave
+pub const BOSS_EVERY: usize = 5;
+
 impl<T> From<T> for Wave where T: Into<Vec<(f32, Drones)>> {
     fn from(timed_departures: T) -> Self {
         Wave {
             end_delay: WAVES_INTERVAL,
             timed_departures: timed_departures.into(),
+            lanes: Lanes::Any,
         }
     }
 }
 
+impl Wave {
+    fn on_lanes(self, lanes: Lanes) -> Self {
+        Wave { lanes, ..self }
+    }
+
+    /// Time of the last departure
+    pub fn duration(&self) -> f32 {
+        self.timed_departures.last().map_or(0., |(t, _)| *t)
+    }
+
+    /// Sum of the base hp of the drones
+    pub fn total_hp(&self) -> f32 {
+        self.timed_departures.iter().map(|(_, d)| d.get_default_stats().max_hp).sum()
+    }
+
+    /// Drones per second while the wave is spawning
+    pub fn density(&self) -> f32 {
+        self.timed_departures.len() as f32 / self.duration().max(1.)
+    }
+
+    /// Adds a drone, keeping departures sorted. Returns its index.
+    pub fn add(&mut self, t: f32, drone: Drones) -> usize {
+        self.insert(t, drone, None)
+    }
+
+    pub fn remove(&mut self, i: usize) {
+        self.take(i);
+    }
+
+    /// Moves drone [i] to time [t], keeping departures sorted. Returns its new index.
+    pub fn set_time(&mut self, i: usize, t: f32) -> usize {
+        let ((_, drone), lane) = self.take(i);
+        self.insert(t, drone, lane)
+    }
+
+    /// Removes drone [i] and its lane if each drone has one.
+    fn take(&mut self, i: usize) -> ((f32, Drones), Option<usize>) {
+        let departure = self.timed_departures.remove(i);
+        let lane = match &mut self.lanes {
+            Lanes::Each(lanes) if i < lanes.len() => Some(lanes.remove(i)),
+            _ => None,
+        };
+        (departure, lane)
+    }
+
+    fn insert(&mut self, t: f32, drone: Drones, lane: Option<usize>) -> usize {
+        let i = self.timed_departures.partition_point(|(t0, _)| *t0 <= t);
+        self.timed_departures.insert(i, (t, drone));
+        if let Lanes::Each(lanes) = &mut self.lanes {
+            if i <= lanes.len() { lanes.insert(i, lane.unwrap_or(0)); }
+        }
+        i
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum WaveIteratorElement {
-    /// Spawn a drone now, and wait t seconds
-    NextDrone(Drones, f32),
+    /// Spawn a drone now on a lane (any lane if None), and wait t seconds
+    NextDrone(Drones, Option<usize>, f32),
     /// Spawn a new wave now, and wait t seconds
     NextWave(f32),
 }
@@ -41,24 +135,32 @@ pub struct WaveIterator {
     /// Upcoming elements, in reversed order
     /// i.e. pop gives the next element
     pub upcoming: Vec<WaveIteratorElement>,
+    /// Number of waves started so far
+    pub wave: usize,
+    /// Multiplier applied to the hp of the drones of the current wave
+    pub hp_multiplier: f32,
+    /// Multiplier of the next wave, applied when it starts
+    pub next_hp_multiplier: f32,
+    /// Generator of endless mode waves
+    endless: Option<StdRng>,
 }
 
 impl WaveIterator {
-    fn from_waves(waves: &Vec<Wave>) -> WaveIterator {
+    pub fn from_waves(waves: &Vec<Wave>) -> WaveIterator {
         let mut result = Vec::new();
         for wave in waves.iter() {
-            let mut prev: Option<(Drones, f32)> = None;
+            let mut prev: Option<(Drones, Option<usize>, f32)> = None;
 
-            for (t1, drone) in wave.timed_departures.iter() {
+            for (i, (t1, drone)) in wave.timed_departures.iter().enumerate() {
                 match prev {
-                    Some((drone, t0)) => { result.push(NextDrone(drone, t1 - t0)) }
+                    Some((drone, lane, t0)) => { result.push(NextDrone(drone, lane, t1 - t0)) }
                     None => { result.push(NextWave(*t1)) }
                 }
-                prev = Some((*drone, *t1));
+                prev = Some((*drone, wave.lanes.of_drone(i), *t1));
             }
 
             match prev {
-                Some((drone, _)) => { result.push(NextDrone(drone, wave.end_delay)) }
+                Some((drone, lane, _)) => { result.push(NextDrone(drone, lane, wave.end_delay)) }
                 None => { panic!("Waves should not be empty.") }
             }
         }
@@ -68,23 +170,156 @@ impl WaveIterator {
         WaveIterator {
             next: Timer::new(Duration::from_secs_f32(2.), TimerMode::Once),
             upcoming: result,
+            wave: 0,
+            hp_multiplier: 1.,
+            next_hp_multiplier: 1.,
+            endless: None,
         }
     }
 
-    pub fn from_level(level: u8) -> WaveIterator {
-        WaveIterator::from_waves(
-            match level {
-                1 => &WAVES_1,
-                2 => &WAVES_2,
-                3 => &WAVES_3,
-                4 => &WAVES_4,
-                5 => &WAVES_5,
-                _ => &WAVES,
+    /// Waves never stop coming in endless mode.
+    pub fn endless(seed: u64) -> WaveIterator {
+        let mut waves = WaveIterator {
+            endless: Some(StdRng::seed_from_u64(seed)),
+            ..WaveIterator::from_waves(&vec![])
+        };
+        waves.refill();
+        waves
+    }
+
+    /// Returns the next element.
+    pub fn pop(&mut self) -> Option<WaveIteratorElement> {
+        let next = self.upcoming.pop();
+        if let Some(NextWave(_)) = next {
+            self.wave += 1;
+            self.hp_multiplier = self.next_hp_multiplier;
+        }
+        self.refill();
+        next
+    }
+
+    /// Generates the next wave in advance in endless mode.
+    fn refill(&mut self) {
+        if !self.upcoming.is_empty() { return; }
+        let Some(rng) = &mut self.endless else { return; };
+        let (wave, hp_multiplier) = endless_wave(self.wave + 1, rng);
+        self.upcoming = WaveIterator::from_waves(&vec![wave]).upcoming;
+        self.next_hp_multiplier = hp_multiplier;
+    }
+
+    /// Drones of the next wave and their count, in order of appearance.
+    pub fn next_wave(&self) -> Vec<(Drones, u16)> {
+        let mut drones: Vec<(Drones, u16)> = vec![];
+        let elements = self.upcoming.iter().rev()
+            .skip_while(|e| matches!(e, NextDrone(..)))
+            .skip(1)
+            .take_while(|e| matches!(e, NextDrone(..)));
+        for element in elements {
+            let NextDrone(drone, _, _) = element else { continue; };
+            match drones.iter_mut().find(|(d, _)| d == drone) {
+                Some((_, count)) => *count += 1,
+                None => drones.push((*drone, 1)),
             }
-        )
+        }
+        drones
+    }
+
+    /// Returns true when every drone has been spawned.
+    pub fn is_over(&self) -> bool {
+        self.upcoming.is_empty() && self.endless.is_none()
+    }
+
+    /// Returns true when the next element starts a new wave.
+    pub fn next_is_wave(&self) -> bool {
+        match self.upcoming.last() {
+            Some(NextWave(_)) => true,
+            Some(NextDrone(..)) => false,
+            None => self.endless.is_some(),
+        }
+    }
+
+    /// Starts the next wave now if the current one has been spawned,
+    /// or right after the drones of the current wave which are not spawned yet.
+    /// Returns the skipped time in seconds.
+    pub fn call_next_wave(&mut self) -> Option<f32> {
+        if self.next_is_wave() {
+            let skipped = self.next.remaining_secs();
+            let duration = self.next.duration();
+            self.next.set_elapsed(duration);
+            return Some(skipped);
+        }
+        // Shortens the wait after the last drone of the wave
+        let left = self.upcoming.iter().rev().take_while(|e| matches!(e, NextDrone(..))).count();
+        if left == 0 { return None; }
+        let i = self.upcoming.len() - left;
+        let NextDrone(_, _, delay) = &mut self.upcoming[i] else { return None; };
+        if *delay <= CALL_DELAY { return None; }
+        let skipped = *delay - CALL_DELAY;
+        *delay = CALL_DELAY;
+        Some(skipped)
+    }
+
+    /// Drops the drones of the current wave which are not spawned yet and starts the next one now.
+    /// Only the console skips waves.
+    #[cfg(any(feature = "dev", test))]
+    pub fn skip_wave(&mut self) {
+        while let Some(NextDrone(..)) = self.upcoming.last() { self.upcoming.pop(); }
+        self.refill();
+        let duration = self.next.duration();
+        self.next.set_elapsed(duration);
+    }
+
+    /// Returns true when every drone of the current wave has been spawned.
+    pub fn wave_spawned(&self) -> bool {
+        self.wave > 0 && matches!(self.upcoming.last(), None | Some(NextWave(_)))
+    }
+
+    pub fn from_level(level: u8) -> WaveIterator {
+        WaveIterator::from_waves(&waves_of_level(level))
+    }
+
+    /// Skips the waves before [first], the wave count starts from there.
+    pub fn starting_at(waves: &Vec<Wave>, first: usize) -> WaveIterator {
+        let first = first.min(waves.len().saturating_sub(1));
+        WaveIterator {
+            wave: first,
+            ..WaveIterator::from_waves(&waves[first..].to_vec())
+        }
+    }
+}
+
+/// Waves of a campaign level, generated after the last one.
+pub fn waves_of_level(level: u8) -> Vec<Wave> {
+    match level {
+        1 => WAVES_1.clone(),
+        2 => WAVES_2.clone(),
+        3 => WAVES_3.clone(),
+        4 => WAVES_4.clone(),
+        5 => WAVES_5.clone(),
+        6 => WAVES.clone(),
+        n => gen_waves(n, &mut thread_rng()),
     }
 }
 
+/// Waves of generated levels, getting harder along the level and with the level number.
+fn gen_waves(level: u8, rng: &mut impl Rng) -> Vec<Wave> {
+    let count = 12 + level as usize / 2;
+    let first = level.saturating_sub(7) as f32 * 0.5;
+    (0..count)
+        .map(|i| {
+            let wave = Wave::from(gen_wave(first + 1.2 * i as f32, rng));
+            let drones = wave.timed_departures.len();
+            // Waves come from one lane, then split between the lanes, then from all the lanes in turn
+            let lanes = match i % 3 {
+                0 => Lanes::Fixed(i / 3),
+                1 => Lanes::Each((0..drones).map(|d| 2 * d / drones).collect()),
+                _ => Lanes::Alternate,
+            };
+            wave.on_lanes(lanes)
+        })
+        .collect()
+}
+
 lazy_static! {
     static ref WAVES: Vec<Wave> = vec![
         [
@@ -551,6 +786,139 @@ lazy_static! {
     ];
 }
 
+/// Wave [n] of endless mode (starting from 1), and the hp multiplier of its drones.
+/// The drone count and mix stop growing after some time, but the hp multiplier doesn't.
+fn endless_wave(n: usize, rng: &mut impl Rng) -> (Wave, f32) {
+    let mut departures = gen_wave((0.8 * n as f32).min(24.), rng);
+    if n % BOSS_EVERY == 0 {
+        let t = departures.last().map_or(0., |(t, _)| *t) + 10.;
+        departures.push((t, if n < 3 * BOSS_EVERY { Drones::Big2 } else { Drones::Invader }));
+    }
+    let lanes = if n % 2 == 0 { Lanes::Alternate } else { Lanes::Any };
+    (Wave::from(departures).on_lanes(lanes), 1.05f32.powi(n as i32))
+}
+
+#[test]
+fn test_endless_waves() {
+    let mut waves = WaveIterator::endless(42);
+    let mut other = WaveIterator::endless(42);
+    for _ in 0..1000 {
+        let (a, b) = (waves.pop(), other.pop());
+        assert!(a.is_some());
+        assert_eq!(format!("{:?}", a), format!("{:?}", b));
+    }
+    assert!(waves.wave > 10);
+    assert!(!waves.is_over());
+}
+
+#[test]
+fn test_call_next_wave() {
+    let mut waves = WaveIterator::from_waves(&vec![
+        [(0.0, Drones::Simple1), (1.0, Drones::Simple1)].into(),
+        [(0.0, Drones::Simple2)].into(),
+    ]);
+    assert_eq!(waves.call_next_wave(), Some(2.));
+    waves.pop();
+    // During the wave, the next one comes right after its last drone
+    assert_eq!(waves.call_next_wave(), Some(WAVES_INTERVAL - CALL_DELAY));
+    assert_eq!(waves.call_next_wave(), None);
+    waves.pop();
+    let Some(NextDrone(_, _, delay)) = waves.pop() else { panic!("The last drone of the wave is missing.") };
+    assert_eq!(delay, CALL_DELAY);
+    assert_eq!(waves.wave, 1);
+    waves.next = Timer::from_seconds(WAVES_INTERVAL, TimerMode::Once);
+    assert_eq!(waves.call_next_wave(), Some(WAVES_INTERVAL));
+    assert_eq!(waves.next.remaining_secs(), 0.);
+    waves.pop();
+    waves.pop();
+    assert_eq!(waves.call_next_wave(), None);
+    assert!(WaveIterator::endless(1).next_is_wave());
+}
+
+#[test]
+fn test_next_wave() {
+    let mut waves = WaveIterator::from_waves(&vec![
+        [(0.0, Drones::Simple1), (1.0, Drones::Medium1), (2.0, Drones::Simple1)].into(),
+        [(0.0, Drones::Simple2)].into(),
+    ]);
+    assert_eq!(waves.next_wave(), vec![(Drones::Simple1, 2), (Drones::Medium1, 1)]);
+    waves.pop();
+    waves.pop();
+    assert_eq!(waves.next_wave(), vec![(Drones::Simple2, 1)]);
+    for _ in 0..4 { waves.pop(); }
+    assert_eq!(waves.next_wave(), vec![]);
+
+    let mut endless = WaveIterator::endless(3);
+    while endless.wave < BOSS_EVERY - 1 || !endless.wave_spawned() { endless.pop(); }
+    assert!(endless.next_wave().iter().any(|(d, _)| d.is_boss()));
+}
+
+#[test]
+fn test_wave_spawned() {
+    let mut waves = WaveIterator::from_waves(&vec![
+        [(0.0, Drones::Simple1), (1.0, Drones::Simple1)].into(),
+        [(0.0, Drones::Simple2)].into(),
+    ]);
+    assert!(!waves.wave_spawned());
+    waves.pop();
+    assert!(!waves.wave_spawned());
+    waves.pop();
+    assert!(!waves.wave_spawned());
+    waves.pop();
+    assert!(waves.wave_spawned());
+    waves.pop();
+    assert_eq!(waves.wave, 2);
+    assert!(!waves.wave_spawned());
+    waves.pop();
+    assert!(waves.wave_spawned());
+}
+
+#[test]
+fn test_edit_wave() {
+    let mut wave: Wave = [(0.0, Drones::Simple1), (2.0, Drones::Medium1), (4.0, Drones::Big1)].into();
+    assert_eq!(wave.duration(), 4.);
+    assert_eq!(wave.density(), 0.75);
+    assert_eq!(wave.set_time(0, 3.), 1);
+    assert_eq!(wave.timed_departures[1], (3., Drones::Simple1));
+    assert_eq!(wave.add(1., Drones::Simple2), 0);
+    wave.remove(3);
+    assert_eq!(wave.duration(), 3.);
+    assert_eq!(wave.total_hp(), Drones::Medium1.get_default_stats().max_hp + 2. * Drones::Simple1.get_default_stats().max_hp);
+
+    let mut lanes: Wave = [(0.0, Drones::Simple1), (1.0, Drones::Simple2)].into();
+    lanes.lanes = Lanes::Each(vec![0, 1]);
+    lanes.set_time(0, 2.);
+    assert!(matches!(&lanes.lanes, Lanes::Each(l) if *l == vec![1, 0]));
+}
+
+#[test]
+fn test_starting_at() {
+    let waves: Vec<Wave> = vec![
+        [(0.0, Drones::Simple1)].into(),
+        [(0.0, Drones::Simple2)].into(),
+        [(0.0, Drones::Simple3)].into(),
+    ];
+    let mut iterator = WaveIterator::starting_at(&waves, 1);
+    assert!(matches!(iterator.pop(), Some(NextWave(_))));
+    assert_eq!(iterator.wave, 2);
+    assert!(matches!(iterator.pop(), Some(NextDrone(Drones::Simple2, _, _))));
+}
+
+#[test]
+fn test_skip_wave() {
+    let waves: Vec<Wave> = vec![
+        [(0.0, Drones::Simple1), (1.0, Drones::Simple1)].into(),
+        [(0.0, Drones::Simple2)].into(),
+    ];
+    let mut iterator = WaveIterator::from_waves(&waves);
+    assert!(matches!(iterator.pop(), Some(NextWave(_))));
+    assert!(matches!(iterator.pop(), Some(NextDrone(Drones::Simple1, _, _))));
+    iterator.skip_wave();
+    assert!(iterator.next_is_wave());
+    assert!(matches!(iterator.pop(), Some(NextWave(_))));
+    assert_eq!(iterator.wave, 2);
+}
+
 #[test]
 fn ensure_waves_are_sorted() {
     let check_wave = |w: &Wave| {
@@ -566,4 +934,16 @@ fn ensure_waves_are_sorted() {
     for wave in WAVES_3.iter() { check_wave(wave); }
     for wave in WAVES_4.iter() { check_wave(wave); }
     for wave in WAVES_5.iter() { check_wave(wave); }
+    for wave in gen_waves(10, &mut thread_rng()).iter() { check_wave(wave); }
+}
+
+#[test]
+fn test_gen_waves_lanes() {
+    let waves = gen_waves(8, &mut StdRng::seed_from_u64(27));
+    assert_eq!(waves[0].lanes, Lanes::Fixed(0));
+    assert_eq!(waves[2].lanes, Lanes::Alternate);
+    assert_eq!(waves[3].lanes, Lanes::Fixed(1));
+    let Lanes::Each(lanes) = &waves[1].lanes else { panic!("{:?}", waves[1].lanes) };
+    assert_eq!(lanes.len(), waves[1].timed_departures.len());
+    assert!(lanes.windows(2).all(|l| l[0] <= l[1]) && lanes.iter().all(|l| *l < 2));
 }
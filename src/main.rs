@@ -1,17 +1,29 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 use bevy_pkv::PkvStore;
 use bevy_text_mode::TextModePlugin;
 
 use graphics::palette::Palette;
 
+use crate::attract::AttractPlugin;
 use crate::battle::BattlePlugin;
+use crate::bot::BotPlugin;
 use crate::collision::CollisionPlugin;
+use crate::editor::EditorPlugin;
+use crate::event_log::EventLogPlugin;
 use crate::game_over::GameOverPlugin;
 use crate::graphics::GraphicsPlugin;
-use crate::level_select::LevelSelectPlugin;
+use crate::level_select::{CurrentLevel, LEVELS, LevelSelectPlugin};
+use crate::logic::difficulty::Difficulty;
+use crate::logic::optimizer::{Budget, PLAN_FILE};
+use crate::logic::score::Record;
 use crate::music::MusicPlugin;
+use crate::sandbox::SandboxPlugin;
+use crate::settings::{Settings, SettingsPlugin};
 use crate::util::size;
 use crate::util::size::tile_to_f32;
+use crate::wave_editor::WaveEditorPlugin;
 
 mod util;
 mod graphics;
@@ -24,6 +36,15 @@ mod collision;
 mod game_over;
 mod music;
 mod level_select;
+mod settings;
+mod editor;
+mod wave_editor;
+mod sandbox;
+mod event_log;
+mod bot;
+mod attract;
+#[cfg(feature = "dev")]
+mod console;
 
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 pub enum GameState {
@@ -32,15 +53,109 @@ pub enum GameState {
     Select,
     Battle,
     GameOver,
+    Settings,
+    Editor,
+    WaveEditor,
 }
 
 #[derive(Resource)]
 pub struct Progress {
     pub level_unlocked: u8,
+    /// Difficulties each level was cleared on, as masks of [Difficulty::bit]
+    cleared: Vec<u8>,
+    /// Best score of each level and difficulty
+    records: Vec<Record>,
+}
+
+impl Progress {
+    pub fn cleared(&self, level: u8) -> u8 {
+        self.cleared.get(level as usize).copied().unwrap_or(0)
+    }
+
+    pub fn clear(&mut self, level: u8, difficulty: Difficulty, pkv: &mut PkvStore) {
+        if self.cleared.len() <= level as usize { self.cleared.resize(level as usize + 1, 0); }
+        self.cleared[level as usize] |= difficulty.bit();
+        let _ = pkv.set("cleared", &self.cleared);
+    }
+
+    pub fn record(&self, level: u8, difficulty: Difficulty) -> Option<Record> {
+        self.records.iter().find(|r| r.level == level && r.difficulty == difficulty).copied()
+    }
+
+    /// Saves [record] if it beats the previous one, returns true in this case.
+    pub fn save_record(&mut self, record: Record, pkv: &mut PkvStore) -> bool {
+        if let Some(best) = self.record(record.level, record.difficulty) {
+            if best.score >= record.score { return false; }
+        }
+        self.records.retain(|r| r.level != record.level || r.difficulty != record.difficulty);
+        self.records.push(record);
+        let _ = pkv.set("records", &self.records);
+        true
+    }
+
+    /// Unlocks the levels after the last one unlocked and cleared, as long as there are enough stars.
+    /// The stars are only checked here, so that the levels unlocked before are kept open.
+    pub fn unlock(&mut self, pkv: &mut PkvStore) {
+        let before = self.level_unlocked;
+        while self.cleared(self.level_unlocked) != 0
+            && self.stars_total() >= CurrentLevel(self.level_unlocked + 1).required_stars() {
+            self.level_unlocked += 1;
+        }
+        if self.level_unlocked != before { let _ = pkv.set("level", &self.level_unlocked); }
+    }
+
+    /// Sum of the best stars of each level, on any difficulty
+    pub fn stars_total(&self) -> u8 {
+        let mut best: HashMap<u8, u8> = HashMap::new();
+        for r in self.records.iter() {
+            let stars = best.entry(r.level).or_default();
+            *stars = (*stars).max(r.stars);
+        }
+        best.values().sum()
+    }
 }
 
 fn main() {
-    App::new()
+    // Headless win rates of the bot, e.g. `--bot-report 20` for 20 runs per level and difficulty
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|a| a == "--bot-report") {
+        let runs = args.get(i + 1).and_then(|n| n.parse().ok()).unwrap_or(10);
+        print!("{}", logic::sim::report(LEVELS, runs));
+        return;
+    }
+    // Best tower layout of a campaign level, e.g. `--optimize 3 200,300,400 hard` with the euros available by each wave,
+    // saved to the data folder
+    if let Some(i) = args.iter().position(|a| a == "--optimize") {
+        let level = args.get(i + 1).and_then(|n| n.parse::<u8>().ok()).unwrap_or(1).clamp(1, 6);
+        let mut budget = Budget::default();
+        let mut difficulty = Difficulty::Normal;
+        for arg in args.iter().skip(i + 2).take(2) {
+            match Difficulty::from_name(arg) {
+                Some(d) => difficulty = d,
+                None => budget = Budget::parse(arg),
+            }
+        }
+        let report = logic::optimizer::report(level, difficulty, &budget);
+        print!("{}", report);
+        if let Some(path) = util::data_path(PLAN_FILE) {
+            if std::fs::write(&path, &report).is_ok() { println!("# Saved to {}", path.display()); }
+        }
+        return;
+    }
+    // Plays the saved plan headless, e.g. `--replay 3 hard`
+    if let Some(i) = args.iter().position(|a| a == "--replay") {
+        let level = args.get(i + 1).and_then(|n| n.parse::<u8>().ok()).unwrap_or(1).clamp(1, 6);
+        let difficulty = args.get(i + 2).and_then(|d| Difficulty::from_name(d)).unwrap_or_default();
+        let script = util::data_path(PLAN_FILE).and_then(|p| std::fs::read_to_string(p).ok()).unwrap_or_default();
+        match logic::optimizer::replay(level, difficulty, &script, 10) {
+            Ok(report) => print!("{}", report),
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
+
+    let mut app = App::new();
+    app
         .insert_resource(ClearColor(Palette::E.into()))
         .insert_resource(Msaa::Off)
         .add_plugins(DefaultPlugins
@@ -67,8 +182,19 @@ fn main() {
         .add_plugin(CollisionPlugin)
         .add_plugin(GameOverPlugin)
         .add_plugin(LevelSelectPlugin)
-        .add_startup_system(init)
-        .run();
+        .add_plugin(SettingsPlugin)
+        .add_plugin(EditorPlugin)
+        .add_plugin(WaveEditorPlugin)
+        .add_plugin(SandboxPlugin)
+        .add_plugin(EventLogPlugin)
+        .add_plugin(BotPlugin)
+        .add_plugin(AttractPlugin)
+        .add_startup_system(init);
+
+    #[cfg(feature = "dev")]
+    app.add_plugin(console::ConsolePlugin);
+
+    app.run();
 }
 
 fn init(mut commands: Commands, pkv: Res<PkvStore>) {
@@ -84,9 +210,13 @@ fn init(mut commands: Commands, pkv: Res<PkvStore>) {
         ..Default::default()
     });
 
+    let cleared = pkv.get::<Vec<u8>>("cleared").unwrap_or_default();
+    let records = pkv.get::<Vec<Record>>("records").unwrap_or_default();
     if let Ok(level) = pkv.get::<u8>("level") {
-        commands.insert_resource(Progress { level_unlocked: level });
+        commands.insert_resource(Progress { level_unlocked: level, cleared, records });
     } else {
-        commands.insert_resource(Progress { level_unlocked: 1 });
+        commands.insert_resource(Progress { level_unlocked: 1, cleared, records });
     }
+
+    commands.insert_resource(Settings::load(&pkv));
 }
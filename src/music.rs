@@ -4,6 +4,7 @@ use bevy::input::Input;
 use bevy::prelude::{Commands, EventReader, EventWriter, KeyCode, Res, Resource};
 use bevy_kira_audio::{AudioApp, AudioChannel, AudioControl, AudioSource};
 use rand::{RngCore, thread_rng};
+use serde::{Deserialize, Serialize};
 
 use crate::graphics::loading::Ost;
 
@@ -24,7 +25,7 @@ impl Plugin for MusicPlugin {
     }
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BGM {
     Title,
     Theme,
@@ -43,6 +44,16 @@ impl BGM {
             BGM::Pause => ost.bgm_pause.clone(),
         }
     }
+
+    pub const fn get_name(&self) -> &'static str {
+        match self {
+            BGM::Title => "Title",
+            BGM::Theme => "Theme",
+            BGM::ThemeMadness => "Madness",
+            BGM::Boss => "Boss",
+            BGM::Pause => "Pause",
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -50,6 +61,13 @@ pub enum SFX {
     Hit,
     PackageMalus,
     PackageBonus,
+    PackageCommon,
+    PackageCoffee,
+    PackageFreeze,
+    PackageRepair,
+    PackageMystery,
+    PackageDecoy,
+    PackageOmega,
     TowerAura,
     TowerBomb,
     TowerShot,
@@ -72,6 +90,12 @@ impl SFX {
             }
             SFX::PackageMalus => ost.sfx_package_malus.clone(),
             SFX::PackageBonus => ost.sfx_package_bonus.clone(),
+            SFX::PackageCommon | SFX::PackageMystery => SFX::PackageBonus.handle(ost),
+            SFX::PackageCoffee => SFX::TowerShot.handle(ost),
+            SFX::PackageFreeze => SFX::TowerAura.handle(ost),
+            SFX::PackageRepair => SFX::UpgradeTower.handle(ost),
+            SFX::PackageDecoy => SFX::PlaceTower.handle(ost),
+            SFX::PackageOmega => SFX::TowerBomb.handle(ost),
             SFX::TowerAura => ost.sfx_tower_aura.clone(),
             SFX::TowerBomb => ost.sfx_bomb.clone(),
             SFX::TowerShot => ost.sfx_shot.clone(),
@@ -82,6 +106,20 @@ impl SFX {
             SFX::Pause => ost.sfx_pause.clone(),
         }
     }
+
+    /// Package sounds reuse other samples at another pitch so that each kind sounds different
+    const fn playback_rate(&self) -> f64 {
+        match self {
+            SFX::PackageCommon => 1.25,
+            SFX::PackageMystery => 0.75,
+            SFX::PackageCoffee => 1.5,
+            SFX::PackageFreeze => 0.6,
+            SFX::PackageRepair => 1.2,
+            SFX::PackageDecoy => 0.8,
+            SFX::PackageOmega => 0.7,
+            _ => 1.,
+        }
+    }
 }
 
 #[derive(Resource)]
@@ -167,6 +205,6 @@ fn update(
 
     // Play SFXs
     for PlaySfxEvent(sfx) in sfx_events.iter() {
-        sfx_channel.play(sfx.handle(&ost));
+        sfx_channel.play(sfx.handle(&ost)).with_playback_rate(sfx.playback_rate());
     }
 }
\ No newline at end of file
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use strum::IntoEnumIterator;
+
+use crate::{GameState, util};
+use crate::battle::{BattleUI, Lives, Money, SANDBOX_MONEY, spawn_drone};
+use crate::collision::body_size;
+use crate::drones::Drones;
+use crate::graphics::{MainBundle, sprite_from_tile};
+use crate::graphics::grid::CurrentPath;
+use crate::graphics::loading::{Fonts, Textures};
+use crate::graphics::package::PackageOdds;
+use crate::graphics::palette::Palette;
+use crate::graphics::text::{TextStyles, ttf_anchor};
+use crate::graphics::transition::Transition;
+use crate::level_select::GameMode;
+use crate::logic::difficulty::Difficulty;
+use crate::logic::dps::DpsMeter;
+use crate::logic::event_log::GameEvent;
+use crate::settings::Settings;
+use crate::tower::Tower;
+use crate::util::is_in;
+use crate::util::size::f32_tile_to_f32;
+
+pub struct SandboxPlugin;
+
+impl Plugin for SandboxPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system(setup.in_schedule(OnEnter(GameState::Battle)))
+            .add_systems((refill, update_palette, spawn_bursts, update_toggles, update_dps)
+                .in_set(OnUpdate(GameState::Battle)))
+            .add_system(clean.in_schedule(OnExit(GameState::Battle)))
+        ;
+    }
+}
+
+/// State of a sandbox battle, only present in [GameMode::Sandbox].
+#[derive(Resource, Default)]
+pub struct Sandbox {
+    /// Drones take no damage
+    pub invincible: bool,
+    /// Towers fire every frame
+    pub no_cooldown: bool,
+    /// Damage dealt by each tower
+    dps: HashMap<Entity, DpsMeter>,
+    /// Drones of the bursts left to spawn, with their delay after the previous one
+    queue: Vec<(Drones, f32)>,
+    /// Time until the next drone of the queue
+    next: f32,
+}
+
+/// Number of drones in a burst
+const BURST_SIZE: usize = 10;
+/// Seconds between two drones of a burst
+const BURST_DELAY: f32 = 0.5;
+/// Scale of the drone icons
+const ICON_SCALE: f32 = 0.5;
+/// Y of the sandbox controls, in tiles
+const CONTROLS_Y: f32 = util::size::HEIGHT as f32 - 1.;
+
+#[derive(Component)]
+struct PaletteIcon(Drones);
+
+#[derive(Component)]
+struct HintText;
+
+#[derive(Component, Copy, Clone, PartialEq)]
+enum Toggle {
+    Invincible,
+    NoCooldown,
+    Quit,
+}
+
+impl Toggle {
+    fn get_text(&self, sandbox: &Sandbox) -> String {
+        let on_off = |b: bool| if b { "ON" } else { "OFF" };
+        match self {
+            Toggle::Invincible => format!("Invincible: {} (I)", on_off(sandbox.invincible)),
+            Toggle::NoCooldown => format!("No cooldown: {} (C)", on_off(sandbox.no_cooldown)),
+            Toggle::Quit => "Quit".to_string(),
+        }
+    }
+
+    /// Position in tiles, between the drone icons and the inventory
+    fn get_pos(&self) -> (f32, f32) {
+        match self {
+            Toggle::Invincible => (17.5, CONTROLS_Y),
+            Toggle::NoCooldown => (17.5, CONTROLS_Y - 1.),
+            Toggle::Quit => (1., CONTROLS_Y - 1.),
+        }
+    }
+}
+
+/// Damage per second of a tower, shown above it
+#[derive(Component)]
+struct DpsText(Entity);
+
+fn setup(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    fonts: Res<Fonts>,
+    textures: Res<Textures>,
+) {
+    if *mode != GameMode::Sandbox { return; }
+    let sandbox = Sandbox::default();
+
+    for (i, drone) in Drones::iter().enumerate() {
+        let mut bundle = MainBundle::from_xyz(
+            f32_tile_to_f32(1. + 1.5 * i as f32), f32_tile_to_f32(CONTROLS_Y - 0.5),
+            util::z_pos::GUI_FG,
+        );
+        bundle.transform.scale = Vec3::splat(ICON_SCALE);
+        commands
+            .spawn(bundle)
+            .with_children(|builder| sprite_from_tile(builder, drone.get_tiles(), &textures.tileset, 0.))
+            .insert(PaletteIcon(drone))
+            .insert(BattleUI);
+    }
+
+    for toggle in [Toggle::Invincible, Toggle::NoCooldown, Toggle::Quit] {
+        let (x, y) = toggle.get_pos();
+        commands
+            .spawn(ttf_anchor(
+                f32_tile_to_f32(x), f32_tile_to_f32(y),
+                util::z_pos::GUI_FG,
+                &toggle.get_text(&sandbox),
+                TextStyles::Body, &fonts, Palette::D,
+                Anchor::CenterLeft,
+            ))
+            .insert(toggle)
+            .insert(BattleUI);
+    }
+
+    commands
+        .spawn(ttf_anchor(
+            f32_tile_to_f32(1.), f32_tile_to_f32(CONTROLS_Y - 2.),
+            util::z_pos::GUI_FG,
+            "",
+            TextStyles::Body, &fonts, Palette::B,
+            Anchor::CenterLeft,
+        ))
+        .insert(HintText)
+        .insert(BattleUI);
+
+    commands.insert_resource(sandbox);
+}
+
+/// Money and lives never run out.
+fn refill(
+    sandbox: Option<Res<Sandbox>>,
+    mut money: ResMut<Money>,
+    mut lives: ResMut<Lives>,
+) {
+    if sandbox.is_none() { return; }
+    if money.0 != SANDBOX_MONEY { money.0 = SANDBOX_MONEY; }
+    if lives.current != lives.max { lives.current = lives.max; }
+}
+
+/// Left click on a drone spawns it, right click spawns a burst.
+fn update_palette(
+    mut commands: Commands,
+    sandbox: Option<ResMut<Sandbox>>,
+    mut events: EventWriter<GameEvent>,
+    icons: Query<(&PaletteIcon, &GlobalTransform)>,
+    mut hint: Query<&mut Text, With<HintText>>,
+    windows: Query<&Window>,
+    mouse: Res<Input<MouseButton>>,
+    textures: Res<Textures>,
+    settings: Res<Settings>,
+    difficulty: Res<Difficulty>,
+    odds: Res<PackageOdds>,
+    path: Res<CurrentPath>,
+) {
+    let Some(mut sandbox) = sandbox else { return; };
+    let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
+    let Ok(mut hint) = hint.get_single_mut() else { return; };
+
+    let hovered = icons.iter().find(|(icon, pos)| {
+        is_in(cursor_pos, pos.translation().truncate(), body_size(icon.0.get_tiles()) * ICON_SCALE)
+    });
+    let value = match hovered {
+        Some((icon, _)) => format!("{} ({}): click to spawn, right click for {}", icon.0.get_name(), icon.0.get_traits(), BURST_SIZE),
+        None => String::new(),
+    };
+    if hint.sections[0].value != value { hint.sections[0].value = value; }
+
+    let Some((icon, _)) = hovered else { return; };
+    if mouse.just_pressed(MouseButton::Left) {
+        let route = path.0.pick_route(None);
+        let id = spawn_drone(&mut commands, icon.0, route, 1., *difficulty, &settings, &odds, &textures.tileset);
+        events.send(GameEvent::DroneSpawned { id, drone: icon.0, route });
+    } else if mouse.just_pressed(MouseButton::Right) {
+        sandbox.queue.extend((0..BURST_SIZE).map(|_| (icon.0, BURST_DELAY)));
+    }
+}
+
+fn spawn_bursts(
+    mut commands: Commands,
+    sandbox: Option<ResMut<Sandbox>>,
+    mut events: EventWriter<GameEvent>,
+    time: Res<Time>,
+    textures: Res<Textures>,
+    settings: Res<Settings>,
+    difficulty: Res<Difficulty>,
+    odds: Res<PackageOdds>,
+    path: Res<CurrentPath>,
+) {
+    let Some(mut sandbox) = sandbox else { return; };
+    sandbox.next -= time.delta_seconds();
+    if sandbox.next > 0. || sandbox.queue.is_empty() { return; }
+    let (drone, delay) = sandbox.queue.remove(0);
+    sandbox.next = delay;
+    let route = path.0.pick_route(None);
+    let id = spawn_drone(&mut commands, drone, route, 1., *difficulty, &settings, &odds, &textures.tileset);
+    events.send(GameEvent::DroneSpawned { id, drone, route });
+}
+
+fn update_toggles(
+    mut commands: Commands,
+    sandbox: Option<ResMut<Sandbox>>,
+    mut toggles: Query<(&Toggle, &Transform, &mut Text)>,
+    windows: Query<&Window>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    transition: Option<Res<Transition>>,
+) {
+    let Some(mut sandbox) = sandbox else { return; };
+    if transition.is_some() { return; }
+
+    if keys.just_pressed(KeyCode::I) { sandbox.invincible = !sandbox.invincible; }
+    if keys.just_pressed(KeyCode::C) { sandbox.no_cooldown = !sandbox.no_cooldown; }
+
+    let cursor_pos = util::cursor_pos(windows);
+    for (toggle, pos, mut text) in toggles.iter_mut() {
+        let value = toggle.get_text(&sandbox);
+        let size = Vec2::new(f32_tile_to_f32(0.7 * value.chars().count() as f32), f32_tile_to_f32(1.));
+        let bottom_left = Vec2::new(pos.translation.x, pos.translation.y - size.y / 2.);
+        let hovered = cursor_pos.map_or(false, |c| is_in(c, bottom_left, size));
+        text.sections[0].style.color = if hovered { Palette::B.into() } else { Palette::D.into() };
+        if hovered && mouse.just_pressed(MouseButton::Left) {
+            match toggle {
+                Toggle::Invincible => sandbox.invincible = !sandbox.invincible,
+                Toggle::NoCooldown => sandbox.no_cooldown = !sandbox.no_cooldown,
+                Toggle::Quit => {
+                    commands.insert_resource(Transition::to(GameState::Select));
+                    return;
+                }
+            }
+        }
+        let value = toggle.get_text(&sandbox);
+        if text.sections[0].value != value { text.sections[0].value = value; }
+    }
+}
+
+/// Feeds the DPS meters and shows them above the towers.
+fn update_dps(
+    mut commands: Commands,
+    sandbox: Option<ResMut<Sandbox>>,
+    mut events: EventReader<GameEvent>,
+    towers: Query<(Entity, &Tower, &GlobalTransform)>,
+    mut texts: Query<(Entity, &DpsText, &mut Text)>,
+    time: Res<Time>,
+    fonts: Res<Fonts>,
+) {
+    let Some(mut sandbox) = sandbox else { return; };
+    let t = time.elapsed_seconds();
+    for event in events.iter() {
+        let GameEvent::Hit { hit, tower: Some(tower), .. } = *event else { continue; };
+        sandbox.dps.entry(tower).or_default().hit(t, hit);
+    }
+
+    // Labels of sold towers
+    for (id, label, _) in texts.iter() {
+        if towers.get(label.0).is_err() {
+            commands.entity(id).despawn();
+            sandbox.dps.remove(&label.0);
+        }
+    }
+
+    for (tower, model, pos) in towers.iter() {
+        let dps = sandbox.dps.get_mut(&tower).map_or(0., |meter| meter.dps(t));
+        let value = format!("{:.0}/s", dps);
+        match texts.iter_mut().find(|(_, label, _)| label.0 == tower) {
+            Some((_, _, mut text)) => {
+                if text.sections[0].value != value { text.sections[0].value = value; }
+            }
+            None => {
+                let pos = pos.translation();
+                let size = body_size(model.model.get_tiles());
+                commands
+                    .spawn(ttf_anchor(
+                        pos.x + size.x / 2., pos.y + size.y + f32_tile_to_f32(0.5),
+                        util::z_pos::FLOATING_TEXT,
+                        &value,
+                        TextStyles::Body, &fonts, Palette::B,
+                        Anchor::Center,
+                    ))
+                    .insert(DpsText(tower))
+                    .insert(BattleUI);
+            }
+        }
+    }
+}
+
+fn clean(mut commands: Commands) {
+    commands.remove_resource::<Sandbox>();
+}
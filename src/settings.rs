@@ -0,0 +1,189 @@
+use bevy::math::{vec2, Vec3Swizzles};
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use bevy_pkv::PkvStore;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::{GameState, util};
+use crate::graphics::loading::Fonts;
+use crate::graphics::palette::Palette;
+use crate::graphics::text::{TextStyles, ttf_anchor};
+use crate::graphics::transition::Transition;
+use crate::util::is_in;
+use crate::util::size::{f32_tile_to_f32, tile_to_f32};
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system(setup.in_schedule(OnEnter(GameState::Settings)))
+            .add_system(update.in_set(OnUpdate(GameState::Settings)))
+            .add_system(clean.in_schedule(OnExit(GameState::Settings)))
+        ;
+    }
+}
+
+/// Player preferences, persisted in the [PkvStore].
+#[derive(Resource, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Show a health bar above damaged drones
+    pub health_bars: bool,
+    /// Show floating damage numbers on hits
+    pub damage_numbers: bool,
+    /// Start the next wave soon when no drone is left
+    pub auto_skip: bool,
+    /// Collect the packages as soon as they land, except the cursed ones
+    pub auto_collect: bool,
+    /// Write the gameplay events to a log file
+    pub event_log: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            health_bars: true,
+            damage_numbers: true,
+            auto_skip: true,
+            auto_collect: false,
+            event_log: false,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(pkv: &PkvStore) -> Self {
+        pkv.get::<Settings>("settings").unwrap_or_default()
+    }
+
+    fn save(&self, pkv: &mut PkvStore) {
+        let _ = pkv.set("settings", self);
+    }
+}
+
+#[derive(Component)]
+struct SettingsUI;
+
+#[derive(Component, Copy, Clone, PartialEq, EnumIter)]
+enum SettingsEntry {
+    HealthBars,
+    DamageNumbers,
+    AutoSkip,
+    AutoCollect,
+    EventLog,
+    Back,
+}
+
+impl SettingsEntry {
+    fn get_text(&self, settings: &Settings) -> String {
+        let on_off = |b: bool| if b { "ON" } else { "OFF" };
+        match self {
+            SettingsEntry::HealthBars => format!("Health bars: {}", on_off(settings.health_bars)),
+            SettingsEntry::DamageNumbers => format!("Damage numbers: {}", on_off(settings.damage_numbers)),
+            SettingsEntry::AutoSkip => format!("Auto next wave: {}", on_off(settings.auto_skip)),
+            SettingsEntry::AutoCollect => format!("Auto collect: {}", on_off(settings.auto_collect)),
+            SettingsEntry::EventLog => format!("Event log: {}", on_off(settings.event_log)),
+            SettingsEntry::Back => "Back".to_string(),
+        }
+    }
+
+    fn toggle(&self, settings: &mut Settings) {
+        match self {
+            SettingsEntry::HealthBars => settings.health_bars = !settings.health_bars,
+            SettingsEntry::DamageNumbers => settings.damage_numbers = !settings.damage_numbers,
+            SettingsEntry::AutoSkip => settings.auto_skip = !settings.auto_skip,
+            SettingsEntry::AutoCollect => settings.auto_collect = !settings.auto_collect,
+            SettingsEntry::EventLog => settings.event_log = !settings.event_log,
+            SettingsEntry::Back => {}
+        }
+    }
+
+    /// Y position of the entry in tiles
+    fn get_y(index: usize) -> f32 {
+        util::size::HEIGHT as f32 - 7. - 2. * index as f32
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    fonts: Res<Fonts>,
+    settings: Res<Settings>,
+) {
+    commands
+        .spawn(ttf_anchor(
+            tile_to_f32(util::size::WIDTH / 2),
+            tile_to_f32(util::size::HEIGHT - 4),
+            util::z_pos::GUI_FG,
+            "Settings",
+            TextStyles::Heading, &fonts, Palette::B,
+            Anchor::Center,
+        ))
+        .insert(SettingsUI);
+
+    for (i, entry) in SettingsEntry::iter().enumerate() {
+        commands
+            .spawn(ttf_anchor(
+                tile_to_f32(util::size::WIDTH / 2),
+                f32_tile_to_f32(SettingsEntry::get_y(i)),
+                util::z_pos::GUI_FG,
+                &entry.get_text(&settings),
+                TextStyles::Heading, &fonts, Palette::D,
+                Anchor::Center,
+            ))
+            .insert(entry)
+            .insert(SettingsUI);
+    }
+}
+
+fn update(
+    mut commands: Commands,
+    mut entries: Query<(&SettingsEntry, &Transform, &mut Text)>,
+    mut settings: ResMut<Settings>,
+    mut pkv: ResMut<PkvStore>,
+    windows: Query<&Window>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    transition: Option<Res<Transition>>,
+) {
+    if transition.is_some() { return; }
+
+    if keys.just_pressed(KeyCode::Escape) {
+        commands.insert_resource(Transition::to(GameState::Select));
+        return;
+    }
+
+    let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
+    let clicked = mouse.just_pressed(MouseButton::Left);
+    let size = vec2(tile_to_f32(20), f32_tile_to_f32(1.5));
+
+    for (entry, pos, mut text) in entries.iter_mut() {
+        // Entries have Anchor::Center
+        let bottom_left = pos.translation.xy() - size / 2.;
+        let hovered = is_in(cursor_pos, bottom_left, size);
+
+        if clicked && hovered {
+            match entry {
+                SettingsEntry::Back => {
+                    commands.insert_resource(Transition::to(GameState::Select));
+                }
+                _ => {
+                    entry.toggle(&mut settings);
+                    settings.save(&mut pkv);
+                }
+            }
+        }
+
+        text.sections[0].value = entry.get_text(&settings);
+        text.sections[0].style.color = if hovered { Palette::B.into() } else { Palette::D.into() };
+    }
+}
+
+fn clean(
+    mut commands: Commands,
+    query: Query<Entity, With<SettingsUI>>,
+) {
+    for id in &query { commands.entity(id).despawn_recursive(); }
+}
@@ -14,6 +14,9 @@ use crate::drones::Enemy;
 use crate::graphics::{circle, MainBundle};
 use crate::graphics::palette::Palette;
 use crate::graphics::sprites::TILE;
+use crate::logic::event_log::GameEvent;
+use crate::logic::rules;
+use crate::sandbox::Sandbox;
 use crate::tower::Tower;
 use crate::util::size::battle::BOMB_RANGE;
 use crate::util::tweening::{BOMB_EXPLODED, SHOT_DESPAWN};
@@ -24,6 +27,8 @@ pub struct Shot {
     pub class: Shots,
     pub damage: f32,
     pub speed: f32,
+    /// Tower which fired the shot
+    pub source: Entity,
 }
 
 #[derive(Copy, Clone, EnumIter, Debug, PartialEq, Eq)]
@@ -33,17 +38,18 @@ pub enum Shots {
 }
 
 impl Shots {
-    fn get_shot(&self, tower: &Tower) -> Shot {
+    fn get_shot(&self, tower: &Tower, source: Entity) -> Shot {
         Shot {
             class: *self,
             damage: tower.damage(),
             speed: tower.shot_speed(),
+            source,
         }
     }
 
-    pub fn instantiate(&self, tower: &Tower) -> (Shot, HitBox) {
+    pub fn instantiate(&self, tower: &Tower, source: Entity) -> (Shot, HitBox) {
         let hitbox: Vec2 = self.get_hitbox();
-        let shot = self.get_shot(tower);
+        let shot = self.get_shot(tower, source);
         let solid_body = HitBox {
             body_type: BodyType::ShipShot,
             width: hitbox.x,
@@ -116,6 +122,8 @@ pub struct Bomb {
     y: f32,
     radius: f32,
     damages: f32,
+    /// Tower which fired the bomb
+    source: Option<Entity>,
 }
 
 impl Bomb {
@@ -125,6 +133,7 @@ impl Bomb {
             y: pos.y,
             radius,
             damages,
+            source: None,
         }
     }
 
@@ -138,6 +147,7 @@ impl Bomb {
             y: tr.y,
             radius: shot.get_bomb_range(),
             damages: shot.damage,
+            source: Some(shot.source),
         }
     }
 }
@@ -151,13 +161,17 @@ pub fn spawn_bomb(bomb: Bomb, commands: &mut Commands) {
 }
 
 pub fn bomb_exploding(
+    mut events: EventWriter<GameEvent>,
     bombs: Query<&Bomb, Added<Bomb>>,
-    mut enemies: Query<(&mut Enemy, &Transform)>,
+    mut enemies: Query<(Entity, &mut Enemy, &Transform)>,
+    sandbox: Option<Res<Sandbox>>,
 ) {
+    let invincible = sandbox.map_or(false, |s| s.invincible);
     for bomb in bombs.iter() {
-        for (mut enemy, t_enemy) in enemies.iter_mut() {
-            if t_enemy.translation.xy().distance_squared(bomb.position()) <= bomb.radius * bomb.radius {
-                enemy.stats.hp = (enemy.stats.hp - bomb.damages).max(0.);
+        for (e_enemy, mut enemy, t_enemy) in enemies.iter_mut() {
+            if rules::in_blast(t_enemy.translation.xy(), bomb.position(), bomb.radius) {
+                let dealt = enemy.damage(bomb.damages, invincible);
+                events.send(GameEvent::Hit { id: e_enemy, drone: enemy.class, damage: dealt, hit: bomb.damages, tower: bomb.source });
             }
         }
     }
@@ -1,27 +1,33 @@
 use std::time::Duration;
 
-use bevy::math::{vec3, Vec3Swizzles};
+use bevy::math::vec3;
 use bevy::prelude::*;
 use bevy_tweening::{Animator, Tween};
 use bevy_tweening::EaseMethod::Linear;
 use bevy_tweening::lens::TransformPositionLens;
+use serde::Serialize;
 use strum_macros::EnumIter;
 
 use crate::battle::{BattleUI, CursorState, Money};
 use crate::collision::body_size;
 use crate::drones::Enemy;
 use crate::graphics::{MainBundle, sprite_from_tile, sprites};
-use crate::graphics::grid::{Grid, GridElement};
+use crate::graphics::grid::{CurrentPath, Grid, GridElement};
 use crate::graphics::gui::{HoveredPos, HoverPopup};
 use crate::graphics::loading::Textures;
+use crate::graphics::package::PackageEffects;
 use crate::graphics::sprites::TILE;
+use crate::logic::battle_log::MoneyFlow;
+use crate::logic::event_log::GameEvent;
+use crate::logic::rules;
+use crate::logic::rules::Coverage;
 use crate::logic::tower_stats;
 use crate::logic::tower_stats::{MAX_DAMAGE, MAX_RELOAD, MIN_DAMAGE, MIN_RELOAD};
 use crate::music::{PlaySfxEvent, SFX};
+use crate::sandbox::Sandbox;
 use crate::shot::Shots;
 use crate::util;
 use crate::util::{vec2_with_battle_z, with_z, z_pos};
-use crate::util::misc::SLOW_DOWN_DELAY;
 use crate::util::size::{f32_tile_to_f32, tile_to_f32};
 use crate::util::tweening::SHOT_DESPAWN;
 
@@ -56,13 +62,7 @@ impl Tower {
         }
     }
 
-    pub fn get_name(&self) -> &str {
-        match self.model {
-            Towers::Lightning => "Lightning Tower",
-            Towers::PaintBomb => "Paint Bomb",
-            Towers::Scrambler => "Scrambler",
-        }
-    }
+    pub fn get_name(&self) -> &str { self.model.get_name() }
 
     pub fn get_description(&self) -> String {
         match self.upgrade_cost() {
@@ -92,7 +92,7 @@ impl Tower {
     }
 }
 
-#[derive(Debug, Copy, Clone, EnumIter, PartialEq)]
+#[derive(Debug, Copy, Clone, EnumIter, PartialEq, Serialize)]
 pub enum Towers {
     Lightning,
     PaintBomb,
@@ -118,6 +118,14 @@ impl Towers {
         Tower { model: *self, rank: 1, x, y }
     }
 
+    pub const fn get_name(&self) -> &'static str {
+        match self {
+            Towers::Lightning => "Lightning Tower",
+            Towers::PaintBomb => "Paint Bomb",
+            Towers::Scrambler => "Scrambler",
+        }
+    }
+
     pub const fn get_shot(&self) -> Option<Shots> {
         match self {
             Towers::Lightning => Some(Shots::Electricity),
@@ -162,7 +170,7 @@ pub fn place_tower(
     commands: &mut Commands,
     tower: Towers, atlas: &Handle<TextureAtlas>,
     time: &Time,
-) {
+) -> Entity {
     let tower = tower.instantiate(x, y);
     let size = body_size(tower.model.get_tiles());
     let tower_pos = util::grid_to_tower_pos(x, y, tower.model);
@@ -183,12 +191,13 @@ pub fn place_tower(
         ))
         .insert(BattleUI)
         .insert(GridElement)
-    ;
+        .id()
 }
 
 pub fn sell_tower(
     mut commands: Commands,
     mut sfx: EventWriter<PlaySfxEvent>,
+    mut events: EventWriter<GameEvent>,
     towers: Query<(&Tower, Entity)>,
     mouse: Res<Input<MouseButton>>,
     cursor_state: Option<ResMut<CursorState>>,
@@ -217,13 +226,16 @@ pub fn sell_tower(
             commands.entity(id).despawn_recursive();
             cursor_state.set_if_neq(CursorState::Select);
             money.0 += t.sell_price();
+            events.send(GameEvent::TowerSold { tower: id });
+            events.send(GameEvent::Money { amount: t.sell_price() as i32, flow: MoneyFlow::Selling });
         }
     }
 }
 
 pub fn upgrade_tower(
-    mut towers: Query<(&mut Tower, &mut HoverPopup)>,
+    mut towers: Query<(Entity, &mut Tower, &mut HoverPopup)>,
     mut sfx: EventWriter<PlaySfxEvent>,
+    mut events: EventWriter<GameEvent>,
     mouse: Res<Input<MouseButton>>,
     cursor_state: Option<ResMut<CursorState>>,
     hovered: Option<Res<HoveredPos>>,
@@ -238,7 +250,7 @@ pub fn upgrade_tower(
     let Some(mut money) = money else { return; };
     let pos = &(hovered.0.0, hovered.0.1);
 
-    for (mut t, mut hp) in towers.iter_mut() {
+    for (e_tower, mut t, mut hp) in towers.iter_mut() {
         if t.x == pos.0 && t.y == pos.1 {
             match t.upgrade_cost() {
                 Some(cost) if cost <= money.0 => {
@@ -246,6 +258,8 @@ pub fn upgrade_tower(
                     sfx.send(PlaySfxEvent(SFX::UpgradeTower));
                     money.0 -= cost;
                     t.rank += 1;
+                    events.send(GameEvent::TowerUpgraded { tower: e_tower, rank: t.rank, cost });
+                    events.send(GameEvent::Money { amount: -(cost as i32), flow: MoneyFlow::Upgrades });
                     hp.description = t.get_description();
                     hp.attr1 = t.get_attr1();
                     hp.attr2 = t.get_attr2();
@@ -265,51 +279,66 @@ pub fn upgrade_tower(
     }
 }
 
+/// Computes the [Coverage] of the towers when they are placed or upgraded.
+pub fn update_coverage(
+    mut commands: Commands,
+    towers: Query<(Entity, &Tower), Changed<Tower>>,
+    path: Res<CurrentPath>,
+) {
+    for (e_tower, tower) in towers.iter() {
+        commands.entity(e_tower).insert(Coverage::new(tower, &path.0));
+    }
+}
+
 pub fn tower_fire(
-    towers: Query<(Entity, &Transform, &Tower), Without<JustFired>>,
+    towers: Query<(Entity, &Transform, &Tower, &Coverage), Without<JustFired>>,
     enemies: Query<(Entity, &Transform, &Enemy)>,
     mut commands: Commands,
+    mut events: EventWriter<GameEvent>,
+    effects: Res<PackageEffects>,
+    sandbox: Option<Res<Sandbox>>,
     time: Res<Time>,
     textures: Res<Textures>,
 ) {
-    for (e_tower, &t_tower, tower) in towers.iter() {
+    let coffee = effects.coffee(&time);
+    let no_cooldown = sandbox.map_or(false, |s| s.no_cooldown);
+    for (e_tower, &t_tower, tower, coverage) in towers.iter() {
         let mut fired = false;
+        let in_range = enemies.iter()
+            .filter(|(_, _, enemy)| coverage.covers(enemy.class, enemy.route, enemy.advance));
         match tower.model {
             Towers::Lightning | Towers::PaintBomb => {
-                let chosen_enemy = enemies.iter()
-                    .filter(|(_, t_enemy, enemy)| util::tower_to_enemy_distance(tower, t_enemy.translation.xy(), enemy.class) <= tower.range())
-                    .max_by_key(|(_, _, enemy)| (enemy.advance * 4096.) as usize);
+                let chosen_enemy = rules::pick_target(in_range.map(|e| (e, e.2.advance)));
 
                 if let Some((_, t_enemy, e)) = chosen_enemy {
                     fired = true;
-                    shoot(&mut commands, &textures, t_tower, tower, t_enemy.translation, body_size(e.class.get_tiles()));
+                    events.send(GameEvent::ShotFired { tower: e_tower, model: tower.model });
+                    shoot(&mut commands, &textures, e_tower, t_tower, tower, t_enemy.translation, body_size(e.class.get_tiles()));
                 }
             }
             Towers::Scrambler => {
-                enemies.iter()
-                    .filter(|(_, t_enemy, enemy)| util::tower_to_enemy_distance(tower, t_enemy.translation.xy(), enemy.class) <= tower.range())
-                    .for_each(|(e, _, _)| {
-                        if let Some(mut entity_commands) = commands.get_entity(e) {
-                            fired = true;
-                            entity_commands.insert(Slow {
-                                factor: tower.slow_factor(),
-                                t_final: time.elapsed() + Duration::from_secs_f32(SLOW_DOWN_DELAY),
-                            });
-                        }
-                    })
-                ;
+                let (factor, duration) = rules::scramble(tower);
+                in_range.for_each(|(e, _, _)| {
+                    if let Some(mut entity_commands) = commands.get_entity(e) {
+                        fired = true;
+                        entity_commands.insert(Slow {
+                            factor,
+                            t_final: time.elapsed() + Duration::from_secs_f32(duration),
+                        });
+                    }
+                });
             }
         }
 
         if fired {
             if let Some(mut entity_commands) = commands.get_entity(e_tower) {
-                entity_commands.insert(JustFired::new(&time, tower.reload_delay()));
+                entity_commands.insert(JustFired::new(&time, rules::reload_delay(tower, coffee, no_cooldown)));
             }
         }
     }
 }
 
-fn shoot(commands: &mut Commands, textures: &Res<Textures>, t_tower: Transform, tower: &Tower, enemy_position: Vec3, enemy_size: Vec2) {
+fn shoot(commands: &mut Commands, textures: &Res<Textures>, e_tower: Entity, t_tower: Transform, tower: &Tower, enemy_position: Vec3, enemy_size: Vec2) {
     let enemy_position = Vec3::new(
         enemy_position.x + enemy_size.x / 2.,
         enemy_position.y + enemy_size.y / 2.,
@@ -323,7 +352,7 @@ fn shoot(commands: &mut Commands, textures: &Res<Textures>, t_tower: Transform,
         z_pos::SHOT,
     );
     let shot_kind = tower.model.get_shot().expect("The tower can't shoot!");
-    let shot = shot_kind.instantiate(tower);
+    let shot = shot_kind.instantiate(tower, e_tower);
     commands
         .spawn(shot.clone())
         .insert(MainBundle::from_translation(shot_translation))
@@ -361,7 +390,7 @@ pub fn update_just_fired(
 #[derive(Component)]
 pub struct Slow {
     pub factor: f32,
-    t_final: Duration,
+    pub(crate) t_final: Duration,
 }
 
 pub fn remove_slow_down(
@@ -1,9 +1,11 @@
+use std::path::PathBuf;
+
 use bevy::math::{vec2, vec3};
 use bevy::prelude::*;
+use directories::ProjectDirs;
 
 use crate::collision::body_size;
-use crate::drones::Drones;
-use crate::tower::{Tower, Towers};
+use crate::tower::Towers;
 use crate::util::size::{f32_tile_to_f32, tile_to_f32};
 
 pub mod size {
@@ -34,6 +36,7 @@ pub mod size {
 
         pub const BOMB_RANGE: f32 = 3.5 * TILE_SIZE as f32;
         pub const OMEGA_RANGE: f32 = 6. * TILE_SIZE as f32;
+        pub const DECOY_RANGE: f32 = 4. * TILE_SIZE as f32;
     }
 }
 
@@ -52,8 +55,11 @@ pub mod z_pos {
     pub const BOMB: f32 = 7.1;
     pub const EXPLOSION: f32 = 8.;
     pub const TRANSPARENT_TOWER: f32 = 9.;
+    pub const DEBUG: f32 = 9.5;
+    pub const FLOATING_TEXT: f32 = 10.;
 
     pub const ATTACHED_PACKAGE_OFFSET: f32 = -1. / 4096.;
+    pub const HEALTH_BAR_OFFSET: f32 = 1. / 4096.;
 
     // GUI
     pub const GUI_BG: f32 = 11.;
@@ -76,6 +82,7 @@ pub mod tweening {
     pub const SHOT_DESPAWN: u64 = 2;
     pub const BOMB_EXPLODED: u64 = 3;
     pub const DRONE_DESPAWN: u64 = 4;
+    pub const FLOATING_TEXT_DESPAWN: u64 = 5;
 
     // durations of tweenings
     pub const DELAY: u64 = 200;
@@ -83,6 +90,7 @@ pub mod tweening {
     pub const DRONE_DEATH_ALPHA: u64 = 800;
     pub const DRONE_DEATH_POS: u64 = 1200;
     pub const PACKAGE_DROP: u64 = 800;
+    pub const FLOATING_TEXT: u64 = 900;
 }
 
 pub mod package {
@@ -90,6 +98,25 @@ pub mod package {
     pub const MONEY_SMALL: u16 = 20;
     pub const MONEY_BIG: u16 = 60;
     pub const MONEY_CURSE: u16 = 30;
+
+    /// Durations of package effects in seconds
+    pub const COFFEE_DURATION: f32 = 10.;
+    pub const FREEZE_DURATION: f32 = 3.;
+    pub const DECOY_DURATION: f32 = 8.;
+
+    /// Seconds before a dropped package expires
+    pub const PACKAGE_LIFETIME: f32 = 12.;
+    /// Dropped packages blink during their last seconds
+    pub const PACKAGE_BLINK: f32 = 3.;
+    /// Blinks per second, counting both the visible and hidden phases
+    pub const BLINK_FREQUENCY: f32 = 8.;
+
+    pub const INVENTORY_SLOTS: usize = 3;
+
+    /// Reload delay multiplier of the towers under [COFFEE_DURATION]
+    pub const COFFEE_RELOAD: f32 = 0.5;
+    /// Speed multiplier of the drones close to a decoy
+    pub const DECOY_SLOW: f32 = 0.4;
 }
 
 pub mod misc {
@@ -102,6 +129,13 @@ pub const fn with_z(Vec3 { x, y, .. }: Vec3, z: f32) -> Vec3 {
     Vec3 { x, y, z }
 }
 
+/// Path of [file] in the data directory, next to the [bevy_pkv::PkvStore] data.
+pub fn data_path(file: &str) -> Option<PathBuf> {
+    let dirs = ProjectDirs::from("", "yopox", "Sabotage, Inc.")?;
+    std::fs::create_dir_all(dirs.data_dir()).ok()?;
+    Some(dirs.data_dir().join(file))
+}
+
 pub fn battle_z_from_y(y: f32) -> f32 {
     use crate::util::size::HEIGHT;
     use crate::util::z_pos::{BATTLE_MAX, BATTLE_MIN};
@@ -140,13 +174,16 @@ pub fn is_in(p: Vec2, o: Vec2, size: Vec2) -> bool {
     p.x >= o.x && p.x <= o.x + size.x && p.y >= o.y && p.y <= o.y + size.y
 }
 
-pub fn tower_center(x: usize, y: usize) -> Vec2 {
-    vec2(tile_to_f32(2 * x + 1), tile_to_f32(2 * y + 1 + size::GUI_HEIGHT))
+/// Position on the screen of the point [p] of a path, in cells.
+pub fn path_to_world(p: Vec2) -> Vec2 {
+    vec2(f32_tile_to_f32(2. * p.x + 1.), f32_tile_to_f32(2. * p.y + 1. + size::GUI_HEIGHT as f32))
+}
+
+/// Point of a path, in cells, shown at [p] on the screen.
+pub fn world_to_path(p: Vec2) -> Vec2 {
+    vec2((p.x / tile_to_f32(1) - 1.) / 2., (p.y / tile_to_f32(1) - 1. - size::GUI_HEIGHT as f32) / 2.)
 }
 
-pub fn tower_to_enemy_distance(tower: &Tower, enemy_pos: Vec2, enemy: Drones) -> f32 {
-    let enemy_size = body_size(enemy.get_tiles());
-    let enemy_center = enemy_pos + enemy_size / 2.;
-    let tower_center = tower_center(tower.x, tower.y);
-    tower_center.distance(enemy_center)
+pub fn tower_center(x: usize, y: usize) -> Vec2 {
+    vec2(tile_to_f32(2 * x + 1), tile_to_f32(2 * y + 1 + size::GUI_HEIGHT))
 }
\ No newline at end of file
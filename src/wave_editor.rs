@@ -0,0 +1,422 @@
+use bevy::math::vec2;
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::{GameState, util};
+use crate::collision::body_size;
+use crate::drones::Drones;
+use crate::editor::{CustomLevel, Editor};
+use crate::graphics::{MainBundle, sprite_from_tile};
+use crate::graphics::loading::{Fonts, Textures};
+use crate::graphics::palette::Palette;
+use crate::graphics::text::{TextStyles, ttf_anchor};
+use crate::graphics::transition::Transition;
+use crate::level_select::{CurrentLevel, GameMode};
+use crate::logic::waves::Wave;
+use crate::util::is_in;
+use crate::util::size::{f32_tile_to_f32, tile_to_f32};
+
+pub struct WaveEditorPlugin;
+
+impl Plugin for WaveEditorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<WaveEditor>()
+            .add_system(setup.in_schedule(OnEnter(GameState::WaveEditor)))
+            .add_systems((update_buttons, update_timeline, draw_timeline.after(update_timeline))
+                .in_set(OnUpdate(GameState::WaveEditor)))
+            .add_system(clean.in_schedule(OnExit(GameState::WaveEditor)))
+        ;
+    }
+}
+
+#[derive(Resource, Default)]
+struct WaveEditor {
+    /// Index of the edited wave
+    selected: usize,
+    /// Index of the drone being dragged on the timeline
+    dragged: Option<usize>,
+    /// Seconds shown on the timeline, updated when no drone is dragged
+    span: f32,
+    /// The timeline must be redrawn
+    dirty: bool,
+    /// Result of the last action
+    status: String,
+    /// Description of the hovered drone, shown instead of the status
+    hint: String,
+}
+
+/// Left of the timeline and its width, in tiles
+const TIMELINE_X: f32 = 2.;
+const TIMELINE_WIDTH: f32 = 36.;
+/// Y of the timeline rows, in tiles
+const TIMELINE_ROWS: [f32; 3] = [17., 14.5, 12.];
+/// Y of the drone palette, in tiles
+const PALETTE_Y: f32 = 7.5;
+/// Scale of the drone icons
+const ICON_SCALE: f32 = 0.5;
+/// Minimum span of the timeline in seconds
+const MIN_SPAN: f32 = 20.;
+
+impl WaveEditor {
+    fn time_to_x(&self, t: f32) -> f32 {
+        f32_tile_to_f32(TIMELINE_X + TIMELINE_WIDTH * t / self.span)
+    }
+
+    /// Time at [x], rounded to a tenth of second.
+    fn x_to_time(&self, x: f32) -> f32 {
+        let t = (x / f32_tile_to_f32(1.) - TIMELINE_X) / TIMELINE_WIDTH * self.span;
+        (t.max(0.) * 10.).round() / 10.
+    }
+
+    fn fit(&mut self, wave: &Wave) {
+        self.span = (wave.duration() * 1.25).max(MIN_SPAN);
+    }
+}
+
+#[derive(Component)]
+struct WaveEditorUI;
+
+/// Drone [i] of the selected wave
+#[derive(Component)]
+struct TimelineIcon(usize);
+
+/// Click to add a drone to the wave
+#[derive(Component)]
+struct PaletteIcon(Drones);
+
+/// Entities redrawn with the timeline
+#[derive(Component)]
+struct Timeline;
+
+#[derive(Component)]
+struct StatusText;
+
+#[derive(Component, Copy, Clone, PartialEq, EnumIter)]
+enum WaveButton {
+    EndDelay,
+    New,
+    Duplicate,
+    Delete,
+    Previous,
+    Next,
+    Test,
+    Back,
+}
+
+impl WaveButton {
+    fn get_text(&self, wave: &Wave) -> String {
+        match self {
+            WaveButton::EndDelay => format!("End delay: {}s", wave.end_delay),
+            WaveButton::New => "New".to_string(),
+            WaveButton::Duplicate => "Duplicate".to_string(),
+            WaveButton::Delete => "Delete".to_string(),
+            WaveButton::Previous => "< Prev".to_string(),
+            WaveButton::Next => "Next >".to_string(),
+            WaveButton::Test => "Test from this wave".to_string(),
+            WaveButton::Back => "Back".to_string(),
+        }
+    }
+
+    /// Position in tiles, buttons have Anchor::CenterLeft
+    fn get_pos(&self) -> Vec2 {
+        match self {
+            WaveButton::EndDelay => vec2(2., 3.25),
+            WaveButton::New => vec2(17., 3.25),
+            WaveButton::Duplicate => vec2(22., 3.25),
+            WaveButton::Delete => vec2(30., 3.25),
+            WaveButton::Previous => vec2(2., 1.25),
+            WaveButton::Next => vec2(8., 1.25),
+            WaveButton::Test => vec2(15., 1.25),
+            WaveButton::Back => vec2(util::size::WIDTH as f32 - 6., 1.25),
+        }
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut wave_editor: ResMut<WaveEditor>,
+    editor: Res<Editor>,
+    fonts: Res<Fonts>,
+    textures: Res<Textures>,
+) {
+    let waves = &editor.level.waves;
+    wave_editor.selected = wave_editor.selected.min(waves.len().saturating_sub(1));
+    wave_editor.dragged = None;
+    wave_editor.dirty = true;
+    wave_editor.status = String::new();
+    wave_editor.hint = String::new();
+    let Some(wave) = waves.get(wave_editor.selected) else { return; };
+
+    for button in WaveButton::iter() {
+        let pos = button.get_pos();
+        commands
+            .spawn(ttf_anchor(
+                f32_tile_to_f32(pos.x), f32_tile_to_f32(pos.y),
+                util::z_pos::GUI_FG,
+                &button.get_text(wave),
+                TextStyles::Heading, &fonts, Palette::D,
+                Anchor::CenterLeft,
+            ))
+            .insert(button)
+            .insert(WaveEditorUI);
+    }
+
+    commands
+        .spawn(ttf_anchor(
+            f32_tile_to_f32(2.), f32_tile_to_f32(5.),
+            util::z_pos::GUI_FG,
+            "",
+            TextStyles::Body, &fonts, Palette::B,
+            Anchor::CenterLeft,
+        ))
+        .insert(StatusText)
+        .insert(WaveEditorUI);
+
+    // Drone palette
+    for (i, drone) in Drones::iter().enumerate() {
+        let mut bundle = MainBundle::from_xyz(
+            f32_tile_to_f32(TIMELINE_X + 3.5 * i as f32), f32_tile_to_f32(PALETTE_Y),
+            util::z_pos::GUI_FG,
+        );
+        bundle.transform.scale = Vec3::splat(ICON_SCALE);
+        commands
+            .spawn(bundle)
+            .with_children(|builder| sprite_from_tile(builder, drone.get_tiles(), &textures.tileset, 0.))
+            .insert(PaletteIcon(drone))
+            .insert(WaveEditorUI);
+    }
+}
+
+fn update_buttons(
+    mut commands: Commands,
+    mut wave_editor: ResMut<WaveEditor>,
+    mut editor: ResMut<Editor>,
+    mut buttons: Query<(&WaveButton, &Transform, &mut Text), Without<StatusText>>,
+    mut status: Query<&mut Text, With<StatusText>>,
+    windows: Query<&Window>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    transition: Option<Res<Transition>>,
+) {
+    if transition.is_some() { return; }
+
+    if keys.just_pressed(KeyCode::Escape) {
+        commands.insert_resource(Transition::to(GameState::Editor));
+        return;
+    }
+
+    let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
+    let left = mouse.just_pressed(MouseButton::Left);
+    let right = mouse.just_pressed(MouseButton::Right);
+    let waves = &mut editor.level.waves;
+    let mut selected = wave_editor.selected;
+    let mut test = false;
+    if waves.is_empty() { return; }
+
+    for (button, pos, mut text) in buttons.iter_mut() {
+        let value = button.get_text(&waves[selected]);
+        let size = vec2(f32_tile_to_f32(0.7 * value.chars().count() as f32), f32_tile_to_f32(1.25));
+        let bottom_left = vec2(pos.translation.x, pos.translation.y - size.y / 2.);
+        let hovered = is_in(cursor_pos, bottom_left, size);
+        text.sections[0].style.color = if hovered { Palette::B.into() } else { Palette::D.into() };
+        if text.sections[0].value != value { text.sections[0].value = value; }
+        if !hovered || !(left || right) { continue; }
+
+        match button {
+            WaveButton::EndDelay => {
+                let delay = &mut waves[selected].end_delay;
+                *delay = if left { *delay + 5. } else { (*delay - 5.).max(5.) };
+            }
+            WaveButton::New if left => {
+                selected += 1;
+                waves.insert(selected, [(0., Drones::Simple1)].into());
+            }
+            WaveButton::Duplicate if left => {
+                let wave = waves[selected].clone();
+                selected += 1;
+                waves.insert(selected, wave);
+            }
+            WaveButton::Delete if left && waves.len() > 1 => {
+                waves.remove(selected);
+                selected = selected.min(waves.len() - 1);
+            }
+            WaveButton::Previous if left => selected = selected.saturating_sub(1),
+            WaveButton::Next if left => selected = (selected + 1).min(waves.len() - 1),
+            WaveButton::Test if left => test = true,
+            WaveButton::Back if left => {
+                commands.insert_resource(Transition::to(GameState::Editor));
+                return;
+            }
+            _ => {}
+        }
+        wave_editor.dirty = true;
+    }
+
+    // Arrow keys select the wave
+    if keys.just_pressed(KeyCode::Left) { selected = selected.saturating_sub(1); }
+    if keys.just_pressed(KeyCode::Right) { selected = (selected + 1).min(waves.len() - 1); }
+    if selected != wave_editor.selected {
+        wave_editor.selected = selected;
+        wave_editor.dragged = None;
+        wave_editor.status = String::new();
+        wave_editor.dirty = true;
+    }
+
+    if test {
+        match editor.level.validate() {
+            Ok(_) => {
+                commands.insert_resource(CustomLevel { level: editor.level.clone(), first_wave: selected });
+                // The level number is not used in custom levels
+                commands.insert_resource(CurrentLevel(0));
+                commands.insert_resource(GameMode::Custom);
+                commands.insert_resource(Transition::to(GameState::Battle));
+                return;
+            }
+            Err(e) => wave_editor.status = e.get_message(),
+        }
+    }
+
+    let value = match wave_editor.hint.is_empty() {
+        true => &wave_editor.status,
+        false => &wave_editor.hint,
+    };
+    let mut status = status.single_mut();
+    if status.sections[0].value != *value { status.sections[0].value = value.clone(); }
+}
+
+/// Drag drones to change their departure time, right click removes them.
+/// Clicking a drone of the palette adds it after the last one.
+fn update_timeline(
+    mut wave_editor: ResMut<WaveEditor>,
+    mut editor: ResMut<Editor>,
+    icons: Query<(&TimelineIcon, &GlobalTransform)>,
+    palette: Query<(&PaletteIcon, &GlobalTransform)>,
+    windows: Query<&Window>,
+    mouse: Res<Input<MouseButton>>,
+    transition: Option<Res<Transition>>,
+) {
+    if transition.is_some() { return; }
+    let Some(cursor_pos) = util::cursor_pos(windows) else { return; };
+    let selected = wave_editor.selected;
+    let Some(wave) = editor.level.waves.get_mut(selected) else { return; };
+    let hovered = |drone: Drones, pos: &GlobalTransform| {
+        is_in(cursor_pos, pos.translation().truncate(), body_size(drone.get_tiles()) * ICON_SCALE)
+    };
+
+    if let Some(i) = wave_editor.dragged {
+        if !mouse.pressed(MouseButton::Left) {
+            wave_editor.dragged = None;
+            wave_editor.fit(wave);
+            wave_editor.dirty = true;
+            return;
+        }
+        let t = wave_editor.x_to_time(cursor_pos.x);
+        if wave.timed_departures.get(i).map(|(t0, _)| *t0) != Some(t) {
+            wave_editor.dragged = Some(wave.set_time(i, t));
+            wave_editor.dirty = true;
+        }
+        return;
+    }
+
+    let clicked = mouse.just_pressed(MouseButton::Left);
+    let right = mouse.just_pressed(MouseButton::Right);
+    wave_editor.hint = String::new();
+
+    for (icon, pos) in icons.iter() {
+        let Some((_, drone)) = wave.timed_departures.get(icon.0) else { continue; };
+        if !hovered(*drone, pos) { continue; }
+        wave_editor.hint = format!("{} at {:.1}s, drag to move, right click to remove", drone.get_name(), wave.timed_departures[icon.0].0);
+        if clicked {
+            wave_editor.dragged = Some(icon.0);
+        } else if right && wave.timed_departures.len() > 1 {
+            wave.remove(icon.0);
+            wave_editor.dirty = true;
+        }
+        return;
+    }
+
+    for (icon, pos) in palette.iter() {
+        if !hovered(icon.0, pos) { continue; }
+        wave_editor.hint = format!("Add a {} drone", drone_label(icon.0));
+        if clicked {
+            wave.add(wave.duration() + 2., icon.0);
+            wave_editor.fit(wave);
+            wave_editor.dirty = true;
+        }
+        return;
+    }
+}
+
+/// Name and traits of a drone, as shown in the palette.
+fn drone_label(drone: Drones) -> String {
+    format!("{} ({})", drone.get_name(), drone.get_traits())
+}
+
+/// Redraws the timeline and the stats of the selected wave.
+fn draw_timeline(
+    mut commands: Commands,
+    mut wave_editor: ResMut<WaveEditor>,
+    editor: Res<Editor>,
+    timeline: Query<Entity, With<Timeline>>,
+    fonts: Res<Fonts>,
+    textures: Res<Textures>,
+) {
+    if !wave_editor.dirty { return; }
+    wave_editor.dirty = false;
+    for id in &timeline { commands.entity(id).despawn_recursive(); }
+
+    let waves = &editor.level.waves;
+    let Some(wave) = waves.get(wave_editor.selected) else { return; };
+    if wave_editor.dragged.is_none() { wave_editor.fit(wave); }
+
+    let mut spawn_text = |x: f32, y: f32, text: &str, style: TextStyles, color: Palette, anchor: Anchor| {
+        commands
+            .spawn(ttf_anchor(x, y, util::z_pos::GUI_FG, text, style, &fonts, color, anchor))
+            .insert(Timeline)
+            .insert(WaveEditorUI);
+    };
+
+    spawn_text(
+        tile_to_f32(util::size::WIDTH / 2), f32_tile_to_f32(util::size::HEIGHT as f32 - 1.5),
+        &format!("Wave {}/{}", wave_editor.selected + 1, waves.len()),
+        TextStyles::Heading, Palette::B, Anchor::Center,
+    );
+    spawn_text(
+        tile_to_f32(util::size::WIDTH / 2), f32_tile_to_f32(util::size::HEIGHT as f32 - 3.),
+        &format!("{} drones - {} hp - {:.2} drones/s", wave.timed_departures.len(), wave.total_hp(), wave.density()),
+        TextStyles::Body, Palette::D, Anchor::Center,
+    );
+
+    // Time scale
+    let step = if wave_editor.span > 120. { 30. } else { 10. };
+    let mut t = 0.;
+    while t <= wave_editor.span {
+        spawn_text(
+            wave_editor.time_to_x(t), f32_tile_to_f32(10.5),
+            &format!("{}s", t), TextStyles::Body, Palette::D, Anchor::TopCenter,
+        );
+        t += step;
+    }
+
+    for (i, (t, drone)) in wave.timed_departures.iter().enumerate() {
+        let row = TIMELINE_ROWS[i % TIMELINE_ROWS.len()];
+        let mut bundle = MainBundle::from_xyz(wave_editor.time_to_x(*t), f32_tile_to_f32(row), util::z_pos::GUI_FG);
+        bundle.transform.scale = Vec3::splat(ICON_SCALE);
+        commands
+            .spawn(bundle)
+            .with_children(|builder| sprite_from_tile(builder, drone.get_tiles(), &textures.tileset, 0.))
+            .insert(TimelineIcon(i))
+            .insert(Timeline)
+            .insert(WaveEditorUI);
+    }
+}
+
+fn clean(
+    mut commands: Commands,
+    query: Query<Entity, With<WaveEditorUI>>,
+) {
+    for id in &query { commands.entity(id).despawn_recursive(); }
+}